@@ -0,0 +1,806 @@
+use crate::lag::{partition_lag, ConsumerLag};
+use crate::ProductEvent;
+use futures::future::{self, Either, Future};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{BorrowedMessage, Header, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Offset, TopicPartitionList};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::pin::pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Event types the provider publishes.
+const KNOWN_EVENTS: [&str; 3] = ["CREATED", "UPDATED", "DELETED"];
+
+/// Header of a dead-lettered message saying why it could not be decoded.
+const DEAD_LETTER_REASON_HEADER: &str = "dlq-reason";
+
+/// How long forwarding a message to the dead letter topic may take.
+const DEAD_LETTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long seeking back to a message that must be retried may take.
+const SEEK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before receiving a message again after it failed, so a store that stays
+/// broken is not retried in a tight loop.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How often the consumer lag is measured.
+const LAG_REPORT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long each query to the broker for the consumer lag may take. The queries block the
+/// consumer, so this is kept short.
+const LAG_QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Errors raised while reading a `ProductEvent` off the topic.
+#[derive(Debug)]
+pub enum ConsumeError {
+    /// The consumer could not receive a message from the broker.
+    Kafka(KafkaError),
+    /// The message had no payload.
+    EmptyPayload,
+    /// The payload was not a valid `ProductEvent` JSON document.
+    Deserialization(serde_json::Error),
+    /// The `event` field was not one of `CREATED`, `UPDATED` or `DELETED`.
+    UnknownEvent(String),
+    /// The event could not be applied, so its offset was not committed and the consumer seeks
+    /// back to it, to receive it again.
+    Apply(String),
+    /// The message at `offset` of `partition` could not be decoded, for the reason in `source`.
+    /// It is skipped, after being forwarded to the dead letter topic if there is one.
+    Deserialize {
+        partition: i32,
+        offset: i64,
+        source: Box<ConsumeError>,
+    },
+    /// A message that could not be decoded could not be forwarded to the dead letter topic
+    /// either, so its offset was not committed and the consumer seeks back to it, to forward
+    /// it again.
+    DeadLetter(KafkaError),
+}
+
+impl fmt::Display for ConsumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsumeError::Kafka(err) => write!(f, "failed to receive message: {}", err),
+            ConsumeError::EmptyPayload => write!(f, "message has no payload"),
+            ConsumeError::Deserialization(err) => {
+                write!(f, "failed to deserialize product event: {}", err)
+            }
+            ConsumeError::UnknownEvent(event) => write!(
+                f,
+                "unknown event type {:?}, expected one of {}",
+                event,
+                KNOWN_EVENTS.join(", ")
+            ),
+            ConsumeError::Apply(err) => write!(f, "failed to apply product event: {}", err),
+            ConsumeError::Deserialize {
+                partition,
+                offset,
+                source,
+            } => write!(
+                f,
+                "skipping message at partition {} offset {}: {}",
+                partition, offset, source
+            ),
+            ConsumeError::DeadLetter(err) => {
+                write!(
+                    f,
+                    "failed to forward message to the dead letter topic: {}",
+                    err
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConsumeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConsumeError::Kafka(err) => Some(err),
+            ConsumeError::Deserialization(err) => Some(err),
+            ConsumeError::Deserialize { source, .. } => Some(source.as_ref()),
+            ConsumeError::DeadLetter(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<KafkaError> for ConsumeError {
+    fn from(err: KafkaError) -> Self {
+        ConsumeError::Kafka(err)
+    }
+}
+
+impl From<serde_json::Error> for ConsumeError {
+    fn from(err: serde_json::Error) -> Self {
+        ConsumeError::Deserialization(err)
+    }
+}
+
+/// Deserializes a JSON payload into a `ProductEvent`, rejecting unknown event types.
+pub fn decode_event(payload: &[u8]) -> Result<ProductEvent, ConsumeError> {
+    let event: ProductEvent = serde_json::from_slice(payload)?;
+    if !KNOWN_EVENTS.contains(&event.event.as_str()) {
+        return Err(ConsumeError::UnknownEvent(event.event));
+    }
+    Ok(event)
+}
+
+/// Where a consumer group without committed offsets starts reading (`auto.offset.reset`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffsetReset {
+    /// From the beginning of the topic, so a fresh consumer rebuilds the full product list.
+    Earliest,
+    /// Only events published after the consumer joined.
+    Latest,
+}
+
+impl OffsetReset {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OffsetReset::Earliest => "earliest",
+            OffsetReset::Latest => "latest",
+        }
+    }
+}
+
+impl FromStr for OffsetReset {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "earliest" => Ok(OffsetReset::Earliest),
+            "latest" => Ok(OffsetReset::Latest),
+            _ => Err(format!(
+                "invalid KAFKA_OFFSET_RESET {:?}, expected earliest or latest",
+                value
+            )),
+        }
+    }
+}
+
+/// Decodes a message's payload and hands the event to `apply`.
+fn handle_payload<E: fmt::Display>(
+    payload: Option<&[u8]>,
+    apply: impl FnOnce(ProductEvent) -> Result<(), E>,
+) -> Result<(), ConsumeError> {
+    let event = decode_event(payload.ok_or(ConsumeError::EmptyPayload)?)?;
+    apply(event).map_err(|err| ConsumeError::Apply(err.to_string()))
+}
+
+/// Like [`handle_payload`], but a message that cannot be decoded is reported as
+/// [`ConsumeError::Deserialize`] with its partition and offset, so it can be found again.
+fn handle_message<E: fmt::Display>(
+    message: &impl Message,
+    apply: impl FnOnce(ProductEvent) -> Result<(), E>,
+) -> Result<(), ConsumeError> {
+    handle_payload(message.payload(), apply).map_err(|err| match err {
+        ConsumeError::Apply(_) => err,
+        err => ConsumeError::Deserialize {
+            partition: message.partition(),
+            offset: message.offset(),
+            source: Box::new(err),
+        },
+    })
+}
+
+/// Whether a message's offset may be committed after handling it. Only a failure to apply
+/// holds the offset back: a message that cannot be decoded will not decode on a retry either.
+fn should_commit(result: &Result<(), ConsumeError>) -> bool {
+    !matches!(result, Err(ConsumeError::Apply(_)))
+}
+
+/// Reads product events from a Kafka topic.
+///
+/// Offsets are committed by hand, only once an event has been applied, which gives
+/// at-least-once delivery. An event that fails to apply is retried: the consumer seeks its
+/// partition back to it, so it is the next message received there and no later message's
+/// commit moves past it. An event whose offset is not committed because the consumer crashed
+/// first is delivered again when the partition is next assigned, for example after a restart.
+/// Applying an event must therefore be idempotent, which the version check in
+/// `ProductStore::apply` takes care of.
+///
+/// A message that cannot be decoded is a poison message: it is skipped rather than retried,
+/// after being forwarded as is to the dead letter topic, if one is configured.
+pub struct ProductConsumer {
+    consumer: StreamConsumer,
+    dead_letters: Option<(FutureProducer, String)>,
+    /// The offset to resume from for each topic partition, after the last message handled
+    /// there, committed once more on shutdown.
+    handled: Mutex<HashMap<(String, i32), i64>>,
+    lag: Option<Arc<ConsumerLag>>,
+}
+
+impl ProductConsumer {
+    /// Starts configuring a consumer of `topic`. Unless told otherwise it joins a group of its
+    /// own and reads from the earliest offset.
+    pub fn builder(broker: &str, topic: &str) -> ProductConsumerBuilder {
+        ProductConsumerBuilder {
+            broker: broker.to_string(),
+            topic: topic.to_string(),
+            group_id: None,
+            offset_reset: OffsetReset::Earliest,
+            dead_letter_topic: None,
+            lag: None,
+        }
+    }
+
+    /// Waits for the next message, decodes it and passes the event to `apply`, committing the
+    /// message's offset unless `apply` fails, in which case the message is received again next,
+    /// after [`RETRY_DELAY`]. A message that cannot be decoded is dead-lettered and skipped, or
+    /// received again the same way if it cannot be dead-lettered.
+    pub async fn process_next<E: fmt::Display>(
+        &self,
+        apply: impl FnOnce(ProductEvent) -> Result<(), E>,
+    ) -> Result<(), ConsumeError> {
+        let message = self.consumer.recv().await?;
+        let result = handle_message(&message, apply);
+        if let Err(err @ ConsumeError::Deserialize { .. }) = &result {
+            if let Err(err) = self.dead_letter(&message, err).await {
+                self.rewind(&message).await?;
+                return Err(err);
+            }
+        }
+        if should_commit(&result) {
+            self.consumer.commit_message(&message, CommitMode::Async)?;
+            self.handled.lock().unwrap().insert(
+                (message.topic().to_string(), message.partition()),
+                message.offset() + 1,
+            );
+        } else {
+            self.rewind(&message).await?;
+        }
+        result
+    }
+
+    /// Seeks the partition of `message` back to it, so it is the next message received there,
+    /// once [`RETRY_DELAY`] has passed.
+    async fn rewind(&self, message: &BorrowedMessage<'_>) -> Result<(), KafkaError> {
+        self.consumer.seek(
+            message.topic(),
+            message.partition(),
+            Offset::Offset(message.offset()),
+            SEEK_TIMEOUT,
+        )?;
+        tokio::time::sleep(RETRY_DELAY).await;
+        Ok(())
+    }
+
+    /// Processes messages, as [`ProductConsumer::process_next`] does, until `shutdown`
+    /// completes, measuring the consumer lag every [`LAG_REPORT_INTERVAL`] if there are lag
+    /// metrics to update. Then commits the offsets of the messages handled, waiting for the
+    /// broker, as the asynchronous commits made along the way may not have finished, and closes
+    /// the consumer.
+    pub async fn run_until_shutdown<E: fmt::Display>(
+        self,
+        mut apply: impl FnMut(ProductEvent) -> Result<(), E>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), KafkaError> {
+        let consume = async {
+            let mut lag_reports = tokio::time::interval(LAG_REPORT_INTERVAL);
+            lag_reports.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                // A lag report that falls due mid-message does not drop the message: it is
+                // picked up again once the report is made.
+                let next = pin!(self.process_next(&mut apply));
+                let tick = pin!(lag_reports.tick());
+                let result = match future::select(next, tick).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right((_, next)) => {
+                        if let Err(err) = self.report_lag() {
+                            eprintln!("Failed to measure the consumer lag: {}", err);
+                        }
+                        next.await
+                    }
+                };
+                if let Err(err) = result {
+                    eprintln!("Failed to consume product event: {}", err);
+                }
+            }
+        };
+        stop_then_commit(consume, shutdown, || self.commit_handled()).await
+    }
+
+    /// Updates the lag metrics, if any, with the distance from the committed offset to the high
+    /// watermark of each assigned partition.
+    fn report_lag(&self) -> Result<(), KafkaError> {
+        let Some(metrics) = &self.lag else {
+            return Ok(());
+        };
+        let committed = self.consumer.committed(LAG_QUERY_TIMEOUT)?;
+        let mut lags = Vec::new();
+        for partition in committed.elements() {
+            let (low, high) = self.consumer.fetch_watermarks(
+                partition.topic(),
+                partition.partition(),
+                LAG_QUERY_TIMEOUT,
+            )?;
+            lags.push((
+                partition.topic().to_string(),
+                partition.partition(),
+                partition_lag(low, high, partition.offset()),
+            ));
+        }
+        metrics.update(&lags);
+        Ok(())
+    }
+
+    /// Commits the offsets after the last message handled on each partition, if any.
+    fn commit_handled(&self) -> Result<(), KafkaError> {
+        let handled = self.handled.lock().unwrap();
+        if handled.is_empty() {
+            return Ok(());
+        }
+        let mut offsets = TopicPartitionList::new();
+        for ((topic, partition), offset) in handled.iter() {
+            offsets.add_partition_offset(topic, *partition, Offset::Offset(*offset))?;
+        }
+        self.consumer.commit(&offsets, CommitMode::Sync)
+    }
+
+    /// Forwards the raw key and payload of `message` to the dead letter topic, with `err` as the
+    /// reason. Does nothing without a dead letter topic.
+    async fn dead_letter(
+        &self,
+        message: &BorrowedMessage<'_>,
+        err: &ConsumeError,
+    ) -> Result<(), ConsumeError> {
+        let Some((producer, topic)) = &self.dead_letters else {
+            return Ok(());
+        };
+        let reason = err.to_string();
+        let mut record: FutureRecord<[u8], [u8]> =
+            FutureRecord::to(topic).headers(OwnedHeaders::new().insert(Header {
+                key: DEAD_LETTER_REASON_HEADER,
+                value: Some(&reason),
+            }));
+        if let Some(key) = message.key() {
+            record = record.key(key);
+        }
+        if let Some(payload) = message.payload() {
+            record = record.payload(payload);
+        }
+        producer
+            .send(record, DEAD_LETTER_TIMEOUT)
+            .await
+            .map(|_| ())
+            .map_err(|(err, _)| ConsumeError::DeadLetter(err))
+    }
+}
+
+/// Configures a [`ProductConsumer`].
+pub struct ProductConsumerBuilder {
+    broker: String,
+    topic: String,
+    group_id: Option<String>,
+    offset_reset: OffsetReset,
+    dead_letter_topic: Option<String>,
+    lag: Option<Arc<ConsumerLag>>,
+}
+
+impl ProductConsumerBuilder {
+    /// The consumer group to join. Without one a unique group is generated, so separate runs
+    /// never share committed offsets.
+    pub fn group_id(mut self, group_id: &str) -> Self {
+        self.group_id = Some(group_id.to_string());
+        self
+    }
+
+    pub fn offset_reset(mut self, offset_reset: OffsetReset) -> Self {
+        self.offset_reset = offset_reset;
+        self
+    }
+
+    /// Forwards messages that cannot be decoded to `topic` before skipping them.
+    pub fn dead_letter_topic(mut self, topic: &str) -> Self {
+        self.dead_letter_topic = Some(topic.to_string());
+        self
+    }
+
+    /// Reports the lag of each assigned partition to `metrics` while running.
+    pub fn lag_metrics(mut self, metrics: Arc<ConsumerLag>) -> Self {
+        self.lag = Some(metrics);
+        self
+    }
+
+    /// Applies `KAFKA_GROUP_ID`, `KAFKA_OFFSET_RESET` (`earliest` or `latest`) and
+    /// `KAFKA_DLQ_TOPIC` from `lookup`, usually the environment.
+    pub fn with_lookup(mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        if let Some(group_id) = lookup("KAFKA_GROUP_ID") {
+            self = self.group_id(&group_id);
+        }
+        if let Some(offset_reset) = lookup("KAFKA_OFFSET_RESET") {
+            self = self.offset_reset(offset_reset.parse()?);
+        }
+        if let Some(topic) = lookup("KAFKA_DLQ_TOPIC").filter(|topic| !topic.trim().is_empty()) {
+            self = self.dead_letter_topic(&topic);
+        }
+        Ok(self)
+    }
+
+    fn client_config(&self) -> ClientConfig {
+        let group_id = self.group_id.clone().unwrap_or_else(unique_group_id);
+        let mut config = ClientConfig::new();
+        config
+            .set("group.id", group_id)
+            .set("bootstrap.servers", &self.broker)
+            .set("auto.offset.reset", self.offset_reset.as_str())
+            .set("enable.auto.commit", "false");
+        config
+    }
+
+    /// Creates the consumer and subscribes it to the topic, along with a producer for the dead
+    /// letter topic if there is one.
+    pub fn build(self) -> Result<ProductConsumer, KafkaError> {
+        let consumer: StreamConsumer = self.client_config().create()?;
+        consumer.subscribe(&[&self.topic])?;
+        let dead_letters = match self.dead_letter_topic {
+            Some(topic) => {
+                let producer: FutureProducer = ClientConfig::new()
+                    .set("bootstrap.servers", &self.broker)
+                    .create()?;
+                Some((producer, topic))
+            }
+            None => None,
+        };
+        Ok(ProductConsumer {
+            consumer,
+            dead_letters,
+            handled: Mutex::new(HashMap::new()),
+            lag: self.lag,
+        })
+    }
+}
+
+/// Drives `consume` until `shutdown` completes, then runs the final `commit`.
+async fn stop_then_commit<T>(
+    consume: impl Future<Output = Infallible>,
+    shutdown: impl Future<Output = ()>,
+    commit: impl FnOnce() -> T,
+) -> T {
+    future::select(pin!(consume), pin!(shutdown)).await;
+    commit()
+}
+
+fn unique_group_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("products-{}-{}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_event, handle_message, handle_payload, should_commit, stop_then_commit,
+        ConsumeError, OffsetReset, ProductConsumer,
+    };
+    use crate::store::ProductStore;
+    use crate::ProductEvent;
+    use expectest::prelude::*;
+    use rdkafka::message::OwnedMessage;
+    use rdkafka::Timestamp;
+    use serde_json::json;
+
+    fn payload(event: &str) -> Vec<u8> {
+        json!({
+            "id": "some-uuid-1234-5678",
+            "name": "Some Product",
+            "type": "Product Range",
+            "version": "v1",
+            "event": event
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn decodes_known_events_and_rejects_unknown_ones() {
+        let event = decode_event(&payload("CREATED")).unwrap();
+        expect!(event.id.as_str()).to(be_equal_to("some-uuid-1234-5678"));
+        expect!(event.event.as_str()).to(be_equal_to("CREATED"));
+
+        let err = decode_event(&payload("ARCHIVED")).unwrap_err();
+        expect!(err.to_string()).to(be_equal_to(
+            "unknown event type \"ARCHIVED\", expected one of CREATED, UPDATED, DELETED",
+        ));
+        expect!(matches!(
+            decode_event(b"not json"),
+            Err(ConsumeError::Deserialization(_))
+        ))
+        .to(be_true());
+    }
+
+    #[test]
+    fn applies_the_group_id_and_offset_reset() {
+        let builder = ProductConsumer::builder("localhost:9092", "products")
+            .with_lookup(|name| match name {
+                "KAFKA_GROUP_ID" => Some("workshop-run-1".to_string()),
+                "KAFKA_OFFSET_RESET" => Some("latest".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        let config = builder.client_config();
+        expect!(config.get("group.id")).to(be_some().value("workshop-run-1"));
+        expect!(config.get("auto.offset.reset")).to(be_some().value("latest"));
+        expect!(config.get("enable.auto.commit")).to(be_some().value("false"));
+        expect!(builder.dead_letter_topic).to(be_none());
+        let builder = ProductConsumer::builder("localhost:9092", "products")
+            .with_lookup(|name| (name == "KAFKA_DLQ_TOPIC").then(|| "products-dlq".to_string()))
+            .unwrap();
+        expect!(builder.dead_letter_topic).to(be_some().value("products-dlq".to_string()));
+
+        let builder = ProductConsumer::builder("localhost:9092", "products");
+        let (first, second) = (builder.client_config(), builder.client_config());
+        expect!(first.get("auto.offset.reset")).to(be_some().value("earliest"));
+        expect!(first.get("group.id")).to_not(be_equal_to(second.get("group.id")));
+
+        let invalid = ProductConsumer::builder("localhost:9092", "products")
+            .with_lookup(|name| (name == "KAFKA_OFFSET_RESET").then(|| "newest".to_string()));
+        expect!(invalid.err())
+            .to(be_some()
+                .value("invalid KAFKA_OFFSET_RESET \"newest\", expected earliest or latest"));
+        expect!("earliest".parse::<OffsetReset>()).to(be_ok().value(OffsetReset::Earliest));
+    }
+
+    /// Stands in for the application's store, failing to apply events while `broken` is set.
+    #[derive(Default)]
+    struct StubStore {
+        store: ProductStore,
+        broken: bool,
+    }
+
+    impl StubStore {
+        fn apply(&mut self, event: ProductEvent) -> Result<(), String> {
+            if self.broken {
+                return Err("store unavailable".to_string());
+            }
+            self.store.apply(&event);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn commits_only_events_that_were_applied() {
+        let mut stub = StubStore::default();
+        let created = payload("CREATED");
+
+        let result = handle_payload(Some(&created), |event| stub.apply(event));
+        expect!(should_commit(&result)).to(be_true());
+        expect!(stub.store.get("some-uuid-1234-5678")).to(be_some());
+
+        stub.broken = true;
+        let result = handle_payload(Some(&created), |event| stub.apply(event));
+        expect!(result.as_ref().unwrap_err().to_string()).to(be_equal_to(
+            "failed to apply product event: store unavailable",
+        ));
+        expect!(should_commit(&result)).to(be_false());
+
+        // retrying a message that cannot be decoded will not help, so it is skipped
+        let result = handle_payload(Some(b"not json"), |event| stub.apply(event));
+        expect!(should_commit(&result)).to(be_true());
+        let result = handle_payload(None, |event| stub.apply(event));
+        expect!(should_commit(&result)).to(be_true());
+    }
+
+    #[test]
+    fn skips_a_malformed_message_and_carries_on_with_the_next() {
+        let message = |offset: i64, payload: Vec<u8>| {
+            OwnedMessage::new(
+                Some(payload),
+                None,
+                "products".to_string(),
+                Timestamp::NotAvailable,
+                2,
+                offset,
+                None,
+            )
+        };
+        let mut stub = StubStore::default();
+
+        let result = handle_message(&message(7, b"{\"id\": 42".to_vec()), |event| {
+            stub.apply(event)
+        });
+        let err = result.as_ref().unwrap_err();
+        expect!(matches!(
+            err,
+            ConsumeError::Deserialize { partition: 2, offset: 7, source }
+                if matches!(source.as_ref(), ConsumeError::Deserialization(_))
+        ))
+        .to(be_true());
+        expect!(err
+            .to_string()
+            .starts_with("skipping message at partition 2 offset 7: "))
+        .to(be_true());
+        expect!(should_commit(&result)).to(be_true());
+
+        let result = handle_message(&message(8, payload("CREATED")), |event| stub.apply(event));
+        expect!(result.is_ok()).to(be_true());
+        expect!(stub.store.get("some-uuid-1234-5678")).to(be_some());
+    }
+
+    #[actix_web::test]
+    async fn an_event_that_fails_to_apply_is_received_again() {
+        use rdkafka::config::ClientConfig;
+        use rdkafka::mocking::MockCluster;
+        use rdkafka::producer::{FutureProducer, FutureRecord};
+        use std::time::Duration;
+
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("products", 1, 1).unwrap();
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        for event in ["CREATED", "UPDATED"] {
+            producer
+                .send(
+                    FutureRecord::<(), _>::to("products").payload(&payload(event)),
+                    Duration::from_secs(10),
+                )
+                .await
+                .unwrap();
+        }
+        let consumer = ProductConsumer::builder(&cluster.bootstrap_servers(), "products")
+            .build()
+            .unwrap();
+        let mut stub = StubStore {
+            broken: true,
+            ..StubStore::default()
+        };
+        let mut received = vec![];
+
+        tokio::time::timeout(Duration::from_secs(30), async {
+            for _ in 0..3 {
+                let _ = consumer
+                    .process_next(|event| {
+                        received.push(event.event.clone());
+                        let result = stub.apply(event);
+                        stub.broken = false;
+                        result
+                    })
+                    .await;
+            }
+        })
+        .await
+        .expect("timed out waiting for the events");
+
+        expect!(received).to(be_equal_to(vec![
+            "CREATED".to_string(),
+            "CREATED".to_string(),
+            "UPDATED".to_string(),
+        ]));
+    }
+
+    #[actix_web::test]
+    async fn a_message_that_fails_to_dead_letter_is_received_again() {
+        use rdkafka::config::ClientConfig;
+        use rdkafka::mocking::MockCluster;
+        use rdkafka::producer::{FutureProducer, FutureRecord};
+        use rdkafka::types::{RDKafkaApiKey, RDKafkaRespErr};
+        use std::time::Duration;
+
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("products", 1, 1).unwrap();
+        cluster.create_topic("products-dlq", 1, 1).unwrap();
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        producer
+            .send(
+                FutureRecord::<(), _>::to("products").payload("not json"),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        // the first forward to the dead letter topic fails for good
+        cluster.request_errors(
+            RDKafkaApiKey::Produce,
+            &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_MSG_SIZE_TOO_LARGE],
+        );
+        let consumer = ProductConsumer::builder(&cluster.bootstrap_servers(), "products")
+            .dead_letter_topic("products-dlq")
+            .build()
+            .unwrap();
+        let mut stub = StubStore::default();
+
+        let (first, second) = tokio::time::timeout(Duration::from_secs(30), async {
+            let first = consumer.process_next(|event| stub.apply(event)).await;
+            let second = consumer.process_next(|event| stub.apply(event)).await;
+            (first, second)
+        })
+        .await
+        .expect("timed out waiting for the message");
+
+        expect!(matches!(first, Err(ConsumeError::DeadLetter(_)))).to(be_true());
+        expect!(matches!(
+            second,
+            Err(ConsumeError::Deserialize { offset: 0, .. })
+        ))
+        .to(be_true());
+    }
+
+    #[actix_web::test]
+    async fn shutting_down_stops_consuming_and_commits() {
+        // consuming never ends by itself, shutdown comes once it has started
+        let (started, running) = futures::channel::oneshot::channel();
+        let consume = async move {
+            let _ = started.send(());
+            futures::future::pending().await
+        };
+        let shutdown = async move {
+            let _ = running.await;
+        };
+        let mut committed = false;
+
+        let result = stop_then_commit(consume, shutdown, || {
+            committed = true;
+            "committed"
+        })
+        .await;
+
+        expect!(result).to(be_equal_to("committed"));
+        expect!(committed).to(be_true());
+    }
+
+    /// Needs a broker on `localhost:9092`, e.g. `docker compose -f ../kafka-cluster.yml up`.
+    #[cfg(feature = "kafka-tests")]
+    #[actix_web::test]
+    async fn reads_back_an_event_published_to_kafka() {
+        use rdkafka::config::ClientConfig;
+        use rdkafka::producer::{FutureProducer, FutureRecord};
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+            .to_string();
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "localhost:9092")
+            .create()
+            .unwrap();
+        let mut body: serde_json::Value = serde_json::from_slice(&payload("UPDATED")).unwrap();
+        body["id"] = json!(unique);
+        let body = body.to_string();
+        producer
+            .send(
+                FutureRecord::to("products").key(&unique).payload(&body),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+
+        let consumer = ProductConsumer::builder("localhost:9092", "products")
+            .build()
+            .unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                let mut received = None;
+                let _ = consumer
+                    .process_next(|event| {
+                        received = Some(event).filter(|event| event.id == unique);
+                        Ok::<_, String>(())
+                    })
+                    .await;
+                if let Some(event) = received {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for the published event");
+
+        expect!(event.event.as_str()).to(be_equal_to("UPDATED"));
+        expect!(event.name.as_str()).to(be_equal_to("Some Product"));
+    }
+}
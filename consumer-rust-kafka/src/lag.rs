@@ -0,0 +1,94 @@
+use prometheus::{Encoder, IntGaugeVec, Opts, Registry, TextEncoder};
+use rdkafka::Offset;
+
+/// How many messages the consumer has yet to handle on a partition: the distance from its
+/// committed offset to the high watermark. Without a committed offset, every message still
+/// retained, from the low watermark on, counts as unread.
+pub fn partition_lag(low: i64, high: i64, committed: Offset) -> i64 {
+    let position = match committed {
+        Offset::Offset(offset) => offset,
+        _ => low,
+    };
+    (high - position).max(0)
+}
+
+/// The `consumer_lag` gauge, by topic and partition, exposed at `GET /metrics`.
+pub struct ConsumerLag {
+    registry: Registry,
+    lag: IntGaugeVec,
+}
+
+impl ConsumerLag {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let lag = IntGaugeVec::new(
+            Opts::new(
+                "consumer_lag",
+                "Messages between the committed offset and the high watermark, by partition",
+            ),
+            &["topic", "partition"],
+        )
+        .expect("valid gauge definition");
+        registry
+            .register(Box::new(lag.clone()))
+            .expect("gauge registered once");
+        ConsumerLag { registry, lag }
+    }
+
+    /// Replaces the lag of every partition with `lags`, so partitions no longer assigned to
+    /// the consumer drop out.
+    pub fn update(&self, lags: &[(String, i32, i64)]) {
+        self.lag.reset();
+        for (topic, partition, lag) in lags {
+            self.lag
+                .with_label_values(&[topic.as_str(), &partition.to_string()])
+                .set(*lag);
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encode as text");
+        String::from_utf8(buffer).expect("metrics are UTF-8")
+    }
+}
+
+impl Default for ConsumerLag {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{partition_lag, ConsumerLag};
+    use expectest::prelude::*;
+    use rdkafka::Offset;
+
+    #[test]
+    fn measures_lag_from_the_committed_offset_or_the_low_watermark() {
+        expect!(partition_lag(0, 120, Offset::Offset(100))).to(be_equal_to(20));
+        expect!(partition_lag(0, 120, Offset::Offset(120))).to(be_equal_to(0));
+        expect!(partition_lag(40, 120, Offset::Invalid)).to(be_equal_to(80));
+        // A committed offset past the watermark, seen before the watermark catches up.
+        expect!(partition_lag(0, 120, Offset::Offset(121))).to(be_equal_to(0));
+    }
+
+    #[test]
+    fn exposes_the_lag_of_the_assigned_partitions() {
+        let metrics = ConsumerLag::new();
+        metrics.update(&[
+            ("products".to_string(), 0, 20),
+            ("products".to_string(), 1, 3),
+        ]);
+        metrics.update(&[("products".to_string(), 0, 5)]);
+
+        let rendered = metrics.render();
+        expect!(rendered.contains(r#"consumer_lag{partition="0",topic="products"} 5"#))
+            .to(be_true());
+        expect!(rendered.contains(r#"partition="1""#)).to(be_false());
+    }
+}
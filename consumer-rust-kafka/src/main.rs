@@ -1,11 +1,14 @@
+mod consumer;
+mod lag;
+mod store;
+mod version;
+
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use futures::StreamExt;
-use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{Consumer, StreamConsumer};
-use rdkafka::message::Message;
+use consumer::{decode_event, ProductConsumer};
+use lag::ConsumerLag;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use store::{ApplyResult, ProductStore};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Product {
@@ -15,7 +18,7 @@ pub struct Product {
     version: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ProductEvent {
     id: String,
     name: String,
@@ -25,18 +28,17 @@ pub struct ProductEvent {
 }
 
 pub struct AppState {
-    products: Mutex<HashMap<String, Product>>,
+    products: Mutex<ProductStore>,
 }
 
 async fn get_all(data: web::Data<AppState>) -> impl Responder {
     let products = data.products.lock().unwrap();
-    let products: Vec<&Product> = products.values().collect();
-    HttpResponse::Ok().json(products)
+    HttpResponse::Ok().json(products.all())
 }
 
 async fn get_by_id(data: web::Data<AppState>, product_id: web::Path<String>) -> impl Responder {
     let products = data.products.lock().unwrap();
-    if let Some(product) = products.get(&product_id.into_inner()) {
+    if let Some(product) = products.get(&product_id) {
         HttpResponse::Ok().json(product)
     } else {
         HttpResponse::NotFound().json("Product not found")
@@ -44,107 +46,147 @@ async fn get_by_id(data: web::Data<AppState>, product_id: web::Path<String>) ->
 }
 
 pub fn product_event_processor(data: &web::Data<AppState>, payload: &[u8]) {
-    let product_event: ProductEvent =
-        serde_json::from_slice(payload).expect("Error deserializing product");
-    let product = Product {
-        id: product_event.id.clone(),
-        r#type: product_event.r#type.clone(),
-        name: product_event.name.clone(),
-        version: product_event.version.clone(),
-    };
-    let mut products = data.products.lock().unwrap();
-    match product_event.event.as_str() {
-        "CREATED" | "UPDATED" => {
-            products.insert(product_event.id.clone(), product);
-        }
-        "DELETED" => {
-            products.remove(&product.id);
-        }
-        _ => {
-            eprintln!("Unknown event type");
+    match decode_event(payload) {
+        Ok(product_event) => {
+            if let Err(e) = apply_product_event(data, product_event) {
+                eprintln!("Failed to apply product event: {}", e);
+            }
         }
+        Err(e) => eprintln!("Skipping product event: {}", e),
     }
 }
 
-async fn kafka_consumer(data: web::Data<AppState>) {
-    let consumer: StreamConsumer = ClientConfig::new()
-        .set("group.id", "products-group")
-        .set("bootstrap.servers", "localhost:9092")
-        .create()
+fn apply_product_event(
+    data: &web::Data<AppState>,
+    product_event: ProductEvent,
+) -> Result<(), String> {
+    let mut products = data
+        .products
+        .lock()
+        .map_err(|_| "the product store is poisoned".to_string())?;
+    match products.apply(&product_event) {
+        ApplyResult::Applied => {}
+        ApplyResult::Stale => eprintln!(
+            "Ignoring stale {} event for product {} at version {}",
+            product_event.event, product_event.id, product_event.version
+        ),
+        ApplyResult::Duplicate => eprintln!(
+            "Ignoring duplicate {} event for product {} at version {}",
+            product_event.event, product_event.id, product_event.version
+        ),
+    }
+    Ok(())
+}
+
+async fn metrics(lag: web::Data<ConsumerLag>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(lag.render())
+}
+
+async fn kafka_consumer(data: web::Data<AppState>, lag: Arc<ConsumerLag>) {
+    let consumer = ProductConsumer::builder("localhost:9092", "products")
+        .with_lookup(|name| std::env::var(name).ok())
+        .expect("Invalid consumer configuration")
+        .lag_metrics(lag)
+        .build()
         .expect("Consumer creation failed");
 
-    consumer
-        .subscribe(&["products"])
-        .expect("Can't subscribe to topic");
-
-    let mut message_stream = consumer.stream();
-
-    while let Some(message) = message_stream.next().await {
-        match message {
-            Ok(m) => {
-                if let Some(payload) = m.payload() {
-                    product_event_processor(&data, payload);
-                    // let product_event: ProductEvent =
-                    //     serde_json::from_slice(payload).expect("Error deserializing product");
-                    // let product = Product {
-                    //     id: product_event.id.clone(),
-                    //     r#type: product_event.r#type.clone(),
-                    //     name: product_event.name.clone(),
-                    //     version: product_event.version.clone(),
-                    // };
-                    // let mut products = data.products.lock().unwrap();
-                    // match product_event.event.as_str() {
-                    //     "CREATED" | "UPDATED" => {
-                    //         products.insert(product_event.id.clone(), product);
-                    //     }
-                    //     "DELETED" => {
-                    //         products.remove(&product.id);
-                    //     }
-                    //     _ => {
-                    //         eprintln!("Unknown event type");
-                    //     }
-                    // }
-                }
+    let stopped = consumer
+        .run_until_shutdown(
+            |product_event| apply_product_event(&data, product_event),
+            shutdown_signal(),
+        )
+        .await;
+    if let Err(e) = stopped {
+        eprintln!("Failed to commit offsets on shutdown: {}", e);
+    }
+}
+
+/// Completes on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
             }
-            Err(e) => eprintln!("Kafka error: {}", e),
+            Err(_) => std::future::pending::<()>().await,
         }
-    }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    futures::future::select(std::pin::pin!(ctrl_c), std::pin::pin!(terminate)).await;
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let products = Mutex::new(HashMap::new());
-    let data = web::Data::new(AppState { products });
+    let products = ProductStore::with_lookup(|name| std::env::var(name).ok())
+        .expect("Invalid product store configuration");
+    let data = web::Data::new(AppState {
+        products: Mutex::new(products),
+    });
+    let lag = web::Data::new(ConsumerLag::new());
 
-    // Start Kafka consumer
+    // Start Kafka consumer. Like the HTTP server, it stops on Ctrl-C or SIGTERM, and is
+    // waited for below so it can make its final commit.
     let data_clone = data.clone();
-    actix_rt::spawn(async move {
-        kafka_consumer(data_clone).await;
+    let lag_clone = lag.clone().into_inner();
+    let consumer = actix_rt::spawn(async move {
+        kafka_consumer(data_clone, lag_clone).await;
     });
 
     HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
+            .app_data(lag.clone())
             .route("/products", web::get().to(get_all))
             .route("/products/{id}", web::get().to(get_by_id))
             .route("/product/{id}", web::get().to(get_by_id))
+            .route("/metrics", web::get().to(metrics))
     })
     .bind("127.0.0.1:8080")?
     .run()
-    .await
+    .await?;
+
+    let _ = consumer.await;
+    Ok(())
 }
 
+/// Consumer contract tests. Each test describes the product event messages this consumer
+/// understands and feeds them through `product_event_processor`. When a test passes, its
+/// interaction is written to `target/pacts/pactflow-example-consumer-rust-kafka-pactflow-example-provider-rust-kafka.json`,
+/// the pact the provider verifies.
 #[cfg(test)]
 mod tests {
 
 use expectest::{expect, prelude::be_some};
+use pact_consumer::builders::MessageInteractionBuilder;
 use pact_consumer::{matching_regex, prelude::*};
+use pact_models::generators::{Generator, GeneratorCategory};
+use pact_models::matchingrules::{MatchingRule, RuleLogic};
+use pact_models::prelude::DataType;
+use pact_models::path_exp::DocPath;
+use pact_models::v4::async_message::AsynchronousMessage;
 use serde_json::Value;
-use crate::{product_event_processor, AppState};
-use std::collections::HashMap;
+use crate::store::ProductStore;
+use crate::{product_event_processor, AppState, ProductEvent};
 use std::sync::Mutex;
 use actix_web::web;
-use expectest::matchers::be_equal_to;
+use expectest::matchers::{be_equal_to, be_true};
+
+/// Id of the product in the example messages. The provider generates a fresh UUID for every
+/// product, so the pacts match ids by format rather than by value.
+const PRODUCT_ID: &str = "5cc989d0-d800-434c-b4bb-b1268499e850";
+const UUID_REGEX: &str = "^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$";
+/// The provider's default version scheme: `v` followed by a counter.
+const VERSION_REGEX: &str = r"^v\d+$";
+
 #[test]
 fn consumes_a_product_event_update_message() {
     // Define the Pact for the test (you can setup multiple interactions by chaining the given or message_interaction calls)
@@ -152,34 +194,36 @@ fn consumes_a_product_event_update_message() {
     let mut pact_builder =
         // Define the message consumer and provider by name
         pact_consumer::builders::PactBuilder::new_v4("pactflow-example-consumer-rust-kafka", "pactflow-example-provider-rust-kafka");
-    pact_builder
 
-        // Adds an interaction given the message description and type.
-        .message_interaction("a product event update", |mut i| {
-            // Can set the test name (optional)
-            i.test_name("consumes_a_product_event_update_message");
-            // // defines a provider state. It is optional.
-            // i.given("some state");
-            // // defines a provider state with parameters. It is optional.
-            // i.given_with_params("some state with params {param}",&json!({
-            //     "param": "some param"
-            //   }));
-            // Set the contents of the message. Here we use a JSON pattern, so that matching rules are applied
-            i.json_body(json_pattern!({
-              "id": like!("some-uuid-1234-5678"),
-              "type": like!("Product Range"),
-              "name": like!("Some Product"),
-              "version": like!("v1"),
-              "event": matching_regex!("^(CREATED|UPDATED|DELETED)$","UPDATED")
-            }));
-            // Set any required metadata
-            i.metadata("kafka_topic", "products");
-            // Need to return the mutated interaction builder
-            i
-        });
+    // Defines an interaction given the message description.
+    let mut i = MessageInteractionBuilder::new("a product event update");
+    // Can set the test name (optional)
+    i.test_name("consumes_a_product_event_update_message");
+    // Defines a provider state. The provider seeds a product for it and hands back its id as
+    // `productId`, which the generators below inject into the message.
+    i.given("a product exists");
+    // // defines a provider state with parameters. It is optional.
+    // i.given_with_params("some state with params {param}",&json!({
+    //     "param": "some param"
+    //   }));
+    // Set the contents of the message. Here we use a JSON pattern, so that matching rules are applied
+    i.json_body(json_pattern!({
+      "id": matching_regex!(UUID_REGEX, PRODUCT_ID),
+      "type": like!("Product Range"),
+      "name": like!("Some Product"),
+      "version": matching_regex!(VERSION_REGEX, "v1"),
+      "event": matching_regex!("^(CREATED|UPDATED|DELETED)$","UPDATED")
+    }));
+    // Set any required metadata
+    product_event_metadata(&mut i, "UPDATED");
+    // Adds the interaction, with matching rules for the metadata, taking the product id from
+    // the provider state
+    let mut message = product_event_message(&i);
+    product_id_from_provider_state(&mut message);
+    pact_builder.push_interaction(&message);
 
     // Arrange. setup product database
-    let products = Mutex::new(HashMap::new());
+    let products = Mutex::new(ProductStore::default());
     let data = web::Data::new(AppState { products });
     
     // This will return each message configured with the Pact builder. We need to process them
@@ -195,9 +239,9 @@ fn consumes_a_product_event_update_message() {
         
         // assert of the state of our product database, after processing the message
         let products = data.products.lock().unwrap();
-        let product = products.get("some-uuid-1234-5678").unwrap();
+        let product = products.get(PRODUCT_ID).unwrap();
         println!("{:?}", product);
-        expect!(product.id.as_str()).to(be_equal_to("some-uuid-1234-5678".to_string()));
+        expect!(product.id.as_str()).to(be_equal_to(PRODUCT_ID.to_string()));
         expect!(product.name.clone()).to(be_equal_to("Some Product".to_string()));
         expect!(product.r#type.clone()).to(be_equal_to("Product Range".to_string()));
         expect!(product.version.clone()).to(be_equal_to("v1".to_string()));
@@ -205,6 +249,102 @@ fn consumes_a_product_event_update_message() {
         // assert the correct topic is included in our message
         expect!(kafka_topic)
             .to(be_some().value("products"));
+        let metadata = &message.contents.metadata;
+        expect!(metadata.get("kafka_key")).to(be_some().value(PRODUCT_ID));
+        expect!(metadata.get("event-type")).to(be_some().value("UPDATED"));
+    }
+}
+
+/// Expects the topic, the record key and the `event-type` header of a product event.
+fn product_event_metadata(i: &mut MessageInteractionBuilder, event: &str) {
+    i.metadata("kafka_topic", "products");
+    i.metadata("kafka_key", PRODUCT_ID);
+    i.metadata("event-type", event);
+}
+
+/// Builds a pact with a single message of the given `event` type, as published for `description`.
+fn product_event_pact(description: &str, test_name: &str, event: &str) -> PactBuilder {
+    let mut pact_builder = PactBuilder::new_v4(
+        "pactflow-example-consumer-rust-kafka",
+        "pactflow-example-provider-rust-kafka",
+    );
+    let mut i = MessageInteractionBuilder::new(description);
+    i.test_name(test_name);
+    i.json_body(json_pattern!({
+      "id": matching_regex!(UUID_REGEX, PRODUCT_ID),
+      "type": like!("Product Range"),
+      "name": like!("Some Product"),
+      "version": matching_regex!(VERSION_REGEX, "v1"),
+      "event": event
+    }));
+    product_event_metadata(&mut i, event);
+    pact_builder.push_interaction(&product_event_message(&i));
+    pact_builder
+}
+
+/// Builds the message of `i`. The builder has no matchers for metadata, so the rules are added
+/// to the built message: the record key is the generated product id, so it is matched as a
+/// UUID, and the topic is matched by type, as the provider may route to a topic per event type.
+fn product_event_message(i: &MessageInteractionBuilder) -> AsynchronousMessage {
+    let mut message = i.build();
+    let metadata_rules = message.contents.matching_rules.add_category("metadata");
+    metadata_rules.add_rule(DocPath::root().join("kafka_topic"), MatchingRule::Type, RuleLogic::And);
+    metadata_rules.add_rule(
+        DocPath::root().join("kafka_key"),
+        MatchingRule::Regex(UUID_REGEX.to_string()),
+        RuleLogic::And,
+    );
+    message
+}
+
+/// Generates the product id, in the body and as the record key, from the `productId` value
+/// the provider state returns, so the provider is checked against the product it set up.
+fn product_id_from_provider_state(message: &mut AsynchronousMessage) {
+    let generator = Generator::ProviderStateGenerator("${productId}".to_string(), Some(DataType::STRING));
+    let generators = &mut message.contents.generators;
+    generators.add_generator_with_subcategory(&GeneratorCategory::BODY, DocPath::new_unwrap("$.id"), generator.clone());
+    generators.add_generator_with_subcategory(&GeneratorCategory::METADATA, DocPath::root().join("kafka_key"), generator);
+}
+
+#[test]
+fn consumes_a_product_event_create_message() {
+    let pact_builder = product_event_pact(
+        "a product event create",
+        "consumes_a_product_event_create_message",
+        "CREATED",
+    );
+    let data = web::Data::new(AppState { products: Mutex::new(ProductStore::default()) });
+
+    for message in pact_builder.messages() {
+        product_event_processor(&data, &message.contents.contents.value().unwrap());
+
+        let products = data.products.lock().unwrap();
+        expect!(products.get(PRODUCT_ID).map(|product| product.name.as_str()))
+            .to(be_some().value("Some Product"));
+    }
+}
+
+#[test]
+fn consumes_a_product_event_delete_message() {
+    let pact_builder = product_event_pact(
+        "a product event delete",
+        "consumes_a_product_event_delete_message",
+        "DELETED",
+    );
+    let mut products = ProductStore::default();
+    products.apply(&ProductEvent {
+        id: PRODUCT_ID.to_string(),
+        name: "Some Product".to_string(),
+        r#type: "Product Range".to_string(),
+        version: "v0".to_string(),
+        event: "CREATED".to_string(),
+    });
+    let data = web::Data::new(AppState { products: Mutex::new(products) });
+
+    for message in pact_builder.messages() {
+        product_event_processor(&data, &message.contents.contents.value().unwrap());
+
+        expect!(data.products.lock().unwrap().all().is_empty()).to(be_true());
     }
 }
 
@@ -0,0 +1,217 @@
+use crate::version::compare_versions;
+use crate::{Product, ProductEvent};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// How many recently applied `(id, version)` pairs are remembered by default.
+pub const DEFAULT_DEDUP_WINDOW: usize = 1024;
+
+/// Whether an event changed the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyResult {
+    Applied,
+    /// The event's version is not newer than the stored product's or its delete, so it was
+    /// ignored.
+    Stale,
+    /// The same id and version was applied recently, so the event is a redelivery.
+    Duplicate,
+}
+
+/// The consumer's view of the product catalogue, built up from product events.
+///
+/// Delivery is at-least-once, so the same event can arrive more than once. The store
+/// remembers the last `dedup_window` `(id, version)` pairs it applied and skips them when they
+/// come round again, evicting the oldest pair once the window is full.
+#[derive(Debug)]
+pub struct ProductStore {
+    products: HashMap<String, Product>,
+    /// The version each removed product was deleted at, so an older event arriving late cannot
+    /// bring it back.
+    deleted: HashMap<String, String>,
+    seen: HashSet<(String, String)>,
+    seen_order: VecDeque<(String, String)>,
+    dedup_window: usize,
+}
+
+impl Default for ProductStore {
+    fn default() -> Self {
+        ProductStore::with_dedup_window(DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+impl ProductStore {
+    /// An empty store remembering up to `dedup_window` applied events, `0` to disable
+    /// deduplication.
+    pub fn with_dedup_window(dedup_window: usize) -> Self {
+        ProductStore {
+            products: HashMap::new(),
+            deleted: HashMap::new(),
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            dedup_window,
+        }
+    }
+
+    /// An empty store with the window size from `PRODUCT_DEDUP_WINDOW` in `lookup`, usually
+    /// the environment.
+    pub fn with_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let dedup_window = match lookup("PRODUCT_DEDUP_WINDOW") {
+            Some(value) => value.parse().map_err(|_| {
+                format!(
+                    "invalid PRODUCT_DEDUP_WINDOW {:?}, expected a number of events",
+                    value
+                )
+            })?,
+            None => DEFAULT_DEDUP_WINDOW,
+        };
+        Ok(ProductStore::with_dedup_window(dedup_window))
+    }
+
+    /// Applies an event: `CREATED` and `UPDATED` insert or replace the product, `DELETED`
+    /// removes it. Events that arrive out of order, with a version no newer than the stored
+    /// product's or the one it was deleted at, are ignored.
+    pub fn apply(&mut self, event: &ProductEvent) -> ApplyResult {
+        let key = (event.id.clone(), event.version.clone());
+        if self.seen.contains(&key) {
+            return ApplyResult::Duplicate;
+        }
+        let latest = self
+            .products
+            .get(&event.id)
+            .map(|current| &current.version)
+            .or_else(|| self.deleted.get(&event.id));
+        if let Some(latest) = latest {
+            if compare_versions(&event.version, latest) != Ordering::Greater {
+                return ApplyResult::Stale;
+            }
+        }
+
+        match event.event.as_str() {
+            "DELETED" => {
+                self.products.remove(&event.id);
+                self.deleted.insert(event.id.clone(), event.version.clone());
+            }
+            _ => {
+                let product = Product {
+                    id: event.id.clone(),
+                    r#type: event.r#type.clone(),
+                    name: event.name.clone(),
+                    version: event.version.clone(),
+                };
+                self.deleted.remove(&event.id);
+                self.products.insert(event.id.clone(), product);
+            }
+        }
+        self.remember(key);
+        ApplyResult::Applied
+    }
+
+    fn remember(&mut self, key: (String, String)) {
+        if self.dedup_window == 0 {
+            return;
+        }
+        if self.seen_order.len() == self.dedup_window {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(key.clone());
+        self.seen_order.push_back(key);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Product> {
+        self.products.get(id)
+    }
+
+    pub fn all(&self) -> Vec<&Product> {
+        self.products.values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApplyResult, ProductStore};
+    use crate::ProductEvent;
+    use expectest::prelude::*;
+
+    fn event(event: &str, version: &str) -> ProductEvent {
+        ProductEvent {
+            id: "some-uuid-1234-5678".to_string(),
+            name: "Some Product".to_string(),
+            r#type: "Product Range".to_string(),
+            version: version.to_string(),
+            event: event.to_string(),
+        }
+    }
+
+    #[test]
+    fn create_then_delete_leaves_the_store_empty() {
+        let mut store = ProductStore::default();
+        store.apply(&event("CREATED", "v1"));
+        expect!(store.get("some-uuid-1234-5678")).to(be_some());
+
+        expect!(store.apply(&event("DELETED", "v2"))).to(be_equal_to(ApplyResult::Applied));
+        expect!(store.all().is_empty()).to(be_true());
+    }
+
+    #[test]
+    fn update_overwrites_an_earlier_version() {
+        let mut store = ProductStore::default();
+        store.apply(&event("CREATED", "v1"));
+        store.apply(&event("UPDATED", "v2"));
+
+        expect!(store.all().len()).to(be_equal_to(1));
+        let product = store.get("some-uuid-1234-5678").unwrap();
+        expect!(product.version.as_str()).to(be_equal_to("v2"));
+    }
+
+    #[test]
+    fn a_stale_create_does_not_bring_back_a_deleted_product() {
+        let mut store = ProductStore::default();
+        store.apply(&event("DELETED", "v2"));
+
+        expect!(store.apply(&event("CREATED", "v1"))).to(be_equal_to(ApplyResult::Stale));
+        expect!(store.all().is_empty()).to(be_true());
+
+        expect!(store.apply(&event("CREATED", "v3"))).to(be_equal_to(ApplyResult::Applied));
+        expect!(store.get("some-uuid-1234-5678")).to(be_some());
+    }
+
+    #[test]
+    fn ignores_stale_and_repeated_versions() {
+        let mut store = ProductStore::default();
+        expect!(store.apply(&event("CREATED", "v1"))).to(be_equal_to(ApplyResult::Applied));
+        expect!(store.apply(&event("UPDATED", "v3"))).to(be_equal_to(ApplyResult::Applied));
+
+        expect!(store.apply(&event("UPDATED", "v2"))).to(be_equal_to(ApplyResult::Stale));
+        expect!(store.apply(&event("UPDATED", "v3"))).to(be_equal_to(ApplyResult::Duplicate));
+        expect!(store.apply(&event("DELETED", "v2"))).to(be_equal_to(ApplyResult::Stale));
+
+        let product = store.get("some-uuid-1234-5678").unwrap();
+        expect!(product.version.as_str()).to(be_equal_to("v3"));
+    }
+
+    #[test]
+    fn skips_a_redelivered_event() {
+        let mut store = ProductStore::default();
+        expect!(store.apply(&event("CREATED", "v1"))).to(be_equal_to(ApplyResult::Applied));
+        expect!(store.apply(&event("CREATED", "v1"))).to(be_equal_to(ApplyResult::Duplicate));
+
+        expect!(store.apply(&event("UPDATED", "v2"))).to(be_equal_to(ApplyResult::Applied));
+        expect!(store.apply(&event("UPDATED", "v2"))).to(be_equal_to(ApplyResult::Duplicate));
+        expect!(store.all().len()).to(be_equal_to(1));
+    }
+
+    #[test]
+    fn forgets_the_oldest_event_once_the_window_overflows() {
+        let mut store = ProductStore::with_dedup_window(2);
+        store.apply(&event("CREATED", "v1"));
+        store.apply(&event("UPDATED", "v2"));
+        store.apply(&event("UPDATED", "v3"));
+
+        // v1 was evicted, so it falls through to the version check
+        expect!(store.apply(&event("CREATED", "v1"))).to(be_equal_to(ApplyResult::Stale));
+        expect!(store.apply(&event("UPDATED", "v2"))).to(be_equal_to(ApplyResult::Duplicate));
+        expect!(store.apply(&event("UPDATED", "v3"))).to(be_equal_to(ApplyResult::Duplicate));
+    }
+}
@@ -0,0 +1,45 @@
+use std::cmp::Ordering;
+
+/// Numeric components of a `vN`, `N`, `vX.Y.Z` or `X.Y.Z` version.
+fn components(version: &str) -> Option<Vec<u64>> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    version
+        .split('.')
+        .map(|part| part.parse::<u64>().ok())
+        .collect()
+}
+
+/// Orders two product versions. Counter (`v2`) and semver (`1.2.3`) versions are compared
+/// numerically component by component, with missing components treated as zero, so `v2` sorts
+/// after `1.9.9`. Versions that can't be parsed fall back to comparing the raw strings.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (components(a), components(b)) {
+        (Some(a), Some(b)) => {
+            let len = a.len().max(b.len());
+            let padded = |parts: Vec<u64>| {
+                let mut parts = parts;
+                parts.resize(len, 0);
+                parts
+            };
+            padded(a).cmp(&padded(b))
+        }
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compare_versions;
+    use expectest::prelude::*;
+    use std::cmp::Ordering;
+
+    #[test]
+    fn compares_counter_and_semver_versions_numerically() {
+        expect!(compare_versions("v2", "v10")).to(be_equal_to(Ordering::Less));
+        expect!(compare_versions("v3", "v3")).to(be_equal_to(Ordering::Equal));
+        expect!(compare_versions("1.10.0", "1.9.3")).to(be_equal_to(Ordering::Greater));
+        expect!(compare_versions("v1.2.3", "1.2.3")).to(be_equal_to(Ordering::Equal));
+        expect!(compare_versions("v2", "1.9.9")).to(be_equal_to(Ordering::Greater));
+        expect!(compare_versions("banana", "cherry")).to(be_equal_to(Ordering::Less));
+    }
+}
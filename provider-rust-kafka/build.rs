@@ -0,0 +1,11 @@
+fn main() {
+    // Generate the protobuf `ProductEvent` type with a pure-Rust compiler, so building the
+    // `protobuf` feature does not need `protoc` installed.
+    #[cfg(feature = "protobuf")]
+    {
+        println!("cargo:rerun-if-changed=proto/product_event.proto");
+        let descriptors = protox::compile(["proto/product_event.proto"], ["proto"])
+            .expect("failed to compile proto/product_event.proto");
+        prost_build::compile_fds(descriptors).expect("failed to generate protobuf types");
+    }
+}
@@ -0,0 +1,110 @@
+use crate::config::KafkaSecurity;
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication, TopicResult};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use std::time::Duration;
+use tracing::info;
+
+/// How long the broker may take to create the topic.
+const CREATE_TOPIC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configuration for an admin client of `broker`, connecting with the same `security`
+/// settings as the producer.
+fn admin_config(broker: &str, security: Option<&KafkaSecurity>) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.set("bootstrap.servers", broker);
+    if let Some(security) = security {
+        security.apply(&mut config);
+    }
+    config
+}
+
+/// Creates `topic` with the given layout unless it already exists. An existing topic is left
+/// as it is, even if its layout differs.
+pub async fn ensure_topic_exists(
+    broker: &str,
+    security: Option<&KafkaSecurity>,
+    topic: &str,
+    partitions: i32,
+    replication: i32,
+) -> KafkaResult<()> {
+    let admin: AdminClient<DefaultClientContext> = admin_config(broker, security).create()?;
+    let new_topic = NewTopic::new(topic, partitions, TopicReplication::Fixed(replication));
+    let options = AdminOptions::new().operation_timeout(Some(CREATE_TOPIC_TIMEOUT));
+
+    let results = admin.create_topics([&new_topic], &options).await?;
+    for result in results {
+        if created(result)? {
+            info!(topic, partitions, replication, "created topic");
+        } else {
+            info!(topic, "topic already exists, skipping creation");
+        }
+    }
+    Ok(())
+}
+
+/// Deletes `topic`, e.g. one only created for the self-test.
+pub async fn delete_topic(
+    broker: &str,
+    security: Option<&KafkaSecurity>,
+    topic: &str,
+) -> KafkaResult<()> {
+    let admin: AdminClient<DefaultClientContext> = admin_config(broker, security).create()?;
+    let options = AdminOptions::new().operation_timeout(Some(CREATE_TOPIC_TIMEOUT));
+
+    for result in admin.delete_topics(&[topic], &options).await? {
+        result.map_err(|(_, code)| KafkaError::AdminOp(code))?;
+    }
+    info!(topic, "deleted topic");
+    Ok(())
+}
+
+/// Whether the topic was created, treating a topic that already exists as success.
+fn created(result: TopicResult) -> KafkaResult<bool> {
+    match result {
+        Ok(_) => Ok(true),
+        Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => Ok(false),
+        Err((_, code)) => Err(KafkaError::AdminOp(code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{admin_config, created};
+    use crate::config::KafkaSecurity;
+    use expectest::prelude::*;
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+
+    #[test]
+    fn existing_topics_are_not_an_error() {
+        expect!(created(Ok("products".to_string()))).to(be_ok().value(true));
+        let exists = Err(("products".to_string(), RDKafkaErrorCode::TopicAlreadyExists));
+        expect!(created(exists)).to(be_ok().value(false));
+        let denied = Err((
+            "products".to_string(),
+            RDKafkaErrorCode::TopicAuthorizationFailed,
+        ));
+        expect!(created(denied)).to(be_err().value(KafkaError::AdminOp(
+            RDKafkaErrorCode::TopicAuthorizationFailed,
+        )));
+    }
+
+    #[test]
+    fn connects_with_the_producer_security_settings() {
+        let security = KafkaSecurity {
+            protocol: "SASL_SSL".to_string(),
+            sasl_mechanism: Some("PLAIN".to_string()),
+            sasl_username: Some("user".to_string()),
+            sasl_password: Some("secret".to_string()),
+            ssl_ca_location: None,
+            ssl_ca_pem: None,
+        };
+
+        let config = admin_config("broker:9093", Some(&security));
+        expect!(config.get("bootstrap.servers")).to(be_some().value("broker:9093"));
+        expect!(config.get("security.protocol")).to(be_some().value("SASL_SSL"));
+        expect!(config.get("sasl.username")).to(be_some().value("user"));
+        expect!(admin_config("broker:9093", None).get("security.protocol")).to(be_none());
+    }
+}
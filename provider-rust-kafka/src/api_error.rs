@@ -0,0 +1,251 @@
+use crate::errors::PublishError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use product_events::{FieldError, ValidationError};
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// How long clients are asked to back off when the producer queue is full.
+const QUEUE_FULL_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Everything a product endpoint can fail with. Each variant maps to its own status code and
+/// is returned as an [`ErrorResponse`], so clients can branch on `error.code`.
+#[derive(Debug)]
+pub enum ApiError {
+    /// `400`, `invalid_product`: the product failed validation, every offending field is listed.
+    InvalidProduct(ValidationError),
+    /// `400`, `invalid_body`: the request body could not be read as JSON for the route.
+    InvalidBody(String),
+    /// `404`, `not_found`.
+    NotFound(String),
+    /// `409`, `id_mismatch`: the body names another product than the path.
+    IdMismatch { body_id: String, path_id: String },
+    /// `412`, `version_mismatch`: the product's last event is not at the version the client
+    /// sent in `If-Match`, or there is none.
+    VersionMismatch {
+        expected: String,
+        actual: Option<String>,
+    },
+    /// `429`, `rate_limited`: the client made too many changes, with `Retry-After` set to when
+    /// it may make the next one.
+    RateLimited(Duration),
+    /// `413 payload_too_large` for events over the payload limit, `503 queue_full` (with
+    /// `Retry-After`) while the producer queue is full, `503 insufficient_replicas` while the
+    /// cluster is short of in-sync replicas, `500 publish_failed` otherwise.
+    Publish(PublishError),
+}
+
+/// Body of every error response: `{ "error": { "code": ..., "message": ... } }`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Stable, machine-readable name of the error, e.g. `invalid_product`.
+    #[schema(example = "invalid_product")]
+    pub code: &'static str,
+    /// Human-readable description, not meant to be parsed.
+    pub message: String,
+    /// The invalid fields, for `invalid_product` only.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidProduct(_) => "invalid_product",
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::IdMismatch { .. } => "id_mismatch",
+            ApiError::VersionMismatch { .. } => "version_mismatch",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::Publish(PublishError::PayloadTooLarge { .. }) => "payload_too_large",
+            ApiError::Publish(err) if err.is_queue_full() => "queue_full",
+            ApiError::Publish(PublishError::InsufficientReplicas(_)) => "insufficient_replicas",
+            ApiError::Publish(_) => "publish_failed",
+        }
+    }
+
+    /// How long the client should wait before trying again, sent as `Retry-After`.
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::RateLimited(retry_after) => Some(*retry_after),
+            ApiError::Publish(err) if err.is_queue_full() => Some(QUEUE_FULL_RETRY_AFTER),
+            _ => None,
+        }
+    }
+
+    /// The `error` object of the response body.
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            fields: match self {
+                ApiError::InvalidProduct(err) => err.errors.clone(),
+                _ => vec![],
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidProduct(err) => write!(f, "{}", err),
+            ApiError::InvalidBody(message) | ApiError::NotFound(message) => {
+                write!(f, "{}", message)
+            }
+            ApiError::IdMismatch { body_id, path_id } => {
+                write!(f, "body id {} does not match path id {}", body_id, path_id)
+            }
+            ApiError::VersionMismatch {
+                expected,
+                actual: Some(actual),
+            } => write!(f, "product is at version {}, not {}", actual, expected),
+            ApiError::VersionMismatch {
+                expected,
+                actual: None,
+            } => write!(
+                f,
+                "no version is known for the product, expected {}",
+                expected
+            ),
+            ApiError::RateLimited(_) => write!(f, "too many requests, slow down"),
+            ApiError::Publish(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidProduct(_) | ApiError::InvalidBody(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::IdMismatch { .. } => StatusCode::CONFLICT,
+            ApiError::VersionMismatch { .. } => StatusCode::PRECONDITION_FAILED,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Publish(PublishError::PayloadTooLarge { .. }) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            ApiError::Publish(err) if err.is_queue_full() => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Publish(PublishError::InsufficientReplicas(_)) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::Publish(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A full queue or a rate limit comes with `Retry-After`, in whole seconds rounded up, so
+    /// clients back off instead of adding to the load.
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.retry_after() {
+            let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            response.insert_header((header::RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(ErrorResponse { error: self.body() })
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(err: ValidationError) -> Self {
+        ApiError::InvalidProduct(err)
+    }
+}
+
+impl From<PublishError> for ApiError {
+    fn from(err: PublishError) -> Self {
+        ApiError::Publish(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiError;
+    use crate::errors::PublishError;
+    use actix_web::body::to_bytes;
+    use actix_web::http::header::{self, HeaderValue};
+    use actix_web::ResponseError;
+    use expectest::prelude::*;
+    use product_events::{FieldError, ValidationError};
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    async fn body(err: &ApiError) -> Value {
+        let bytes = to_bytes(err.error_response().into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn invalid_products_list_their_fields() {
+        let err = ApiError::from(ValidationError {
+            errors: vec![FieldError {
+                field: "name",
+                message: "must not be empty",
+            }],
+        });
+        expect!(err.status_code().as_u16()).to(be_equal_to(400));
+        expect!(body(&err).await).to(be_equal_to(json!({
+            "error": {
+                "code": "invalid_product",
+                "message": "invalid fields: name",
+                "fields": [{ "field": "name", "message": "must not be empty" }]
+            }
+        })));
+    }
+
+    #[actix_web::test]
+    async fn id_mismatches_are_conflicts() {
+        let err = ApiError::IdMismatch {
+            body_id: "99".to_string(),
+            path_id: "42".to_string(),
+        };
+        expect!(err.status_code().as_u16()).to(be_equal_to(409));
+        expect!(body(&err).await).to(be_equal_to(json!({
+            "error": {
+                "code": "id_mismatch",
+                "message": "body id 99 does not match path id 42"
+            }
+        })));
+    }
+
+    #[actix_web::test]
+    async fn publish_failures_map_to_their_status() {
+        let too_large = ApiError::from(PublishError::PayloadTooLarge {
+            size: 2048,
+            limit: 1024,
+        });
+        expect!(too_large.status_code().as_u16()).to(be_equal_to(413));
+        expect!(body(&too_large).await["error"]["code"].as_str())
+            .to(be_some().value("payload_too_large"));
+
+        let queue_full = ApiError::from(PublishError::Delivery(KafkaError::MessageProduction(
+            RDKafkaErrorCode::QueueFull,
+        )));
+        let response = queue_full.error_response();
+        expect!(response.status().as_u16()).to(be_equal_to(503));
+        expect!(response.headers().contains_key(header::RETRY_AFTER)).to(be_true());
+
+        let failed = ApiError::from(PublishError::Delivery(KafkaError::MessageProduction(
+            RDKafkaErrorCode::MessageTimedOut,
+        )));
+        expect!(failed.status_code().as_u16()).to(be_equal_to(500));
+        expect!(failed.code()).to(be_equal_to("publish_failed"));
+    }
+
+    #[actix_web::test]
+    async fn rate_limited_requests_are_told_when_to_retry() {
+        let err = ApiError::RateLimited(Duration::from_millis(1500));
+        let response = err.error_response();
+        expect!(response.status().as_u16()).to(be_equal_to(429));
+        expect!(response.headers().get(header::RETRY_AFTER))
+            .to(be_some().value(HeaderValue::from_static("2")));
+        expect!(body(&err).await["error"]["code"].as_str()).to(be_some().value("rate_limited"));
+    }
+}
@@ -0,0 +1,158 @@
+use chrono::{DateTime, Utc};
+use product_events::ProductEvent;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// One line of the audit log: an event that was published.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// When the broker acknowledged the event or, unless `acknowledged`, when it was enqueued.
+    pub timestamp: DateTime<Utc>,
+    /// Whether the broker acknowledged the event. With `KAFKA_DELIVERY_MODE=fire-and-forget`
+    /// events are recorded once enqueued, and may still fail to be delivered.
+    pub acknowledged: bool,
+    pub id: String,
+    pub event: String,
+    /// Topic the event was published to, unknown for publishers without topics.
+    pub topic: Option<String>,
+    pub version: String,
+}
+
+impl AuditRecord {
+    /// Records `event` as published to `topic` just now, `acknowledged` by the broker or only
+    /// enqueued.
+    pub fn published(event: &ProductEvent, topic: Option<&str>, acknowledged: bool) -> Self {
+        AuditRecord {
+            timestamp: SystemTime::now().into(),
+            acknowledged,
+            id: event.id.clone(),
+            event: event.event.to_string(),
+            topic: topic.map(str::to_string),
+            version: event.version.clone(),
+        }
+    }
+}
+
+/// Keeps a durable record of every published event, one JSON line per event.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, record: &AuditRecord) -> io::Result<()>;
+}
+
+/// Writes the audit log to stdout. The tracing output goes to stderr, so stdout carries
+/// nothing but audit lines and can be read as JSON lines.
+pub struct StdoutAuditSink;
+
+impl AuditSink for StdoutAuditSink {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        let line = serde_json::to_string(record)?;
+        writeln!(io::stdout().lock(), "{}", line)
+    }
+}
+
+/// Appends the audit log to a file, creating it if needed.
+pub struct FileAuditSink {
+    file: Mutex<File>,
+}
+
+impl FileAuditSink {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileAuditSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        // one write per line, so concurrent records never interleave
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.lock().unwrap().write_all(&line)
+    }
+}
+
+/// Where the audit log goes (`AUDIT_SINK`: `stdout` or `file:<path>`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+impl AuditTarget {
+    /// Parses `stdout` or `file:<path>`, `None` for anything else.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':') {
+            Some(("file", path)) if !path.trim().is_empty() => {
+                Some(AuditTarget::File(PathBuf::from(path)))
+            }
+            None if value.eq_ignore_ascii_case("stdout") => Some(AuditTarget::Stdout),
+            _ => None,
+        }
+    }
+
+    pub fn open(&self) -> io::Result<Box<dyn AuditSink>> {
+        Ok(match self {
+            AuditTarget::Stdout => Box::new(StdoutAuditSink),
+            AuditTarget::File(path) => Box::new(FileAuditSink::open(path)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditRecord, AuditSink, AuditTarget, FileAuditSink};
+    use expectest::prelude::*;
+    use product_events::{create_event, EventKind, Product, ProductType};
+    use std::path::PathBuf;
+
+    #[test]
+    fn parses_the_audit_target() {
+        expect!(AuditTarget::parse("stdout")).to(be_some().value(AuditTarget::Stdout));
+        expect!(AuditTarget::parse("file:/var/log/audit.jsonl"))
+            .to(be_some().value(AuditTarget::File(PathBuf::from("/var/log/audit.jsonl"))));
+        expect!(AuditTarget::parse("file:")).to(be_none());
+        expect!(AuditTarget::parse("syslog")).to(be_none());
+    }
+
+    #[test]
+    fn file_sink_appends_one_json_line_per_record() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let product = Product {
+            id: Some("42".to_string()),
+            name: "Some Product".to_string(),
+            r#type: ProductType::Range,
+            version: Some("v1".to_string()),
+        };
+        let created = create_event(product.clone(), EventKind::Created);
+        let updated = create_event(product, EventKind::Updated);
+
+        let sink = FileAuditSink::open(&path).unwrap();
+        let records = vec![
+            AuditRecord::published(&created, Some("products"), true),
+            AuditRecord::published(&updated, Some("products"), false),
+        ];
+        for record in &records {
+            sink.record(record).unwrap();
+        }
+        // reopening appends rather than truncating
+        let sink = FileAuditSink::open(&path).unwrap();
+        sink.record(&records[0]).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<AuditRecord> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        expect!(lines).to(be_equal_to(vec![
+            records[0].clone(),
+            records[1].clone(),
+            records[0].clone(),
+        ]));
+    }
+}
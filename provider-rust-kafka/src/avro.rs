@@ -0,0 +1,150 @@
+use apache_avro::writer::datum::GenericDatumWriter;
+use apache_avro::Schema;
+use product_events::ProductEvent;
+use serde::Deserialize;
+use std::fmt;
+
+/// Avro schema for `ProductEvent`, registered with the schema registry at startup.
+const PRODUCT_EVENT_SCHEMA: &str = include_str!("../schemas/product_event.avsc");
+
+/// First byte of every Confluent wire-format message.
+const MAGIC_BYTE: u8 = 0;
+
+#[derive(Debug)]
+pub enum AvroError {
+    Schema(apache_avro::Error),
+    Registry(reqwest::Error),
+}
+
+impl fmt::Display for AvroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AvroError::Schema(err) => write!(f, "avro encoding failed: {}", err),
+            AvroError::Registry(err) => write!(f, "schema registry request failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AvroError {}
+
+impl From<apache_avro::Error> for AvroError {
+    fn from(err: apache_avro::Error) -> Self {
+        AvroError::Schema(err)
+    }
+}
+
+impl From<reqwest::Error> for AvroError {
+    fn from(err: reqwest::Error) -> Self {
+        AvroError::Registry(err)
+    }
+}
+
+#[derive(Deserialize)]
+struct RegisteredSchema {
+    id: u32,
+}
+
+/// Minimal client for the Confluent Schema Registry REST API.
+pub struct SchemaRegistryClient {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl SchemaRegistryClient {
+    pub fn new(base_url: &str) -> Self {
+        SchemaRegistryClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Registers `schema` under `subject`, returning its global schema id. Registering a schema
+    /// the registry already knows is idempotent and returns the existing id.
+    pub async fn register(&self, subject: &str, schema: &Schema) -> Result<u32, AvroError> {
+        let registered: RegisteredSchema = self
+            .http
+            .post(format!("{}/subjects/{}/versions", self.base_url, subject))
+            .header("Content-Type", "application/vnd.schemaregistry.v1+json")
+            .json(&serde_json::json!({ "schema": schema.canonical_form() }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(registered.id)
+    }
+}
+
+/// Encodes product events as Confluent-framed Avro: the magic byte, the big-endian schema id
+/// and then the Avro binary datum.
+pub struct AvroEncoder {
+    schema: Schema,
+    schema_id: u32,
+}
+
+impl AvroEncoder {
+    /// Loads the `ProductEvent` schema and registers it under the `<topic>-value` subject.
+    pub async fn register(
+        registry: &SchemaRegistryClient,
+        topic: &str,
+    ) -> Result<AvroEncoder, AvroError> {
+        let schema = Schema::parse_str(PRODUCT_EVENT_SCHEMA)?;
+        let schema_id = registry
+            .register(&format!("{}-value", topic), &schema)
+            .await?;
+        Ok(AvroEncoder { schema, schema_id })
+    }
+
+    pub fn encode(&self, event: &ProductEvent) -> Result<Vec<u8>, AvroError> {
+        let datum = GenericDatumWriter::builder(&self.schema)
+            .build()?
+            .write_ser_to_vec(event)?;
+
+        let mut payload = Vec::with_capacity(datum.len() + 5);
+        payload.push(MAGIC_BYTE);
+        payload.extend_from_slice(&self.schema_id.to_be_bytes());
+        payload.extend_from_slice(&datum);
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AvroEncoder, PRODUCT_EVENT_SCHEMA};
+    use apache_avro::reader::datum::GenericDatumReader;
+    use apache_avro::{from_value, Schema};
+    use expectest::prelude::*;
+    use product_events::{create_event, EventKind, Product, ProductEvent, ProductType};
+
+    #[test]
+    fn encodes_events_in_the_confluent_wire_format() {
+        let schema = Schema::parse_str(PRODUCT_EVENT_SCHEMA).unwrap();
+        let encoder = AvroEncoder {
+            schema: schema.clone(),
+            schema_id: 42,
+        };
+        let mut event = create_event(
+            Product {
+                id: Some("some-uuid-1234-5678".to_string()),
+                name: "Some Product".to_string(),
+                r#type: ProductType::Range,
+                version: Some("v1".to_string()),
+            },
+            EventKind::Updated,
+        );
+
+        let decode = |payload: &[u8]| {
+            let reader = GenericDatumReader::builder(&schema).build().unwrap();
+            let decoded = reader.read_value(&mut &payload[5..]).unwrap();
+            from_value::<ProductEvent>(&decoded).unwrap()
+        };
+
+        let payload = encoder.encode(&event).unwrap();
+        expect!(&payload[..5]).to(be_equal_to(&[0u8, 0, 0, 0, 42][..]));
+        expect!(decode(&payload)).to(be_equal_to(event.clone()));
+
+        event.occurred_at = Some("2024-05-01T12:00:00Z".parse().unwrap());
+        let payload = encoder.encode(&event).unwrap();
+        expect!(decode(&payload)).to(be_equal_to(event));
+    }
+}
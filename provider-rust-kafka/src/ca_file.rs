@@ -0,0 +1,70 @@
+use crate::config::KafkaSecurity;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// An inline CA certificate (`KAFKA_SSL_CA_PEM`) written to a temporary file, as librdkafka
+/// reads `ssl.ca.location` from disk. The file is removed when this is dropped, on shutdown.
+pub struct CaFile {
+    path: PathBuf,
+}
+
+impl CaFile {
+    /// Writes `security`'s inline certificate to a temporary file and points
+    /// `ssl_ca_location` at it. Returns `None`, changing nothing, without an inline certificate.
+    pub fn materialize(security: &mut KafkaSecurity) -> io::Result<Option<CaFile>> {
+        let Some(pem) = security.ssl_ca_pem.take() else {
+            return Ok(None);
+        };
+        let path = std::env::temp_dir().join(format!("kafka-ca-{}.pem", uuid::Uuid::new_v4()));
+        fs::write(&path, pem)?;
+        security.ssl_ca_location = Some(path.to_string_lossy().into_owned());
+        Ok(Some(CaFile { path }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CaFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %err, "failed to remove the CA file");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaFile;
+    use crate::config::KafkaSecurity;
+    use expectest::prelude::*;
+    use rdkafka::config::ClientConfig;
+
+    const PEM: &str = "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n";
+
+    #[test]
+    fn writes_an_inline_certificate_to_a_file_for_librdkafka() {
+        let mut security = KafkaSecurity {
+            protocol: "SSL".to_string(),
+            sasl_mechanism: None,
+            sasl_username: None,
+            sasl_password: None,
+            ssl_ca_location: None,
+            ssl_ca_pem: Some(PEM.to_string()),
+        };
+
+        let ca_file = CaFile::materialize(&mut security).unwrap().unwrap();
+        let path = ca_file.path().to_path_buf();
+        expect!(std::fs::read_to_string(&path).unwrap()).to(be_equal_to(PEM));
+        let mut config = ClientConfig::new();
+        security.apply(&mut config);
+        expect!(config.get("ssl.ca.location")).to(be_some().value(path.to_str().unwrap()));
+
+        drop(ca_file);
+        expect!(path.exists()).to(be_false());
+        expect!(CaFile::materialize(&mut security).unwrap().is_none()).to(be_true());
+    }
+}
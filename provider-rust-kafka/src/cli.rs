@@ -0,0 +1,181 @@
+use crate::verify::DEFAULT_PACT_FILE;
+use clap::{Args, Parser, Subcommand};
+use std::collections::HashMap;
+
+/// Publishes product events to Kafka, and verifies them against the consumer pacts.
+///
+/// Every flag overrides the environment variable of the same setting, anything not given
+/// on the command line is still read from the environment.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Runs `serve` when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Runs the HTTP API, publishing an event to Kafka for every product change.
+    Serve(ServeArgs),
+    /// Verifies the product events against the consumer pacts.
+    Verify(VerifyArgs),
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ServeArgs {
+    /// Kafka bootstrap servers (`KAFKA_BROKER`).
+    #[arg(long)]
+    pub broker: Option<String>,
+    /// Topic product events are published to (`KAFKA_TOPIC`).
+    #[arg(long)]
+    pub topic: Option<String>,
+    /// Address the HTTP API listens on (`HTTP_BIND_ADDR`).
+    #[arg(long)]
+    pub bind: Option<String>,
+    /// Checks a round trip through Kafka before serving (`SELFTEST`).
+    #[arg(long)]
+    pub selftest: bool,
+}
+
+impl ServeArgs {
+    /// The given flags, keyed by the environment variable they override.
+    pub fn overrides(&self) -> HashMap<&'static str, String> {
+        let selftest = self.selftest.then(|| "true".to_string());
+        overrides([
+            ("KAFKA_BROKER", &self.broker),
+            ("KAFKA_TOPIC", &self.topic),
+            ("HTTP_BIND_ADDR", &self.bind),
+            ("SELFTEST", &selftest),
+        ])
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Pact file to verify when no Pact Broker is given.
+    #[arg(long, default_value = DEFAULT_PACT_FILE)]
+    pub pact_file: String,
+    /// Directory whose `*.json` pact files are all verified, instead of `--pact-file`
+    /// (`PACT_DIR`).
+    #[arg(long)]
+    pub pact_dir: Option<String>,
+    /// Pact Broker to fetch the pacts from (`PACT_BROKER_URL`).
+    #[arg(long)]
+    pub broker_url: Option<String>,
+    /// Token to authenticate with the Pact Broker (`PACT_BROKER_TOKEN`).
+    #[arg(long)]
+    pub broker_token: Option<String>,
+    /// Provider version to publish the results as (`PROVIDER_VERSION`).
+    #[arg(long)]
+    pub provider_version: Option<String>,
+    /// Only verifies interactions whose description matches this regex
+    /// (`PACT_FILTER_DESCRIPTION`).
+    #[arg(long)]
+    pub filter_description: Option<String>,
+    /// File to write a JUnit report of the verification to (`PACT_JUNIT_OUTPUT`).
+    #[arg(long)]
+    pub junit_output: Option<String>,
+}
+
+impl VerifyArgs {
+    /// The given flags, keyed by the environment variable they override.
+    pub fn overrides(&self) -> HashMap<&'static str, String> {
+        overrides([
+            ("PACT_DIR", &self.pact_dir),
+            ("PACT_BROKER_URL", &self.broker_url),
+            ("PACT_BROKER_TOKEN", &self.broker_token),
+            ("PROVIDER_VERSION", &self.provider_version),
+            ("PACT_FILTER_DESCRIPTION", &self.filter_description),
+            ("PACT_JUNIT_OUTPUT", &self.junit_output),
+        ])
+    }
+}
+
+fn overrides<const N: usize>(
+    flags: [(&'static str, &Option<String>); N],
+) -> HashMap<&'static str, String> {
+    flags
+        .into_iter()
+        .filter_map(|(name, value)| value.clone().map(|value| (name, value)))
+        .collect()
+}
+
+/// Looks variables up in `overrides` first, then in `fallback` (usually the environment).
+pub fn lookup_with<'a>(
+    overrides: &'a HashMap<&'static str, String>,
+    fallback: impl Fn(&str) -> Option<String> + 'a,
+) -> impl Fn(&str) -> Option<String> + 'a {
+    move |name| overrides.get(name).cloned().or_else(|| fallback(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lookup_with, Cli, Command};
+    use crate::verify::DEFAULT_PACT_FILE;
+    use clap::Parser;
+    use expectest::prelude::*;
+    use maplit::hashmap;
+
+    #[test]
+    fn serves_by_default() {
+        let cli = Cli::try_parse_from(["provider-rust-kafka"]).unwrap();
+        expect!(cli.command.is_none()).to(be_true());
+    }
+
+    #[test]
+    fn serve_flags_override_the_environment() {
+        let cli = Cli::try_parse_from([
+            "provider-rust-kafka",
+            "serve",
+            "--broker",
+            "kafka:9092",
+            "--bind",
+            "0.0.0.0:9000",
+            "--selftest",
+        ])
+        .unwrap();
+        let Some(Command::Serve(args)) = cli.command else {
+            panic!("expected the serve command");
+        };
+        let overrides = args.overrides();
+        expect!(&overrides).to(be_equal_to(&hashmap! {
+            "KAFKA_BROKER" => "kafka:9092".to_string(),
+            "HTTP_BIND_ADDR" => "0.0.0.0:9000".to_string(),
+            "SELFTEST" => "true".to_string(),
+        }));
+
+        let lookup = lookup_with(&overrides, |name| {
+            Some(format!("{} from the environment", name))
+        });
+        expect!(lookup("KAFKA_BROKER")).to(be_some().value("kafka:9092"));
+        expect!(lookup("KAFKA_TOPIC")).to(be_some().value("KAFKA_TOPIC from the environment"));
+    }
+
+    #[test]
+    fn verify_flags_select_the_pact_source() {
+        let cli = Cli::try_parse_from(["provider-rust-kafka", "verify"]).unwrap();
+        let Some(Command::Verify(args)) = cli.command else {
+            panic!("expected the verify command");
+        };
+        expect!(args.pact_file.as_str()).to(be_equal_to(DEFAULT_PACT_FILE));
+        expect!(args.overrides().is_empty()).to(be_true());
+
+        let cli = Cli::try_parse_from([
+            "provider-rust-kafka",
+            "verify",
+            "--broker-url",
+            "https://broker.example.com",
+            "--broker-token",
+            "secret",
+        ])
+        .unwrap();
+        let Some(Command::Verify(args)) = cli.command else {
+            panic!("expected the verify command");
+        };
+        expect!(args.overrides()).to(be_equal_to(hashmap! {
+            "PACT_BROKER_URL" => "https://broker.example.com".to_string(),
+            "PACT_BROKER_TOKEN" => "secret".to_string(),
+        }));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::ConfigError;
+use product_events::ProductEvent;
+use rdkafka::message::{Header, OwnedHeaders};
+use serde_json::json;
+
+const SPEC_VERSION: &str = "1.0";
+const DEFAULT_SOURCE: &str = "/pactflow-example-provider-rust-kafka";
+/// Prefix of the CloudEvents `type`, followed by the lower-cased event type.
+const TYPE_PREFIX: &str = "com.example.product";
+
+/// How the CloudEvent is laid out on the Kafka record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CloudEventsMode {
+    /// The whole envelope, with the `ProductEvent` as `data`, is the JSON payload.
+    #[default]
+    Structured,
+    /// The payload is the plain encoded `ProductEvent` and the attributes are `ce_` headers.
+    Binary,
+}
+
+/// Wraps published events as CloudEvents 1.0:
+///
+/// * `CLOUDEVENTS_SOURCE` - the `source` attribute, defaults to the provider name
+/// * `CLOUDEVENTS_MODE` - `structured` (default) or `binary`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloudEventsOptions {
+    pub source: String,
+    pub mode: CloudEventsMode,
+}
+
+impl Default for CloudEventsOptions {
+    fn default() -> Self {
+        CloudEventsOptions {
+            source: DEFAULT_SOURCE.to_string(),
+            mode: CloudEventsMode::default(),
+        }
+    }
+}
+
+impl CloudEventsOptions {
+    pub fn from_lookup(lookup: &impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mode = match lookup("CLOUDEVENTS_MODE") {
+            None => CloudEventsMode::default(),
+            Some(mode) => match mode.to_ascii_lowercase().as_str() {
+                "structured" => CloudEventsMode::Structured,
+                "binary" => CloudEventsMode::Binary,
+                _ => {
+                    return Err(ConfigError::Invalid {
+                        name: "CLOUDEVENTS_MODE",
+                        value: mode,
+                        reason: "expected structured or binary",
+                    })
+                }
+            },
+        };
+        Ok(CloudEventsOptions {
+            source: lookup("CLOUDEVENTS_SOURCE").unwrap_or_else(|| DEFAULT_SOURCE.to_string()),
+            mode,
+        })
+    }
+
+    /// The structured-mode JSON envelope, with the `ProductEvent` as `data`.
+    pub fn envelope(&self, event: &ProductEvent) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(&json!({
+            "specversion": SPEC_VERSION,
+            "type": event_type(event),
+            "source": self.source,
+            "id": event_id(event),
+            "subject": event.id,
+            "datacontenttype": "application/json",
+            "data": event,
+        }))
+    }
+
+    /// Adds the `ce_` attribute headers in binary mode. The `content-type` header comes from
+    /// the payload's serializer, like for every record.
+    pub fn headers(&self, event: &ProductEvent, headers: OwnedHeaders) -> OwnedHeaders {
+        let header = |headers: OwnedHeaders, key: &str, value: &str| {
+            headers.insert(Header {
+                key,
+                value: Some(value),
+            })
+        };
+        match self.mode {
+            CloudEventsMode::Structured => headers,
+            CloudEventsMode::Binary => {
+                let headers = header(headers, "ce_specversion", SPEC_VERSION);
+                let headers = header(headers, "ce_type", &event_type(event));
+                let headers = header(headers, "ce_source", &self.source);
+                let headers = header(headers, "ce_id", &event_id(event));
+                header(headers, "ce_subject", &event.id)
+            }
+        }
+    }
+}
+
+fn event_type(event: &ProductEvent) -> String {
+    format!(
+        "{}.{}",
+        TYPE_PREFIX,
+        event.event.as_str().to_ascii_lowercase()
+    )
+}
+
+/// Product id plus version identifies one change to a product, and stays the same when a
+/// publish is retried, so consumers can de-duplicate on it.
+fn event_id(event: &ProductEvent) -> String {
+    format!("{}-{}", event.id, event.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CloudEventsMode, CloudEventsOptions};
+    use expectest::prelude::*;
+    use product_events::{create_event, EventKind, Product, ProductType};
+    use rdkafka::message::{Headers, OwnedHeaders};
+    use serde_json::{json, Value};
+
+    fn event() -> product_events::ProductEvent {
+        create_event(
+            Product {
+                id: Some("some-uuid-1234-5678".to_string()),
+                name: "Some Product".to_string(),
+                r#type: ProductType::Range,
+                version: Some("v1".to_string()),
+            },
+            EventKind::Updated,
+        )
+    }
+
+    #[test]
+    fn wraps_events_in_a_structured_envelope() {
+        let options = CloudEventsOptions {
+            source: "/products".to_string(),
+            mode: CloudEventsMode::Structured,
+        };
+        let envelope: Value = serde_json::from_slice(&options.envelope(&event()).unwrap()).unwrap();
+
+        expect!(envelope).to(be_equal_to(json!({
+            "specversion": "1.0",
+            "type": "com.example.product.updated",
+            "source": "/products",
+            "id": "some-uuid-1234-5678-v2",
+            "subject": "some-uuid-1234-5678",
+            "datacontenttype": "application/json",
+            "data": serde_json::to_value(event()).unwrap(),
+        })));
+    }
+
+    #[test]
+    fn binary_mode_moves_attributes_to_headers() {
+        let options = CloudEventsOptions {
+            mode: CloudEventsMode::Binary,
+            ..CloudEventsOptions::from_lookup(&|_| None).unwrap()
+        };
+        let headers = options.headers(&event(), OwnedHeaders::new());
+        let header = |key: &str| {
+            headers
+                .iter()
+                .find(|header| header.key == key)
+                .and_then(|header| header.value)
+                .map(|value| String::from_utf8_lossy(value).to_string())
+        };
+        expect!(header("ce_specversion")).to(be_some().value("1.0"));
+        expect!(header("ce_type")).to(be_some().value("com.example.product.updated"));
+        expect!(header("ce_source")).to(be_some().value("/pactflow-example-provider-rust-kafka"));
+        expect!(header("ce_id")).to(be_some().value("some-uuid-1234-5678-v2"));
+    }
+}
@@ -0,0 +1,1668 @@
+use crate::audit::AuditTarget;
+use crate::retry::RetryPolicy;
+use crate::routing::TopicRouter;
+use product_events::{EventKind, IdScheme, JsonCase, VersionScheme};
+use rdkafka::config::ClientConfig;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+const DEFAULT_BROKER: &str = "localhost:9092";
+const DEFAULT_TOPIC: &str = "products";
+const DEFAULT_HTTP_BIND_ADDR: &str = "127.0.0.1:8081";
+const DEFAULT_MAX_PAYLOAD_BYTES: usize = 1_000_000;
+const DEFAULT_HTTP_MAX_BODY_BYTES: usize = 1_048_576;
+const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_CLIENT_ID: &str = "product-event-service";
+/// Separates a `KAFKA_TOPIC_PREFIX` namespace from the topic name, as in `prod.products`.
+const TOPIC_PREFIX_SEPARATOR: char = '.';
+
+/// Runtime configuration for the provider, read from the environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Kafka bootstrap servers (`KAFKA_BROKER`).
+    pub broker: String,
+    /// Topic product events are published to (`KAFKA_TOPIC`). Like the routed and dead letter
+    /// topics, it is namespaced by `KAFKA_TOPIC_PREFIX` if set, e.g. `prod.products`.
+    pub topic: String,
+    /// Routes event types to their own topics (`KAFKA_TOPIC_ROUTES`, e.g.
+    /// `DELETED=product-tombstones`), falling back to `topic`.
+    pub topics: TopicRouter,
+    /// Address the HTTP API listens on (`HTTP_BIND_ADDR`).
+    pub http_bind_addr: String,
+    /// Number of HTTP worker threads (`HTTP_WORKERS`), or actix's default of one per core.
+    pub http_workers: Option<usize>,
+    /// Largest JSON request body the HTTP API accepts (`HTTP_MAX_BODY_BYTES`), default 1 MiB.
+    pub http_max_body_bytes: usize,
+    /// Changes each client may make per second (`HTTP_RATE_LIMIT_PER_SEC`), unlimited by
+    /// default, see [`crate::rate_limit::RateLimiter`].
+    pub http_rate_limit_per_sec: Option<u32>,
+    /// Topic for events that still fail to publish after retries (`KAFKA_DLQ_TOPIC`).
+    pub dlq_topic: Option<String>,
+    /// How often creating the producer is retried at startup before giving up
+    /// (`KAFKA_STARTUP_RETRIES`), default 0.
+    pub startup_retries: u32,
+    /// Logs events instead of publishing them, without connecting to Kafka (`DRY_RUN=true`).
+    pub dry_run: bool,
+    /// Publishes a synthetic event to a temporary topic and reads it back before serving
+    /// (`SELFTEST=true`), see [`crate::selftest`]. Ignored with `DRY_RUN`.
+    pub selftest: bool,
+    /// Serves `GET /debug/config`, see [`EffectiveConfig`] (`DEBUG_ENDPOINTS=true`), off by
+    /// default.
+    pub debug_endpoints: bool,
+    /// Creates every topic in [`Config::all_topics`] at startup unless it already exists
+    /// (`KAFKA_AUTO_CREATE_TOPIC=true`), see [`TopicSettings`].
+    pub auto_create_topic: Option<TopicSettings>,
+    /// How event versions are written (`VERSION_SCHEME`: `prefixed-int`, the default,
+    /// `plain-int` or `semver`).
+    pub version_scheme: VersionScheme,
+    /// How ids are generated for products created without one (`ID_SCHEME`: `uuid-v4`, the
+    /// default, or the time-ordered `uuid-v7`).
+    pub id_scheme: IdScheme,
+    /// How the keys of JSON payloads are named (`EVENT_JSON_CASE`: `snake`, the default, or
+    /// `camel`). This changes the contract, see [`JsonCase`].
+    pub event_json_case: JsonCase,
+    /// Indents JSON payloads over several lines, for reading them in a console consumer
+    /// (`PRETTY_JSON=true`). HTTP responses are unaffected.
+    pub pretty_json: bool,
+    /// Records every published event (`AUDIT_SINK`: `stdout` or `file:<path>`, appending JSON
+    /// lines), off by default.
+    pub audit_sink: Option<AuditTarget>,
+    /// Gets a JSON POST for every event that still fails to publish after retries
+    /// (`FAILURE_WEBHOOK_URL`), e.g. to page someone about the lost change.
+    pub failure_webhook_url: Option<String>,
+    /// Producer tuning, see [`ProducerOptions`].
+    pub producer: ProducerOptions,
+    /// When set, events are published as Confluent-framed Avro (`SCHEMA_REGISTRY_URL`).
+    #[cfg(feature = "avro")]
+    pub schema_registry_url: Option<String>,
+    /// Envelope settings, see [`crate::cloudevents::CloudEventsOptions`].
+    #[cfg(feature = "cloudevents")]
+    pub cloudevents: crate::cloudevents::CloudEventsOptions,
+}
+
+/// Tuning options applied to the Kafka producer. The defaults match the workshop setup: a
+/// plain producer with librdkafka's default acknowledgements.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProducerOptions {
+    /// Turns on `enable.idempotence` and `acks=all` (`KAFKA_ENABLE_IDEMPOTENCE=true`). The
+    /// broker then de-duplicates retried sends and preserves per-partition ordering, at the
+    /// cost of waiting for every in-sync replica to acknowledge each write. Requires a cluster
+    /// that supports idempotent producers. Without it, a send retried after a connection blip
+    /// may write the same event twice.
+    pub idempotent: bool,
+    /// How many replicas must acknowledge each write (`KAFKA_ACKS`), see [`Acks`].
+    pub acks: Option<Acks>,
+    /// Names the producer to the broker (`KAFKA_CLIENT_ID`), so broker-side metrics, logs and
+    /// quotas can attribute its traffic. Defaults to `product-event-service`, followed by the
+    /// host name when `HOSTNAME` is set, which tells replicas apart.
+    pub client_id: String,
+    /// How transient delivery failures are retried before a publish gives up.
+    pub retry: RetryPolicy,
+    /// Only set when `KAFKA_SECURITY_PROTOCOL` is, see [`KafkaSecurity`].
+    pub security: Option<KafkaSecurity>,
+    /// Picks the partition of each record (`KAFKA_PARTITION_STRATEGY`), see
+    /// [`PartitionStrategy`].
+    pub partitioning: PartitionStrategy,
+    /// Follows every DELETED event with a null-payload record keyed by the product id
+    /// (`KAFKA_TOMBSTONE_ON_DELETE=true`), so a log-compacted topic eventually drops the
+    /// product entirely.
+    pub tombstone_on_delete: bool,
+    /// The codec records are compressed with (`KAFKA_COMPRESSION`), see [`Compression`].
+    pub compression: Compression,
+    /// Trades publish latency for throughput under bursts of writes, see [`Batching`].
+    pub batching: Batching,
+    /// Larger encoded events are rejected before they are sent (`KAFKA_MAX_PAYLOAD_BYTES`).
+    /// Defaults to librdkafka's `message.max.bytes` of 1000000, and should stay below the
+    /// broker's own limit so oversized events fail fast instead of at the broker.
+    pub max_payload_bytes: usize,
+    /// How long a single publish waits for the broker to accept and acknowledge a record
+    /// (`KAFKA_SEND_TIMEOUT_MS`, default 30s), so a wedged broker fails requests instead of
+    /// stalling them. librdkafka gives up on the record at the same time.
+    pub send_timeout: Duration,
+    /// Whether a publish waits for the broker at all (`KAFKA_DELIVERY_MODE`), see
+    /// [`DeliveryMode`].
+    pub delivery_mode: DeliveryMode,
+}
+
+impl Default for ProducerOptions {
+    fn default() -> Self {
+        ProducerOptions {
+            idempotent: false,
+            acks: None,
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            retry: RetryPolicy::default(),
+            security: None,
+            partitioning: PartitionStrategy::default(),
+            tombstone_on_delete: false,
+            compression: Compression::default(),
+            batching: Batching::default(),
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+            delivery_mode: DeliveryMode::default(),
+        }
+    }
+}
+
+impl ProducerOptions {
+    /// Applies these options on top of an existing client configuration.
+    pub fn apply(&self, config: &mut ClientConfig) {
+        config.set("client.id", &self.client_id);
+        if self.idempotent {
+            config.set("enable.idempotence", "true").set("acks", "all");
+        }
+        if let Some(acks) = self.acks {
+            config.set("acks", acks.as_str());
+        }
+        if let Some(security) = &self.security {
+            security.apply(config);
+        }
+        if self.partitioning == PartitionStrategy::RoundRobin {
+            // records are keyed for compaction, so the partition is picked ignoring the key
+            config.set("partitioner", "random");
+        }
+        config.set("compression.type", self.compression.as_str());
+        // librdkafka gives up on a record when the publish does, so a publish that timed out
+        // is never delivered later, behind the retry or the next version of the product
+        config.set(
+            "message.timeout.ms",
+            self.send_timeout.as_millis().to_string(),
+        );
+        self.batching.apply(config);
+    }
+}
+
+/// Layout of the topic created by `KAFKA_AUTO_CREATE_TOPIC`:
+///
+/// * `partitions` (`KAFKA_TOPIC_PARTITIONS`) - default 1.
+/// * `replication_factor` (`KAFKA_TOPIC_REPLICATION_FACTOR`) - default 1, which suits the
+///   single-broker workshop cluster but loses data if that broker does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicSettings {
+    pub partitions: i32,
+    pub replication_factor: i32,
+}
+
+impl Default for TopicSettings {
+    fn default() -> Self {
+        TopicSettings {
+            partitions: 1,
+            replication_factor: 1,
+        }
+    }
+}
+
+impl TopicSettings {
+    fn from_lookup(lookup: &impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let setting = |name: &'static str, default: i32| match lookup(name) {
+            Some(value) => match value.trim().parse::<i32>() {
+                Ok(number) if number > 0 => Ok(number),
+                _ => Err(ConfigError::Invalid {
+                    name,
+                    value,
+                    reason: "expected a positive integer",
+                }),
+            },
+            None => Ok(default),
+        };
+        let defaults = TopicSettings::default();
+        Ok(TopicSettings {
+            partitions: setting("KAFKA_TOPIC_PARTITIONS", defaults.partitions)?,
+            replication_factor: setting(
+                "KAFKA_TOPIC_REPLICATION_FACTOR",
+                defaults.replication_factor,
+            )?,
+        })
+    }
+}
+
+/// How long the producer waits to fill a batch, and how large batches may get. Anything left
+/// unset keeps the librdkafka default:
+///
+/// * `linger_ms` (`KAFKA_LINGER_MS`) - `linger.ms`, default 5. Raising it lets a burst of
+///   publishes share one request, but delays every publish by up to that long.
+/// * `batch_size` (`KAFKA_BATCH_SIZE`) - `batch.size` in bytes, default 1000000.
+/// * `batch_num_messages` (`KAFKA_BATCH_NUM_MESSAGES`) - `batch.num.messages`, default 10000.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batching {
+    pub linger_ms: Option<u32>,
+    pub batch_size: Option<u32>,
+    pub batch_num_messages: Option<u32>,
+}
+
+impl Batching {
+    fn from_lookup(lookup: &impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let setting = |name: &'static str, min: u32| {
+            lookup(name)
+                .map(|value| match value.trim().parse::<u32>() {
+                    Ok(number) if number >= min => Ok(number),
+                    _ => Err(ConfigError::Invalid {
+                        name,
+                        value,
+                        reason: if min == 0 {
+                            "expected a non-negative integer"
+                        } else {
+                            "expected a positive integer"
+                        },
+                    }),
+                })
+                .transpose()
+        };
+        Ok(Batching {
+            linger_ms: setting("KAFKA_LINGER_MS", 0)?,
+            batch_size: setting("KAFKA_BATCH_SIZE", 1)?,
+            batch_num_messages: setting("KAFKA_BATCH_NUM_MESSAGES", 1)?,
+        })
+    }
+
+    fn apply(&self, config: &mut ClientConfig) {
+        let settings = [
+            ("linger.ms", self.linger_ms),
+            ("batch.size", self.batch_size),
+            ("batch.num.messages", self.batch_num_messages),
+        ];
+        for (key, value) in settings {
+            if let Some(value) = value {
+                config.set(key, value.to_string());
+            }
+        }
+    }
+}
+
+/// Whether publishing an event waits for the broker to acknowledge it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// Wait for the acknowledgement, so a change only succeeds once its event is stored
+    /// (`await-delivery`, the default).
+    #[default]
+    AwaitDelivery,
+    /// Only enqueue the event in the producer and return, at most once: failed deliveries are
+    /// logged but not retried, dead-lettered or reported to the client
+    /// (`fire-and-forget`). For high-volume changes that can tolerate losing some events.
+    FireAndForget,
+}
+
+impl DeliveryMode {
+    const ALL: [DeliveryMode; 2] = [DeliveryMode::AwaitDelivery, DeliveryMode::FireAndForget];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryMode::AwaitDelivery => "await-delivery",
+            DeliveryMode::FireAndForget => "fire-and-forget",
+        }
+    }
+
+    fn parse(value: String) -> Result<Self, ConfigError> {
+        DeliveryMode::ALL
+            .into_iter()
+            .find(|mode| value.eq_ignore_ascii_case(mode.as_str()))
+            .ok_or(ConfigError::Invalid {
+                name: "KAFKA_DELIVERY_MODE",
+                value,
+                reason: "expected await-delivery or fire-and-forget",
+            })
+    }
+}
+
+/// Compression codec for produced records. JSON events compress well, so `gzip`, `lz4` or
+/// `zstd` noticeably cut broker storage at the cost of some producer CPU. `zstd` needs
+/// librdkafka built with zstd support.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// No compression (`none`, the default).
+    #[default]
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    const ALL: [Compression; 5] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Snappy,
+        Compression::Lz4,
+        Compression::Zstd,
+    ];
+
+    /// The librdkafka `compression.type` value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Snappy => "snappy",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    fn parse(value: String) -> Result<Self, ConfigError> {
+        Compression::ALL
+            .into_iter()
+            .find(|codec| value.eq_ignore_ascii_case(codec.as_str()))
+            .ok_or(ConfigError::Invalid {
+                name: "KAFKA_COMPRESSION",
+                value,
+                reason: "expected none, gzip, snappy, lz4 or zstd",
+            })
+    }
+}
+
+/// How many replicas must acknowledge a record before the broker confirms it (`acks`).
+/// Unset, librdkafka's default applies.
+///
+/// Only `All` survives losing the partition leader. With it, the broker refuses writes while
+/// fewer replicas than the topic's `min.insync.replicas` are in sync, which publishes report
+/// as [`PublishError::InsufficientReplicas`](crate::errors::PublishError::InsufficientReplicas).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acks {
+    /// Fire and forget (`0`): nothing is acknowledged, so failed writes go unnoticed.
+    None,
+    /// The partition leader alone (`1`).
+    Leader,
+    /// Every in-sync replica (`all`).
+    All,
+}
+
+impl Acks {
+    const ALL: [Acks; 3] = [Acks::None, Acks::Leader, Acks::All];
+
+    /// The librdkafka `acks` value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Acks::None => "0",
+            Acks::Leader => "1",
+            Acks::All => "all",
+        }
+    }
+
+    fn parse(value: String) -> Result<Self, ConfigError> {
+        Acks::ALL
+            .into_iter()
+            .find(|acks| value.eq_ignore_ascii_case(acks.as_str()))
+            .ok_or(ConfigError::Invalid {
+                name: "KAFKA_ACKS",
+                value,
+                reason: "expected 0, 1 or all",
+            })
+    }
+}
+
+/// How records are assigned to partitions.
+///
+/// Only `KeyHash` preserves per-product ordering on a multi-partition topic, which consumers
+/// that rebuild product state rely on. `Fixed` keeps ordering too, but only because everything
+/// goes to a single partition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartitionStrategy {
+    /// Key records by product id and let rdkafka hash the key (`key-hash`, the default), so
+    /// every event for a product lands on the same partition.
+    #[default]
+    KeyHash,
+    /// Spread records across partitions regardless of their key (`round-robin`). Events for
+    /// the same product may be consumed out of order.
+    RoundRobin,
+    /// Send every record to one partition (`fixed:<n>`), e.g. while debugging.
+    Fixed(i32),
+}
+
+impl PartitionStrategy {
+    fn parse(value: String) -> Result<Self, ConfigError> {
+        match value.to_ascii_lowercase().as_str() {
+            "key-hash" => return Ok(PartitionStrategy::KeyHash),
+            "round-robin" => return Ok(PartitionStrategy::RoundRobin),
+            _ => {}
+        }
+        match value.strip_prefix("fixed:").map(str::parse::<i32>) {
+            Some(Ok(partition)) if partition >= 0 => Ok(PartitionStrategy::Fixed(partition)),
+            _ => Err(ConfigError::Invalid {
+                name: "KAFKA_PARTITION_STRATEGY",
+                value,
+                reason: "expected key-hash, round-robin or fixed:<partition>",
+            }),
+        }
+    }
+}
+
+const SECURITY_PROTOCOLS: [&str; 4] = ["PLAINTEXT", "SSL", "SASL_PLAINTEXT", "SASL_SSL"];
+
+/// TLS and SASL settings for brokers that require authentication, e.g. `SASL_SSL` with
+/// `SCRAM-SHA-512`:
+///
+/// * `KAFKA_SECURITY_PROTOCOL` - `PLAINTEXT`, `SSL`, `SASL_PLAINTEXT` or `SASL_SSL`
+/// * `KAFKA_SASL_MECHANISM` - e.g. `PLAIN`, `SCRAM-SHA-256` or `SCRAM-SHA-512`
+/// * `KAFKA_SASL_USERNAME` / `KAFKA_SASL_PASSWORD` - required for the `SASL_*` protocols
+/// * `KAFKA_SSL_CA_LOCATION` - CA certificate used to verify the broker
+/// * `KAFKA_SSL_CA_PEM` - the same CA certificate inline, for platforms where files cannot be
+///   mounted. It is written to a temporary file at startup, see [`crate::ca_file::CaFile`].
+///
+/// The password is redacted from the `Debug` output, so the configuration can be logged.
+#[derive(Clone, PartialEq)]
+pub struct KafkaSecurity {
+    pub protocol: String,
+    pub sasl_mechanism: Option<String>,
+    pub sasl_username: Option<String>,
+    pub sasl_password: Option<String>,
+    pub ssl_ca_location: Option<String>,
+    pub ssl_ca_pem: Option<String>,
+}
+
+impl KafkaSecurity {
+    pub fn apply(&self, config: &mut ClientConfig) {
+        config.set("security.protocol", &self.protocol);
+        let optional = [
+            ("sasl.mechanism", &self.sasl_mechanism),
+            ("sasl.username", &self.sasl_username),
+            ("sasl.password", &self.sasl_password),
+            ("ssl.ca.location", &self.ssl_ca_location),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                config.set(key, value);
+            }
+        }
+    }
+
+    fn from_lookup(
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<KafkaSecurity>, ConfigError> {
+        let Some(protocol) = lookup("KAFKA_SECURITY_PROTOCOL") else {
+            return Ok(None);
+        };
+        let protocol = protocol.to_ascii_uppercase();
+        if !SECURITY_PROTOCOLS.contains(&protocol.as_str()) {
+            return Err(ConfigError::Invalid {
+                name: "KAFKA_SECURITY_PROTOCOL",
+                value: protocol,
+                reason: "expected PLAINTEXT, SSL, SASL_PLAINTEXT or SASL_SSL",
+            });
+        }
+
+        let security = KafkaSecurity {
+            sasl_mechanism: lookup("KAFKA_SASL_MECHANISM"),
+            sasl_username: lookup("KAFKA_SASL_USERNAME"),
+            sasl_password: lookup("KAFKA_SASL_PASSWORD"),
+            ssl_ca_location: lookup("KAFKA_SSL_CA_LOCATION"),
+            ssl_ca_pem: lookup("KAFKA_SSL_CA_PEM").map(ca_pem).transpose()?,
+            protocol,
+        };
+        if security.ssl_ca_location.is_some() && security.ssl_ca_pem.is_some() {
+            return Err(ConfigError::Invalid {
+                name: "KAFKA_SSL_CA_PEM",
+                value: "<pem>".to_string(),
+                reason: "cannot be combined with KAFKA_SSL_CA_LOCATION",
+            });
+        }
+        if security.protocol.starts_with("SASL_") {
+            if security.sasl_username.is_none() {
+                return Err(ConfigError::Missing {
+                    name: "KAFKA_SASL_USERNAME",
+                    reason: "required for SASL authentication",
+                });
+            }
+            if security.sasl_password.is_none() {
+                return Err(ConfigError::Missing {
+                    name: "KAFKA_SASL_PASSWORD",
+                    reason: "required for SASL authentication",
+                });
+            }
+        }
+        Ok(Some(security))
+    }
+}
+
+impl fmt::Debug for KafkaSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaSecurity")
+            .field("protocol", &self.protocol)
+            .field("sasl_mechanism", &self.sasl_mechanism)
+            .field("sasl_username", &self.sasl_username)
+            .field(
+                "sasl_password",
+                &self.sasl_password.as_ref().map(|_| "<redacted>"),
+            )
+            .field("ssl_ca_location", &self.ssl_ca_location)
+            .field("ssl_ca_pem", &self.ssl_ca_pem.as_ref().map(|_| "<pem>"))
+            .finish()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    Invalid {
+        name: &'static str,
+        value: String,
+        reason: &'static str,
+    },
+    Missing {
+        name: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(f, "invalid value {:?} for {}: {}", value, name, reason),
+            ConfigError::Missing { name, reason } => write!(f, "{} is not set: {}", name, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Shown in place of credentials in an [`EffectiveConfig`].
+const REDACTED: &str = "***";
+
+/// Client settings that hold credentials.
+const SECRET_SETTINGS: [&str; 5] = [
+    "sasl.password",
+    "ssl.key.password",
+    "ssl.keystore.password",
+    "ssl.truststore.password",
+    "sasl.oauthbearer.client.secret",
+];
+
+/// What the producer was actually configured with, served at `GET /debug/config`: every
+/// librdkafka setting it is created with, credentials redacted to `***`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectiveConfig {
+    pub broker: String,
+    pub topic: String,
+    pub producer: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// The settings the producer is created with, see [`EffectiveConfig`].
+    pub fn effective(&self) -> EffectiveConfig {
+        let mut client = ClientConfig::new();
+        client.set("bootstrap.servers", &self.broker);
+        self.producer.apply(&mut client);
+        let producer = client
+            .config_map()
+            .into_iter()
+            .map(|(name, value)| {
+                let value = if SECRET_SETTINGS.contains(&name) {
+                    REDACTED
+                } else {
+                    value
+                };
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        EffectiveConfig {
+            broker: self.broker.clone(),
+            topic: self.topic.clone(),
+            producer,
+        }
+    }
+
+    /// Builds the configuration from an arbitrary variable lookup, falling back to the
+    /// workshop defaults for anything that is not set.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let broker = non_empty(
+            "KAFKA_BROKER",
+            lookup("KAFKA_BROKER").unwrap_or_else(|| DEFAULT_BROKER.to_string()),
+        )?;
+        let prefix = lookup("KAFKA_TOPIC_PREFIX").unwrap_or_default();
+        let topic = non_empty(
+            "KAFKA_TOPIC",
+            lookup("KAFKA_TOPIC").unwrap_or_else(|| DEFAULT_TOPIC.to_string()),
+        )?;
+        let topic = prefixed_topic(&prefix, &topic);
+        let topics = match lookup("KAFKA_TOPIC_ROUTES") {
+            Some(value) => topic_routes(&prefix, &topic, value)?,
+            None => TopicRouter::new(&topic),
+        };
+        let http_bind_addr = bind_addr(
+            "HTTP_BIND_ADDR",
+            lookup("HTTP_BIND_ADDR").unwrap_or_else(|| DEFAULT_HTTP_BIND_ADDR.to_string()),
+        )?;
+
+        let idempotent = lookup("KAFKA_ENABLE_IDEMPOTENCE")
+            .map(|value| flag("KAFKA_ENABLE_IDEMPOTENCE", value))
+            .transpose()?
+            .unwrap_or_default();
+        let acks = lookup("KAFKA_ACKS").map(Acks::parse).transpose()?;
+        if let (true, Some(acks @ (Acks::None | Acks::Leader))) = (idempotent, acks) {
+            return Err(ConfigError::Invalid {
+                name: "KAFKA_ACKS",
+                value: acks.as_str().to_string(),
+                reason: "must be all when KAFKA_ENABLE_IDEMPOTENCE is true",
+            });
+        }
+
+        let client_id = match lookup("KAFKA_CLIENT_ID") {
+            Some(value) => non_empty("KAFKA_CLIENT_ID", value)?,
+            None => default_client_id(lookup("HOSTNAME")),
+        };
+
+        let producer = ProducerOptions {
+            idempotent,
+            acks,
+            client_id,
+            security: KafkaSecurity::from_lookup(&lookup)?,
+            partitioning: lookup("KAFKA_PARTITION_STRATEGY")
+                .map(PartitionStrategy::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            compression: lookup("KAFKA_COMPRESSION")
+                .map(Compression::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            batching: Batching::from_lookup(&lookup)?,
+            max_payload_bytes: lookup("KAFKA_MAX_PAYLOAD_BYTES")
+                .map(|value| payload_limit("KAFKA_MAX_PAYLOAD_BYTES", value))
+                .transpose()?
+                .unwrap_or(DEFAULT_MAX_PAYLOAD_BYTES),
+            send_timeout: lookup("KAFKA_SEND_TIMEOUT_MS")
+                .map(|value| timeout_ms("KAFKA_SEND_TIMEOUT_MS", value))
+                .transpose()?
+                .unwrap_or(DEFAULT_SEND_TIMEOUT),
+            tombstone_on_delete: lookup("KAFKA_TOMBSTONE_ON_DELETE")
+                .map(|value| flag("KAFKA_TOMBSTONE_ON_DELETE", value))
+                .transpose()?
+                .unwrap_or_default(),
+            delivery_mode: lookup("KAFKA_DELIVERY_MODE")
+                .map(DeliveryMode::parse)
+                .transpose()?
+                .unwrap_or_default(),
+            ..ProducerOptions::default()
+        };
+
+        let auto_create_topic = lookup("KAFKA_AUTO_CREATE_TOPIC")
+            .map(|value| flag("KAFKA_AUTO_CREATE_TOPIC", value))
+            .transpose()?
+            .unwrap_or_default()
+            .then(|| TopicSettings::from_lookup(&lookup))
+            .transpose()?;
+
+        Ok(Config {
+            broker,
+            topic,
+            topics,
+            http_bind_addr,
+            http_workers: lookup("HTTP_WORKERS")
+                .map(|value| workers("HTTP_WORKERS", value))
+                .transpose()?,
+            http_max_body_bytes: lookup("HTTP_MAX_BODY_BYTES")
+                .map(|value| payload_limit("HTTP_MAX_BODY_BYTES", value))
+                .transpose()?
+                .unwrap_or(DEFAULT_HTTP_MAX_BODY_BYTES),
+            http_rate_limit_per_sec: lookup("HTTP_RATE_LIMIT_PER_SEC")
+                .map(|value| rate_limit("HTTP_RATE_LIMIT_PER_SEC", value))
+                .transpose()?,
+            dlq_topic: lookup("KAFKA_DLQ_TOPIC")
+                .map(|value| non_empty("KAFKA_DLQ_TOPIC", value))
+                .transpose()?
+                .map(|topic| prefixed_topic(&prefix, &topic)),
+            startup_retries: lookup("KAFKA_STARTUP_RETRIES")
+                .map(|value| retries("KAFKA_STARTUP_RETRIES", value))
+                .transpose()?
+                .unwrap_or_default(),
+            dry_run: lookup("DRY_RUN")
+                .map(|value| flag("DRY_RUN", value))
+                .transpose()?
+                .unwrap_or_default(),
+            selftest: lookup("SELFTEST")
+                .map(|value| flag("SELFTEST", value))
+                .transpose()?
+                .unwrap_or_default(),
+            debug_endpoints: lookup("DEBUG_ENDPOINTS")
+                .map(|value| flag("DEBUG_ENDPOINTS", value))
+                .transpose()?
+                .unwrap_or_default(),
+            auto_create_topic,
+            version_scheme: lookup("VERSION_SCHEME")
+                .map(version_scheme)
+                .transpose()?
+                .unwrap_or_default(),
+            id_scheme: lookup("ID_SCHEME")
+                .map(id_scheme)
+                .transpose()?
+                .unwrap_or_default(),
+            event_json_case: lookup("EVENT_JSON_CASE")
+                .map(json_case)
+                .transpose()?
+                .unwrap_or_default(),
+            pretty_json: lookup("PRETTY_JSON")
+                .map(|value| flag("PRETTY_JSON", value))
+                .transpose()?
+                .unwrap_or_default(),
+            audit_sink: lookup("AUDIT_SINK").map(audit_target).transpose()?,
+            failure_webhook_url: lookup("FAILURE_WEBHOOK_URL")
+                .map(|value| http_url("FAILURE_WEBHOOK_URL", value))
+                .transpose()?,
+            producer,
+            #[cfg(feature = "avro")]
+            schema_registry_url: lookup("SCHEMA_REGISTRY_URL")
+                .map(|value| non_empty("SCHEMA_REGISTRY_URL", value))
+                .transpose()?,
+            #[cfg(feature = "cloudevents")]
+            cloudevents: crate::cloudevents::CloudEventsOptions::from_lookup(&lookup)?,
+        })
+    }
+
+    /// Every topic the service publishes to: the routed topics and the dead letter topic.
+    pub fn all_topics(&self) -> Vec<&str> {
+        let mut topics = self.topics.topics();
+        topics.extend(self.dlq_topic.as_deref());
+        topics
+    }
+}
+
+/// `product-event-service`, suffixed with `hostname` when there is one.
+fn default_client_id(hostname: Option<String>) -> String {
+    match hostname.filter(|hostname| !hostname.trim().is_empty()) {
+        Some(hostname) => format!("{}-{}", DEFAULT_CLIENT_ID, hostname.trim()),
+        None => DEFAULT_CLIENT_ID.to_string(),
+    }
+}
+
+fn version_scheme(value: String) -> Result<VersionScheme, ConfigError> {
+    VersionScheme::ALL
+        .into_iter()
+        .find(|scheme| value.eq_ignore_ascii_case(scheme.as_str()))
+        .ok_or(ConfigError::Invalid {
+            name: "VERSION_SCHEME",
+            value,
+            reason: "expected prefixed-int, plain-int or semver",
+        })
+}
+
+fn id_scheme(value: String) -> Result<IdScheme, ConfigError> {
+    IdScheme::ALL
+        .into_iter()
+        .find(|scheme| value.eq_ignore_ascii_case(scheme.as_str()))
+        .ok_or(ConfigError::Invalid {
+            name: "ID_SCHEME",
+            value,
+            reason: "expected uuid-v4 or uuid-v7",
+        })
+}
+
+fn json_case(value: String) -> Result<JsonCase, ConfigError> {
+    JsonCase::ALL
+        .into_iter()
+        .find(|case| value.eq_ignore_ascii_case(case.as_str()))
+        .ok_or(ConfigError::Invalid {
+            name: "EVENT_JSON_CASE",
+            value,
+            reason: "expected snake or camel",
+        })
+}
+
+fn audit_target(value: String) -> Result<AuditTarget, ConfigError> {
+    AuditTarget::parse(&value).ok_or(ConfigError::Invalid {
+        name: "AUDIT_SINK",
+        value,
+        reason: "expected stdout or file:<path>",
+    })
+}
+
+fn flag(name: &'static str, value: String) -> Result<bool, ConfigError> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected true or false",
+        }),
+    }
+}
+
+fn non_empty(name: &'static str, value: String) -> Result<String, ConfigError> {
+    if value.trim().is_empty() {
+        return Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "must not be empty",
+        });
+    }
+    Ok(value)
+}
+
+fn http_url(name: &'static str, value: String) -> Result<String, ConfigError> {
+    if !value.starts_with("http://") && !value.starts_with("https://") {
+        return Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected an http:// or https:// URL",
+        });
+    }
+    Ok(value)
+}
+
+fn ca_pem(value: String) -> Result<String, ConfigError> {
+    if !value.contains("-----BEGIN CERTIFICATE-----") {
+        return Err(ConfigError::Invalid {
+            name: "KAFKA_SSL_CA_PEM",
+            value,
+            reason: "expected a PEM encoded certificate",
+        });
+    }
+    Ok(value)
+}
+
+fn bind_addr(name: &'static str, value: String) -> Result<String, ConfigError> {
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(value),
+        _ => Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected <host>:<port>",
+        }),
+    }
+}
+
+/// `topic` in the `prefix` namespace, e.g. `prod.products` for the prefix `prod`, or `topic`
+/// itself for an empty prefix. A prefix that already ends in the separator keeps just the one.
+fn prefixed_topic(prefix: &str, topic: &str) -> String {
+    let prefix = prefix.trim().trim_end_matches(TOPIC_PREFIX_SEPARATOR);
+    if prefix.is_empty() {
+        return topic.to_string();
+    }
+    format!("{}{}{}", prefix, TOPIC_PREFIX_SEPARATOR, topic)
+}
+
+/// Parses `KAFKA_TOPIC_ROUTES`, namespacing each routed topic by `prefix`. `default` is
+/// expected to be namespaced already.
+fn topic_routes(prefix: &str, default: &str, value: String) -> Result<TopicRouter, ConfigError> {
+    let mut router = TopicRouter::new(default);
+    for route in value.split(',') {
+        let (event_type, topic) = match route.split_once('=') {
+            Some((event_type, topic))
+                if !event_type.trim().is_empty() && !topic.trim().is_empty() =>
+            {
+                (event_type.trim(), topic.trim())
+            }
+            _ => {
+                return Err(ConfigError::Invalid {
+                    name: "KAFKA_TOPIC_ROUTES",
+                    value,
+                    reason: "expected <event type>=<topic>[,...]",
+                })
+            }
+        };
+        // matched exactly, so a misspelt event type is an error rather than a dead route
+        let Ok(kind) = event_type.parse::<EventKind>() else {
+            return Err(ConfigError::Invalid {
+                name: "KAFKA_TOPIC_ROUTES",
+                value,
+                reason: "unknown event type, expected CREATED, UPDATED or DELETED",
+            });
+        };
+        router = router.route(kind, &prefixed_topic(prefix, topic));
+    }
+    Ok(router)
+}
+
+fn workers(name: &'static str, value: String) -> Result<usize, ConfigError> {
+    match value.trim().parse::<usize>() {
+        Ok(workers) if workers > 0 => Ok(workers),
+        _ => Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected a positive number of workers",
+        }),
+    }
+}
+
+fn rate_limit(name: &'static str, value: String) -> Result<u32, ConfigError> {
+    match value.trim().parse::<u32>() {
+        Ok(limit) if limit > 0 => Ok(limit),
+        _ => Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected a positive number of requests",
+        }),
+    }
+}
+
+fn payload_limit(name: &'static str, value: String) -> Result<usize, ConfigError> {
+    match value.trim().parse::<usize>() {
+        Ok(limit) if limit > 0 => Ok(limit),
+        _ => Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected a positive number of bytes",
+        }),
+    }
+}
+
+fn timeout_ms(name: &'static str, value: String) -> Result<Duration, ConfigError> {
+    match value.trim().parse::<u64>() {
+        Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+        _ => Err(ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected a positive number of milliseconds",
+        }),
+    }
+}
+
+fn retries(name: &'static str, value: String) -> Result<u32, ConfigError> {
+    value
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| ConfigError::Invalid {
+            name,
+            value,
+            reason: "expected a non-negative number of retries",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        default_client_id, prefixed_topic, Acks, Config, ConfigError, DeliveryMode,
+        PartitionStrategy, ProducerOptions, TopicSettings,
+    };
+    use crate::audit::AuditTarget;
+    use crate::routing::TopicRouter;
+    use expectest::prelude::*;
+    use product_events::{EventKind, IdScheme, JsonCase, VersionScheme};
+    use rdkafka::config::ClientConfig;
+    use std::time::Duration;
+
+    #[test]
+    fn reads_config_from_environment() {
+        // the environment is shared by tests running in parallel, so the variables are passed
+        // through a lookup of their own
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_BROKER" => Some("kafka-1:9093,kafka-2:9093".to_string()),
+            "KAFKA_TOPIC" => Some("product-events".to_string()),
+            "HTTP_BIND_ADDR" => Some("0.0.0.0:9000".to_string()),
+            _ => None,
+        });
+
+        expect!(config).to(be_ok().value(Config {
+            broker: "kafka-1:9093,kafka-2:9093".to_string(),
+            topic: "product-events".to_string(),
+            topics: TopicRouter::new("product-events"),
+            http_bind_addr: "0.0.0.0:9000".to_string(),
+            http_workers: None,
+            http_max_body_bytes: 1_048_576,
+            http_rate_limit_per_sec: None,
+            dlq_topic: None,
+            startup_retries: 0,
+            dry_run: false,
+            selftest: false,
+            debug_endpoints: false,
+            auto_create_topic: None,
+            version_scheme: VersionScheme::PrefixedInt,
+            id_scheme: IdScheme::UuidV4,
+            event_json_case: JsonCase::Snake,
+            pretty_json: false,
+            audit_sink: None,
+            failure_webhook_url: None,
+            producer: ProducerOptions {
+                client_id: default_client_id(None),
+                ..ProducerOptions::default()
+            },
+            #[cfg(feature = "avro")]
+            schema_registry_url: None,
+            #[cfg(feature = "cloudevents")]
+            cloudevents: Default::default(),
+        }));
+    }
+
+    #[test]
+    fn defaults_and_rejects_invalid_values() {
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.broker).to(be_equal_to("localhost:9092"));
+        expect!(config.topic).to(be_equal_to("products"));
+        expect!(config.http_bind_addr).to(be_equal_to("127.0.0.1:8081"));
+
+        let config = Config::from_lookup(|name| match name {
+            "HTTP_BIND_ADDR" => Some("127.0.0.1".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "HTTP_BIND_ADDR",
+            value: "127.0.0.1".to_string(),
+            reason: "expected <host>:<port>",
+        }));
+    }
+
+    #[test]
+    fn reads_the_version_scheme() {
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.version_scheme).to(be_equal_to(VersionScheme::PrefixedInt));
+        let config = Config::from_lookup(|name| match name {
+            "VERSION_SCHEME" => Some("SemVer".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.version_scheme))
+            .to(be_ok().value(VersionScheme::SemVer));
+        let config = Config::from_lookup(|name| match name {
+            "VERSION_SCHEME" => Some("calver".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "VERSION_SCHEME",
+            value: "calver".to_string(),
+            reason: "expected prefixed-int, plain-int or semver",
+        }));
+    }
+
+    #[test]
+    fn reads_the_id_scheme() {
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.id_scheme).to(be_equal_to(IdScheme::UuidV4));
+        let config = Config::from_lookup(|name| match name {
+            "ID_SCHEME" => Some("UUID-V7".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.id_scheme)).to(be_ok().value(IdScheme::UuidV7));
+        let config = Config::from_lookup(|name| match name {
+            "ID_SCHEME" => Some("ulid".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "ID_SCHEME",
+            value: "ulid".to_string(),
+            reason: "expected uuid-v4 or uuid-v7",
+        }));
+    }
+
+    #[test]
+    fn reads_the_pretty_json_flag() {
+        expect!(Config::from_lookup(|_| None).unwrap().pretty_json).to(be_false());
+        let config = Config::from_lookup(|name| match name {
+            "PRETTY_JSON" => Some("true".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.pretty_json)).to(be_ok().value(true));
+    }
+
+    #[test]
+    fn reads_the_event_json_case() {
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.event_json_case).to(be_equal_to(JsonCase::Snake));
+        let config = Config::from_lookup(|name| match name {
+            "EVENT_JSON_CASE" => Some("Camel".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.event_json_case)).to(be_ok().value(JsonCase::Camel));
+        let config = Config::from_lookup(|name| match name {
+            "EVENT_JSON_CASE" => Some("kebab".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "EVENT_JSON_CASE",
+            value: "kebab".to_string(),
+            reason: "expected snake or camel",
+        }));
+    }
+
+    #[test]
+    fn reads_the_audit_sink() {
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.audit_sink).to(be_none());
+        let config = Config::from_lookup(|name| match name {
+            "AUDIT_SINK" => Some("file:/tmp/audit.jsonl".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.audit_sink))
+            .to(be_ok().value(Some(AuditTarget::File("/tmp/audit.jsonl".into()))));
+        let config = Config::from_lookup(|name| match name {
+            "AUDIT_SINK" => Some("syslog".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "AUDIT_SINK",
+            value: "syslog".to_string(),
+            reason: "expected stdout or file:<path>",
+        }));
+    }
+
+    #[test]
+    fn reads_the_failure_webhook_url() {
+        expect!(Config::from_lookup(|_| None).unwrap().failure_webhook_url).to(be_none());
+        let config = Config::from_lookup(|name| match name {
+            "FAILURE_WEBHOOK_URL" => Some("https://alerts.example.com/hooks/kafka".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.failure_webhook_url))
+            .to(be_ok().value(Some("https://alerts.example.com/hooks/kafka".to_string())));
+        let config = Config::from_lookup(|name| match name {
+            "FAILURE_WEBHOOK_URL" => Some("alerts.example.com".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "FAILURE_WEBHOOK_URL",
+            value: "alerts.example.com".to_string(),
+            reason: "expected an http:// or https:// URL",
+        }));
+    }
+
+    #[test]
+    fn reads_the_dry_run_flag() {
+        expect!(Config::from_lookup(|_| None).unwrap().dry_run).to(be_false());
+        let config = Config::from_lookup(|name| match name {
+            "DRY_RUN" => Some("true".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.dry_run)).to(be_ok().value(true));
+        let config = Config::from_lookup(|name| match name {
+            "DRY_RUN" => Some("yes".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "DRY_RUN",
+            value: "yes".to_string(),
+            reason: "expected true or false",
+        }));
+    }
+
+    #[test]
+    fn reads_the_debug_endpoints_flag() {
+        expect!(Config::from_lookup(|_| None).unwrap().debug_endpoints).to(be_false());
+        let config = Config::from_lookup(|name| match name {
+            "DEBUG_ENDPOINTS" => Some("1".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.debug_endpoints)).to(be_ok().value(true));
+    }
+
+    #[test]
+    fn reads_the_selftest_flag() {
+        expect!(Config::from_lookup(|_| None).unwrap().selftest).to(be_false());
+        let config = Config::from_lookup(|name| match name {
+            "SELFTEST" => Some("1".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.selftest)).to(be_ok().value(true));
+    }
+
+    #[test]
+    fn parses_topic_routes() {
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_TOPIC_ROUTES" => Some("DELETED=product-tombstones".to_string()),
+            _ => None,
+        });
+        expect!(config.map(|config| config.topics)).to(be_ok()
+            .value(TopicRouter::new("products").route(EventKind::Deleted, "product-tombstones")));
+
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_TOPIC_ROUTES" => Some("DELETED".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_TOPIC_ROUTES",
+            value: "DELETED".to_string(),
+            reason: "expected <event type>=<topic>[,...]",
+        }));
+
+        for typo in ["deleted=product-tombstones", "DELTED=product-tombstones"] {
+            let config = Config::from_lookup(|name| match name {
+                "KAFKA_TOPIC_ROUTES" => Some(typo.to_string()),
+                _ => None,
+            });
+            expect!(config).to(be_err().value(ConfigError::Invalid {
+                name: "KAFKA_TOPIC_ROUTES",
+                value: typo.to_string(),
+                reason: "unknown event type, expected CREATED, UPDATED or DELETED",
+            }));
+        }
+    }
+
+    #[test]
+    fn namespaces_every_topic_by_the_topic_prefix() {
+        expect!(prefixed_topic("", "products")).to(be_equal_to("products"));
+        expect!(prefixed_topic("prod", "products")).to(be_equal_to("prod.products"));
+        expect!(prefixed_topic("prod.", "products")).to(be_equal_to("prod.products"));
+
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_TOPIC_PREFIX" => Some("prod".to_string()),
+            "KAFKA_TOPIC_ROUTES" => Some("DELETED=product-tombstones".to_string()),
+            "KAFKA_DLQ_TOPIC" => Some("products-dlq".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        expect!(config.topic.as_str()).to(be_equal_to("prod.products"));
+        expect!(config.topics).to(be_equal_to(
+            TopicRouter::new("prod.products").route(EventKind::Deleted, "prod.product-tombstones"),
+        ));
+        expect!(config.dlq_topic).to(be_some().value("prod.products-dlq"));
+    }
+
+    #[test]
+    fn parses_the_http_worker_count() {
+        let workers = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "HTTP_WORKERS" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.http_workers)
+        };
+
+        expect!(Config::from_lookup(|_| None).unwrap().http_workers).to(be_none());
+        expect!(workers("4")).to(be_ok().value(Some(4)));
+        for invalid in ["0", "four", "-1", ""] {
+            expect!(workers(invalid)).to(be_err().value(ConfigError::Invalid {
+                name: "HTTP_WORKERS",
+                value: invalid.to_string(),
+                reason: "expected a positive number of workers",
+            }));
+        }
+    }
+
+    #[test]
+    fn parses_the_http_body_limit() {
+        let limit = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "HTTP_MAX_BODY_BYTES" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.http_max_body_bytes)
+        };
+
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.http_max_body_bytes).to(be_equal_to(1_048_576));
+        expect!(limit("4096")).to(be_ok().value(4096));
+        expect!(limit("0")).to(be_err().value(ConfigError::Invalid {
+            name: "HTTP_MAX_BODY_BYTES",
+            value: "0".to_string(),
+            reason: "expected a positive number of bytes",
+        }));
+    }
+
+    #[test]
+    fn parses_the_http_rate_limit() {
+        let limit = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "HTTP_RATE_LIMIT_PER_SEC" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.http_rate_limit_per_sec)
+        };
+
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.http_rate_limit_per_sec).to(be_none());
+        expect!(limit("50")).to(be_ok().value(Some(50)));
+        expect!(limit("0")).to(be_err().value(ConfigError::Invalid {
+            name: "HTTP_RATE_LIMIT_PER_SEC",
+            value: "0".to_string(),
+            reason: "expected a positive number of requests",
+        }));
+    }
+
+    #[test]
+    fn parses_the_send_timeout() {
+        let timeout = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "KAFKA_SEND_TIMEOUT_MS" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.producer.send_timeout)
+        };
+
+        let config = Config::from_lookup(|_| None).unwrap();
+        expect!(config.producer.send_timeout).to(be_equal_to(Duration::from_secs(30)));
+        let mut client = ClientConfig::new();
+        config.producer.apply(&mut client);
+        expect!(client.get("message.timeout.ms")).to(be_some().value("30000"));
+        expect!(timeout("2500")).to(be_ok().value(Duration::from_millis(2500)));
+        expect!(timeout("never")).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_SEND_TIMEOUT_MS",
+            value: "never".to_string(),
+            reason: "expected a positive number of milliseconds",
+        }));
+    }
+
+    #[test]
+    fn parses_the_startup_retry_count() {
+        let retries = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "KAFKA_STARTUP_RETRIES" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.startup_retries)
+        };
+
+        expect!(Config::from_lookup(|_| None).unwrap().startup_retries).to(be_equal_to(0));
+        expect!(retries("5")).to(be_ok().value(5));
+        for invalid in ["-1", "five", ""] {
+            expect!(retries(invalid)).to(be_err().value(ConfigError::Invalid {
+                name: "KAFKA_STARTUP_RETRIES",
+                value: invalid.to_string(),
+                reason: "expected a non-negative number of retries",
+            }));
+        }
+    }
+
+    #[test]
+    fn reads_the_auto_created_topic_layout() {
+        let config = |vars: &[(&str, &str)]| {
+            let vars: Vec<(String, String)> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            Config::from_lookup(move |name| {
+                vars.iter()
+                    .find(|(var, _)| var == name)
+                    .map(|(_, value)| value.clone())
+            })
+        };
+
+        expect!(config(&[]).unwrap().auto_create_topic).to(be_none());
+        expect!(
+            config(&[("KAFKA_TOPIC_PARTITIONS", "3")])
+                .unwrap()
+                .auto_create_topic
+        )
+        .to(be_none());
+        expect!(config(&[("KAFKA_AUTO_CREATE_TOPIC", "true")]).map(|c| c.auto_create_topic))
+            .to(be_ok().value(Some(TopicSettings::default())));
+        let configured = config(&[
+            ("KAFKA_AUTO_CREATE_TOPIC", "true"),
+            ("KAFKA_TOPIC_PARTITIONS", "6"),
+            ("KAFKA_TOPIC_REPLICATION_FACTOR", "3"),
+        ]);
+        expect!(configured.map(|c| c.auto_create_topic)).to(be_ok().value(Some(TopicSettings {
+            partitions: 6,
+            replication_factor: 3,
+        })));
+        let invalid = config(&[
+            ("KAFKA_AUTO_CREATE_TOPIC", "true"),
+            ("KAFKA_TOPIC_PARTITIONS", "0"),
+        ]);
+        expect!(invalid).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_TOPIC_PARTITIONS",
+            value: "0".to_string(),
+            reason: "expected a positive integer",
+        }));
+
+        let routed = config(&[
+            ("KAFKA_TOPIC_ROUTES", "DELETED=product-tombstones"),
+            ("KAFKA_DLQ_TOPIC", "products-dlq"),
+        ])
+        .unwrap();
+        expect!(routed.all_topics()).to(be_equal_to(vec![
+            "products",
+            "product-tombstones",
+            "products-dlq",
+        ]));
+    }
+
+    #[test]
+    fn parses_the_payload_size_limit() {
+        let limit = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "KAFKA_MAX_PAYLOAD_BYTES" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.producer.max_payload_bytes)
+        };
+
+        expect!(
+            Config::from_lookup(|_| None)
+                .unwrap()
+                .producer
+                .max_payload_bytes
+        )
+        .to(be_equal_to(1_000_000));
+        expect!(limit("1024")).to(be_ok().value(1024));
+        for invalid in ["0", "1MB", ""] {
+            expect!(limit(invalid)).to(be_err().value(ConfigError::Invalid {
+                name: "KAFKA_MAX_PAYLOAD_BYTES",
+                value: invalid.to_string(),
+                reason: "expected a positive number of bytes",
+            }));
+        }
+    }
+
+    #[test]
+    fn idempotent_producer_sets_idempotence_and_acks() {
+        let mut config = ClientConfig::new();
+        ProducerOptions::default().apply(&mut config);
+        expect!(config.get("enable.idempotence")).to(be_none());
+        expect!(config.get("acks")).to(be_none());
+
+        let options = Config::from_lookup(|name| match name {
+            "KAFKA_ENABLE_IDEMPOTENCE" => Some("true".to_string()),
+            _ => None,
+        })
+        .unwrap()
+        .producer;
+        options.apply(&mut config);
+        expect!(config.get("enable.idempotence")).to(be_some().value("true"));
+        expect!(config.get("acks")).to(be_some().value("all"));
+    }
+
+    #[test]
+    fn reads_the_acks_level() {
+        let acks = |vars: &[(&'static str, &'static str)]| {
+            let vars = vars.to_vec();
+            Config::from_lookup(move |name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+            .map(|config| config.producer)
+        };
+
+        let mut config = ClientConfig::new();
+        let options = acks(&[]).unwrap();
+        expect!(options.acks).to(be_none());
+        options.apply(&mut config);
+        expect!(config.get("acks")).to(be_none());
+
+        for (value, expected) in [("0", Acks::None), ("1", Acks::Leader), ("ALL", Acks::All)] {
+            let options = acks(&[("KAFKA_ACKS", value)]).unwrap();
+            expect!(options.acks).to(be_some().value(expected));
+            let mut config = ClientConfig::new();
+            options.apply(&mut config);
+            expect!(config.get("acks")).to(be_some().value(expected.as_str()));
+        }
+
+        expect!(acks(&[("KAFKA_ACKS", "2")])).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_ACKS",
+            value: "2".to_string(),
+            reason: "expected 0, 1 or all",
+        }));
+        let idempotent = acks(&[("KAFKA_ENABLE_IDEMPOTENCE", "true"), ("KAFKA_ACKS", "1")]);
+        expect!(idempotent).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_ACKS",
+            value: "1".to_string(),
+            reason: "must be all when KAFKA_ENABLE_IDEMPOTENCE is true",
+        }));
+    }
+
+    #[test]
+    fn names_the_producer_to_the_broker() {
+        let client_id = |vars: &[(&'static str, &'static str)]| {
+            let vars = vars.to_vec();
+            Config::from_lookup(move |name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+            .map(|config| config.producer.client_id)
+        };
+
+        expect!(client_id(&[])).to(be_ok().value("product-event-service".to_string()));
+        expect!(client_id(&[("HOSTNAME", "provider-7f9c")]))
+            .to(be_ok().value("product-event-service-provider-7f9c".to_string()));
+        let configured = client_id(&[("HOSTNAME", "provider-7f9c"), ("KAFKA_CLIENT_ID", "shop")]);
+        expect!(configured).to(be_ok().value("shop".to_string()));
+        expect!(client_id(&[("KAFKA_CLIENT_ID", " ")])).to(be_err());
+
+        let mut config = ClientConfig::new();
+        ProducerOptions::default().apply(&mut config);
+        expect!(config.get("client.id")).to(be_some().value("product-event-service"));
+    }
+
+    #[test]
+    fn applies_sasl_settings_without_logging_the_password() {
+        let lookup = |name: &str| match name {
+            "KAFKA_SECURITY_PROTOCOL" => Some("sasl_ssl".to_string()),
+            "KAFKA_SASL_MECHANISM" => Some("SCRAM-SHA-512".to_string()),
+            "KAFKA_SASL_USERNAME" => Some("workshop".to_string()),
+            "KAFKA_SASL_PASSWORD" => Some("hunter2".to_string()),
+            _ => None,
+        };
+        let options = Config::from_lookup(lookup).unwrap().producer;
+        expect!(format!("{:?}", options).contains("hunter2")).to(be_false());
+
+        let mut config = ClientConfig::new();
+        options.apply(&mut config);
+        expect!(config.get("security.protocol")).to(be_some().value("SASL_SSL"));
+        expect!(config.get("sasl.mechanism")).to(be_some().value("SCRAM-SHA-512"));
+        expect!(config.get("sasl.username")).to(be_some().value("workshop"));
+        expect!(config.get("sasl.password")).to(be_some().value("hunter2"));
+        expect!(config.get("ssl.ca.location")).to(be_none());
+
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_SECURITY_PROTOCOL" => Some("SASL_SSL".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Missing {
+            name: "KAFKA_SASL_USERNAME",
+            reason: "required for SASL authentication",
+        }));
+        let security = Config::from_lookup(|_| None).unwrap().producer.security;
+        expect!(security).to(be_none());
+    }
+
+    #[test]
+    fn reads_an_inline_ca_certificate() {
+        let pem = "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n";
+        let security = |location: Option<&str>, pem: &str| {
+            let (location, pem) = (location.map(str::to_string), pem.to_string());
+            Config::from_lookup(move |name| match name {
+                "KAFKA_SECURITY_PROTOCOL" => Some("SSL".to_string()),
+                "KAFKA_SSL_CA_LOCATION" => location.clone(),
+                "KAFKA_SSL_CA_PEM" => Some(pem.clone()),
+                _ => None,
+            })
+            .map(|config| config.producer.security.unwrap())
+        };
+        let read = security(None, pem).unwrap();
+        expect!(read.ssl_ca_pem.as_deref()).to(be_some().value(pem));
+        expect!(format!("{:?}", read).contains("MIIB")).to(be_false());
+
+        expect!(security(None, "not a certificate")).to(be_err());
+        expect!(security(Some("/etc/ssl/ca.pem"), pem)).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_SSL_CA_PEM",
+            value: "<pem>".to_string(),
+            reason: "cannot be combined with KAFKA_SSL_CA_LOCATION",
+        }));
+    }
+
+    #[test]
+    fn parses_partition_strategies() {
+        let strategy = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "KAFKA_PARTITION_STRATEGY" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.producer.partitioning)
+        };
+        expect!(strategy("key-hash")).to(be_ok().value(PartitionStrategy::KeyHash));
+        expect!(strategy("Round-Robin")).to(be_ok().value(PartitionStrategy::RoundRobin));
+        expect!(strategy("fixed:3")).to(be_ok().value(PartitionStrategy::Fixed(3)));
+        expect!(strategy("fixed:-1")).to(be_err());
+        expect!(strategy("random")).to(be_err());
+    }
+
+    #[test]
+    fn passes_batching_settings_through() {
+        let mut config = ClientConfig::new();
+        ProducerOptions::default().apply(&mut config);
+        expect!(config.get("linger.ms")).to(be_none());
+        expect!(config.get("batch.size")).to(be_none());
+        expect!(config.get("batch.num.messages")).to(be_none());
+
+        let options = Config::from_lookup(|name| match name {
+            "KAFKA_LINGER_MS" => Some("50".to_string()),
+            "KAFKA_BATCH_SIZE" => Some("262144".to_string()),
+            "KAFKA_BATCH_NUM_MESSAGES" => Some("500".to_string()),
+            _ => None,
+        })
+        .unwrap()
+        .producer;
+        options.apply(&mut config);
+        expect!(config.get("linger.ms")).to(be_some().value("50"));
+        expect!(config.get("batch.size")).to(be_some().value("262144"));
+        expect!(config.get("batch.num.messages")).to(be_some().value("500"));
+
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_BATCH_SIZE" => Some("0".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_BATCH_SIZE",
+            value: "0".to_string(),
+            reason: "expected a positive integer",
+        }));
+    }
+
+    #[test]
+    fn parses_the_delivery_mode() {
+        let mode = |value: &str| {
+            let value = value.to_string();
+            Config::from_lookup(move |name| match name {
+                "KAFKA_DELIVERY_MODE" => Some(value.clone()),
+                _ => None,
+            })
+            .map(|config| config.producer.delivery_mode)
+        };
+        expect!(
+            Config::from_lookup(|_| None)
+                .unwrap()
+                .producer
+                .delivery_mode
+        )
+        .to(be_equal_to(DeliveryMode::AwaitDelivery));
+        expect!(mode("Fire-And-Forget")).to(be_ok().value(DeliveryMode::FireAndForget));
+        expect!(mode("await-delivery")).to(be_ok().value(DeliveryMode::AwaitDelivery));
+        expect!(mode("eventually")).to(be_err());
+    }
+
+    #[test]
+    fn applies_the_compression_codec() {
+        let mut config = ClientConfig::new();
+        ProducerOptions::default().apply(&mut config);
+        expect!(config.get("compression.type")).to(be_some().value("none"));
+
+        for codec in ["none", "gzip", "snappy", "lz4", "zstd"] {
+            let options = Config::from_lookup(|name| match name {
+                "KAFKA_COMPRESSION" => Some(codec.to_string()),
+                _ => None,
+            })
+            .unwrap()
+            .producer;
+            let mut config = ClientConfig::new();
+            options.apply(&mut config);
+            expect!(config.get("compression.type")).to(be_some().value(codec));
+        }
+
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_COMPRESSION" => Some("brotli".to_string()),
+            _ => None,
+        });
+        expect!(config).to(be_err().value(ConfigError::Invalid {
+            name: "KAFKA_COMPRESSION",
+            value: "brotli".to_string(),
+            reason: "expected none, gzip, snappy, lz4 or zstd",
+        }));
+    }
+}
@@ -0,0 +1,219 @@
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use std::fmt;
+use std::time::Duration;
+
+/// Errors raised while publishing a `ProductEvent` to Kafka.
+#[derive(Debug)]
+pub enum PublishError {
+    /// The event could not be serialized to JSON.
+    Serialization(serde_json::Error),
+    /// The broker rejected the record or it could not be delivered.
+    Delivery(KafkaError),
+    /// With `acks=all`, fewer replicas are in sync than the topic's `min.insync.replicas`, so
+    /// the broker would not accept the record. A cluster health problem, not a client bug.
+    InsufficientReplicas(KafkaError),
+    /// Neither the broker nor librdkafka reported on the record within the configured
+    /// `send_timeout`, so it may still be delivered.
+    Timeout(Duration),
+    /// The encoded event is larger than the configured `max_payload_bytes`.
+    PayloadTooLarge { size: usize, limit: usize },
+    /// The event could not be encoded as Avro.
+    #[cfg(feature = "avro")]
+    Avro(crate::avro::AvroError),
+    /// The event does not match the `ProductEvent` JSON schema.
+    #[cfg(feature = "validate-schema")]
+    SchemaViolation(crate::schema::SchemaError),
+}
+
+impl PublishError {
+    /// Whether retrying the publish might succeed: broker, transport and queueing problems
+    /// are transient, while serialization failures or oversized messages never will be. A
+    /// [`PublishError::Timeout`] is not retried either, as the record may still be delivered
+    /// and a retry would duplicate it.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            PublishError::InsufficientReplicas(_) => true,
+            PublishError::Delivery(err) => matches!(
+                err.rdkafka_error_code(),
+                Some(
+                    RDKafkaErrorCode::QueueFull
+                        | RDKafkaErrorCode::MessageTimedOut
+                        | RDKafkaErrorCode::RequestTimedOut
+                        | RDKafkaErrorCode::OperationTimedOut
+                        | RDKafkaErrorCode::BrokerTransportFailure
+                        | RDKafkaErrorCode::AllBrokersDown
+                        | RDKafkaErrorCode::NetworkException
+                        | RDKafkaErrorCode::LeaderNotAvailable
+                        | RDKafkaErrorCode::NotLeaderForPartition
+                )
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether librdkafka's local producer queue was full. The queue drains as the broker
+    /// acknowledges records, so callers should back off rather than give up.
+    pub fn is_queue_full(&self) -> bool {
+        matches!(
+            self,
+            PublishError::Delivery(err) if err.rdkafka_error_code() == Some(RDKafkaErrorCode::QueueFull)
+        )
+    }
+
+    /// Groups the error for the delivery failure metric: `timeout` and `queue_full` are
+    /// usually transient, `broker_unavailable` means no broker could be reached,
+    /// `insufficient_replicas` that too few replicas are in sync, and anything else lands in
+    /// `other`.
+    pub fn category(&self) -> &'static str {
+        let err = match self {
+            PublishError::Delivery(err) => err,
+            PublishError::Timeout(_) => return "timeout",
+            PublishError::InsufficientReplicas(_) => return "insufficient_replicas",
+            _ => return "other",
+        };
+        match err.rdkafka_error_code() {
+            Some(
+                RDKafkaErrorCode::MessageTimedOut
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::OperationTimedOut,
+            ) => "timeout",
+            Some(RDKafkaErrorCode::QueueFull) => "queue_full",
+            Some(
+                RDKafkaErrorCode::BrokerTransportFailure
+                | RDKafkaErrorCode::AllBrokersDown
+                | RDKafkaErrorCode::NetworkException,
+            ) => "broker_unavailable",
+            _ => "other",
+        }
+    }
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::Serialization(err) => write!(f, "failed to serialize event: {}", err),
+            PublishError::Delivery(err) => write!(f, "failed to deliver event: {}", err),
+            PublishError::InsufficientReplicas(err) => write!(
+                f,
+                "not enough in-sync replicas to acknowledge the event: {}",
+                err
+            ),
+            PublishError::Timeout(timeout) => write!(
+                f,
+                "broker did not acknowledge the event within {} ms",
+                timeout.as_millis()
+            ),
+            PublishError::PayloadTooLarge { size, limit } => write!(
+                f,
+                "event payload is {} bytes, more than the limit of {} bytes",
+                size, limit
+            ),
+            #[cfg(feature = "avro")]
+            PublishError::Avro(err) => write!(f, "failed to encode event: {}", err),
+            #[cfg(feature = "validate-schema")]
+            PublishError::SchemaViolation(err) => write!(f, "invalid event: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PublishError::Serialization(err) => Some(err),
+            PublishError::Delivery(err) | PublishError::InsufficientReplicas(err) => Some(err),
+            PublishError::Timeout(_) | PublishError::PayloadTooLarge { .. } => None,
+            #[cfg(feature = "avro")]
+            PublishError::Avro(err) => Some(err),
+            #[cfg(feature = "validate-schema")]
+            PublishError::SchemaViolation(err) => Some(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for PublishError {
+    fn from(err: serde_json::Error) -> Self {
+        PublishError::Serialization(err)
+    }
+}
+
+impl From<KafkaError> for PublishError {
+    /// Delivery failures, with a missing in-sync replica told apart from the rest.
+    fn from(err: KafkaError) -> Self {
+        match err.rdkafka_error_code() {
+            Some(
+                RDKafkaErrorCode::NotEnoughReplicas
+                | RDKafkaErrorCode::NotEnoughReplicasAfterAppend,
+            ) => PublishError::InsufficientReplicas(err),
+            _ => PublishError::Delivery(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PublishError;
+    use expectest::prelude::*;
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use std::time::Duration;
+
+    #[test]
+    fn classifies_retriable_errors() {
+        let delivery = |code| PublishError::Delivery(KafkaError::MessageProduction(code));
+        expect!(delivery(RDKafkaErrorCode::QueueFull).is_retriable()).to(be_true());
+        expect!(delivery(RDKafkaErrorCode::MessageTimedOut).is_retriable()).to(be_true());
+        expect!(delivery(RDKafkaErrorCode::MessageSizeTooLarge).is_retriable()).to(be_false());
+
+        let serialization = serde_json::from_str::<u32>("nope").unwrap_err();
+        expect!(PublishError::Serialization(serialization).is_retriable()).to(be_false());
+        let too_large = PublishError::PayloadTooLarge {
+            size: 2048,
+            limit: 1024,
+        };
+        expect!(too_large.is_retriable()).to(be_false());
+        let timeout = PublishError::Timeout(Duration::from_secs(30));
+        expect!(timeout.is_retriable()).to(be_false());
+    }
+
+    #[test]
+    fn tells_missing_in_sync_replicas_apart_from_other_delivery_failures() {
+        for code in [
+            RDKafkaErrorCode::NotEnoughReplicas,
+            RDKafkaErrorCode::NotEnoughReplicasAfterAppend,
+        ] {
+            let err = PublishError::from(KafkaError::MessageProduction(code));
+            expect!(matches!(err, PublishError::InsufficientReplicas(_))).to(be_true());
+            expect!(err.is_retriable()).to(be_true());
+            expect!(err.category()).to(be_equal_to("insufficient_replicas"));
+        }
+        let err = PublishError::from(KafkaError::MessageProduction(
+            RDKafkaErrorCode::MessageTimedOut,
+        ));
+        expect!(matches!(err, PublishError::Delivery(_))).to(be_true());
+    }
+
+    #[test]
+    fn recognizes_a_full_producer_queue() {
+        let delivery = |code| PublishError::Delivery(KafkaError::MessageProduction(code));
+        expect!(delivery(RDKafkaErrorCode::QueueFull).is_queue_full()).to(be_true());
+        expect!(delivery(RDKafkaErrorCode::MessageTimedOut).is_queue_full()).to(be_false());
+        expect!(PublishError::Timeout(Duration::from_secs(30)).is_queue_full()).to(be_false());
+    }
+
+    #[test]
+    fn categorizes_delivery_failures() {
+        let delivery = |code| PublishError::Delivery(KafkaError::MessageProduction(code));
+        expect!(delivery(RDKafkaErrorCode::MessageTimedOut).category()).to(be_equal_to("timeout"));
+        expect!(delivery(RDKafkaErrorCode::RequestTimedOut).category()).to(be_equal_to("timeout"));
+        expect!(delivery(RDKafkaErrorCode::QueueFull).category()).to(be_equal_to("queue_full"));
+        expect!(delivery(RDKafkaErrorCode::AllBrokersDown).category())
+            .to(be_equal_to("broker_unavailable"));
+        expect!(delivery(RDKafkaErrorCode::MessageSizeTooLarge).category())
+            .to(be_equal_to("other"));
+        expect!(PublishError::Delivery(KafkaError::Canceled).category()).to(be_equal_to("other"));
+        let timeout = PublishError::Timeout(Duration::from_secs(30));
+        expect!(timeout.category()).to(be_equal_to("timeout"));
+
+        let serialization = serde_json::from_str::<u32>("nope").unwrap_err();
+        expect!(PublishError::Serialization(serialization).category()).to(be_equal_to("other"));
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// What happened to a product. On the wire this is the uppercase name the contracts have
+/// always used (`"CREATED"`, `"UPDATED"`, `"DELETED"`), and any other value is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum EventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 3] = [EventKind::Created, EventKind::Updated, EventKind::Deleted];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Created => "CREATED",
+            EventKind::Updated => "UPDATED",
+            EventKind::Deleted => "DELETED",
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An event type other than `CREATED`, `UPDATED` or `DELETED`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownEventKind(pub String);
+
+impl fmt::Display for UnknownEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown event type {:?}, expected CREATED, UPDATED or DELETED",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownEventKind {}
+
+impl FromStr for EventKind {
+    type Err = UnknownEventKind;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == value)
+            .ok_or_else(|| UnknownEventKind(value.to_string()))
+    }
+}
+
+impl TryFrom<String> for EventKind {
+    type Error = UnknownEventKind;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<EventKind> for String {
+    fn from(value: EventKind) -> Self {
+        value.as_str().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventKind, UnknownEventKind};
+    use expectest::prelude::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_each_kind_as_its_uppercase_name() {
+        for (kind, value) in [
+            (EventKind::Created, "CREATED"),
+            (EventKind::Updated, "UPDATED"),
+            (EventKind::Deleted, "DELETED"),
+        ] {
+            expect!(serde_json::to_value(kind).unwrap()).to(be_equal_to(json!(value)));
+            expect!(serde_json::from_value::<EventKind>(json!(value)).unwrap())
+                .to(be_equal_to(kind));
+            expect!(kind.to_string()).to(be_equal_to(value));
+            expect!(value.parse::<EventKind>()).to(be_ok().value(kind));
+        }
+
+        expect!("created".parse::<EventKind>())
+            .to(be_err().value(UnknownEventKind("created".to_string())));
+        expect!(serde_json::from_value::<EventKind>(json!("ARCHIVED")).is_err()).to(be_true());
+    }
+}
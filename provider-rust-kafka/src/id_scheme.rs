@@ -0,0 +1,53 @@
+use uuid::Uuid;
+
+/// How ids are generated for products created without one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdScheme {
+    /// Random UUIDs (`uuid-v4`, the default, which the consumer pacts expect).
+    #[default]
+    UuidV4,
+    /// Time-ordered UUIDs (`uuid-v7`): ids generated later sort after earlier ones, which
+    /// keeps inserts into ordered stores and indexes local.
+    UuidV7,
+}
+
+impl IdScheme {
+    pub const ALL: [IdScheme; 2] = [IdScheme::UuidV4, IdScheme::UuidV7];
+
+    /// The name the scheme is configured by.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdScheme::UuidV4 => "uuid-v4",
+            IdScheme::UuidV7 => "uuid-v7",
+        }
+    }
+
+    /// A fresh id in this scheme.
+    pub fn generate(&self) -> String {
+        match self {
+            IdScheme::UuidV4 => Uuid::new_v4().to_string(),
+            IdScheme::UuidV7 => Uuid::now_v7().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdScheme;
+    use expectest::prelude::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn generates_uuids_of_the_scheme_version() {
+        for (scheme, version) in [(IdScheme::UuidV4, 4), (IdScheme::UuidV7, 7)] {
+            let id = Uuid::parse_str(&scheme.generate()).unwrap();
+            expect!(id.get_version_num()).to(be_equal_to(version));
+        }
+    }
+
+    #[test]
+    fn v7_ids_increase_when_generated_in_sequence() {
+        let ids: Vec<String> = (0..1000).map(|_| IdScheme::UuidV7.generate()).collect();
+        expect!(ids.windows(2).all(|pair| pair[0] < pair[1])).to(be_true());
+    }
+}
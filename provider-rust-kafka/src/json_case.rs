@@ -0,0 +1,127 @@
+use crate::{ProductEvent, ProductType};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+/// How the keys of JSON event payloads are named.
+///
+/// This is part of the contract: consumers and their pacts see the keys exactly as written, so
+/// switching to `Camel` breaks every consumer that still expects the default names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonCase {
+    /// The field names as they are: `type`, `occurred_at` (`snake`, the default, which the
+    /// consumer pacts expect).
+    #[default]
+    Snake,
+    /// camelCase keys, with the product type as `productType`: `productType`, `occurredAt`
+    /// (`camel`).
+    Camel,
+}
+
+impl JsonCase {
+    pub const ALL: [JsonCase; 2] = [JsonCase::Snake, JsonCase::Camel];
+
+    /// The name the convention is configured by.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JsonCase::Snake => "snake",
+            JsonCase::Camel => "camel",
+        }
+    }
+
+    /// Serializes `event` as JSON with keys in this convention.
+    pub fn to_vec(&self, event: &ProductEvent) -> serde_json::Result<Vec<u8>> {
+        match self {
+            JsonCase::Snake => serde_json::to_vec(event),
+            JsonCase::Camel => serde_json::to_vec(&CamelCaseEvent::from(event)),
+        }
+    }
+
+    /// Like [`JsonCase::to_vec`], indented over several lines.
+    pub fn to_vec_pretty(&self, event: &ProductEvent) -> serde_json::Result<Vec<u8>> {
+        match self {
+            JsonCase::Snake => serde_json::to_vec_pretty(event),
+            JsonCase::Camel => serde_json::to_vec_pretty(&CamelCaseEvent::from(event)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CamelCaseEvent<'a> {
+    id: &'a str,
+    name: &'a str,
+    #[serde(rename = "productType")]
+    r#type: &'a ProductType,
+    version: &'a str,
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    occurred_at: Option<DateTime<FixedOffset>>,
+}
+
+impl<'a> From<&'a ProductEvent> for CamelCaseEvent<'a> {
+    fn from(event: &'a ProductEvent) -> Self {
+        CamelCaseEvent {
+            id: &event.id,
+            name: &event.name,
+            r#type: &event.r#type,
+            version: &event.version,
+            event: event.event.as_str(),
+            occurred_at: event.occurred_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonCase;
+    use crate::{EventKind, ProductEvent, ProductType};
+    use chrono::DateTime;
+    use expectest::prelude::*;
+    use serde_json::{json, Value};
+
+    fn event() -> ProductEvent {
+        ProductEvent {
+            id: "42".to_string(),
+            name: "Some Product".to_string(),
+            r#type: ProductType::Range,
+            version: "v2".to_string(),
+            event: EventKind::Updated,
+            occurred_at: Some(DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap()),
+        }
+    }
+
+    fn encoded(case: JsonCase, event: &ProductEvent) -> Value {
+        serde_json::from_slice(&case.to_vec(event).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn snake_case_keeps_the_field_names() {
+        expect!(encoded(JsonCase::Snake, &event())).to(be_equal_to(json!({
+            "id": "42",
+            "name": "Some Product",
+            "type": "Product Range",
+            "version": "v2",
+            "event": "UPDATED",
+            "occurred_at": "2024-05-01T12:00:00Z"
+        })));
+        expect!(JsonCase::Snake.to_vec(&event()).unwrap())
+            .to(be_equal_to(serde_json::to_vec(&event()).unwrap()));
+    }
+
+    #[test]
+    fn camel_case_renames_the_keys() {
+        expect!(encoded(JsonCase::Camel, &event())).to(be_equal_to(json!({
+            "id": "42",
+            "name": "Some Product",
+            "productType": "Product Range",
+            "version": "v2",
+            "event": "UPDATED",
+            "occurredAt": "2024-05-01T12:00:00Z"
+        })));
+        let without_time = ProductEvent {
+            occurred_at: None,
+            ..event()
+        };
+        expect!(encoded(JsonCase::Camel, &without_time).get("occurredAt")).to(be_none());
+    }
+}
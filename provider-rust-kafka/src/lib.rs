@@ -0,0 +1,195 @@
+//! Product domain model and the logic that turns product changes into `ProductEvent`s.
+//!
+//! Shared by the provider binary, its tests and anything else that needs the contract types.
+
+mod event_kind;
+mod id_scheme;
+mod json_case;
+mod product_type;
+mod validation;
+pub mod version;
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub use event_kind::{EventKind, UnknownEventKind};
+pub use id_scheme::IdScheme;
+pub use json_case::JsonCase;
+pub use product_type::ProductType;
+pub use validation::{FieldError, ValidationError};
+pub use version::{
+    increment_version, increment_version_with, is_valid_version, VersionBump, VersionScheme,
+};
+
+/// Kafka header carrying the [`EventKind`] (`CREATED`, `UPDATED` or `DELETED`), so consumers can
+/// filter events without parsing the payload.
+pub const EVENT_TYPE_HEADER: &str = "event-type";
+
+/// Kafka header carrying [`SCHEMA_VERSION`], so consumers can tell payload shapes apart.
+pub const SCHEMA_VERSION_HEADER: &str = "schema-version";
+
+/// Version of the `ProductEvent` payload shape. Bump it whenever the payload changes in a way
+/// consumers have to handle, so they can branch on the `schema-version` header while old and
+/// new events are both around.
+pub const SCHEMA_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct Product {
+    pub id: Option<String>,
+    pub name: String,
+    #[schema(value_type = String, example = "Product Range")]
+    pub r#type: ProductType,
+    pub version: Option<String>,
+}
+
+/// Body of a partial update: only the fields to change. The id comes from the path and the
+/// version is always the next one, so neither can be given.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProductPatch {
+    pub name: Option<String>,
+    #[schema(value_type = Option<String>, example = "Product Range")]
+    pub r#type: Option<ProductType>,
+}
+
+impl ProductPatch {
+    /// The product described by `latest` with the patched fields replaced.
+    pub fn merged_over(self, latest: &ProductEvent) -> Product {
+        Product {
+            id: Some(latest.id.clone()),
+            name: self.name.unwrap_or_else(|| latest.name.clone()),
+            r#type: self.r#type.unwrap_or_else(|| latest.r#type.clone()),
+            version: Some(latest.version.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ProductEvent {
+    pub id: String,
+    pub name: String,
+    #[schema(value_type = String, example = "Product Range")]
+    pub r#type: ProductType,
+    pub version: String,
+    #[schema(value_type = String, example = "CREATED")]
+    pub event: EventKind,
+    /// When the change happened, as RFC 3339 (`2024-05-01T12:00:00Z`). Published as the Kafka
+    /// record timestamp; events without it get the producer's timestamp instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub occurred_at: Option<DateTime<FixedOffset>>,
+}
+
+pub fn create_event(product: Product, kind: EventKind) -> ProductEvent {
+    create_event_with(product, kind, &|| IdScheme::default().generate())
+}
+
+/// Like [`create_event`], but the event's version is written in `scheme`, and products
+/// without an id get one in `ids`.
+pub fn create_event_in(
+    scheme: VersionScheme,
+    ids: IdScheme,
+    product: Product,
+    kind: EventKind,
+) -> ProductEvent {
+    let version = scheme.increment(product.version.as_deref(), VersionBump::Patch);
+    event_from(product, kind, version, &|| ids.generate())
+}
+
+/// Like [`create_event`], but products without an id get one from `id_gen`, so tests can
+/// predict the id of the event.
+pub fn create_event_with(
+    product: Product,
+    kind: EventKind,
+    id_gen: &dyn Fn() -> String,
+) -> ProductEvent {
+    let version = increment_version(product.version.clone());
+    event_from(product, kind, version, id_gen)
+}
+
+fn event_from(
+    product: Product,
+    kind: EventKind,
+    version: String,
+    id_gen: &dyn Fn() -> String,
+) -> ProductEvent {
+    ProductEvent {
+        id: product.id.unwrap_or_else(id_gen),
+        name: product.name,
+        r#type: product.r#type,
+        event: kind,
+        version,
+        occurred_at: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_event_in, create_event_with, EventKind, IdScheme, Product, ProductEvent,
+        ProductType, VersionScheme,
+    };
+    use expectest::prelude::*;
+    use serde_json::json;
+
+    #[test]
+    fn uses_the_id_generator_only_for_products_without_an_id() {
+        let product = |id: Option<&str>| Product {
+            id: id.map(str::to_string),
+            name: "Some Product".to_string(),
+            r#type: ProductType::Range,
+            version: None,
+        };
+        let fixed_id = || "fixed-id".to_string();
+
+        let event = create_event_with(product(None), EventKind::Created, &fixed_id);
+        expect!(event.id).to(be_equal_to("fixed-id"));
+        let event = create_event_with(product(Some("42")), EventKind::Updated, &fixed_id);
+        expect!(event.id).to(be_equal_to("42"));
+    }
+
+    #[test]
+    fn versions_events_in_the_given_scheme() {
+        let product = Product {
+            id: Some("42".to_string()),
+            name: "Some Product".to_string(),
+            r#type: ProductType::Range,
+            version: Some("v1".to_string()),
+        };
+
+        let ids = IdScheme::default();
+        let event = create_event_in(
+            VersionScheme::PlainInt,
+            ids,
+            product.clone(),
+            EventKind::Updated,
+        );
+        expect!(event.version).to(be_equal_to("2"));
+        let event = create_event_in(VersionScheme::PrefixedInt, ids, product, EventKind::Updated);
+        expect!(event.version).to(be_equal_to("v2"));
+    }
+
+    #[test]
+    fn serializes_the_event_time_only_when_known() {
+        let mut event = json!({
+            "id": "42",
+            "name": "Some Product",
+            "type": "Product Range",
+            "version": "v2",
+            "event": "UPDATED"
+        });
+        let parsed: ProductEvent = serde_json::from_value(event.clone()).unwrap();
+        expect!(parsed.occurred_at).to(be_none());
+        expect!(serde_json::to_value(&parsed).unwrap()).to(be_equal_to(event.clone()));
+
+        event["occurred_at"] = json!("2024-05-01T12:00:00+02:00");
+        let parsed: ProductEvent = serde_json::from_value(event.clone()).unwrap();
+        let occurred_at = parsed.occurred_at.unwrap();
+        expect!(occurred_at.timestamp_millis()).to(be_equal_to(1_714_557_600_000));
+        expect!(serde_json::to_value(&parsed).unwrap()).to(be_equal_to(event.clone()));
+
+        event["occurred_at"] = json!("yesterday");
+        expect!(serde_json::from_value::<ProductEvent>(event).is_err()).to(be_true());
+    }
+}
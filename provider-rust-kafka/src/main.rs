@@ -1,57 +1,142 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use rdkafka::config::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
+mod admin;
+mod api_error;
+mod audit;
+#[cfg(feature = "avro")]
+mod avro;
+mod ca_file;
+mod cli;
+#[cfg(feature = "cloudevents")]
+mod cloudevents;
+mod config;
+mod errors;
+mod metrics;
+mod negotiate;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod publisher;
+mod rate_limit;
+mod retry;
+mod routing;
+#[cfg(feature = "validate-schema")]
+mod schema;
+mod selftest;
+mod serializer;
+mod verify;
+mod webhook;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{
+    web, App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder, ResponseError,
+};
+use api_error::{ApiError, ErrorBody, ErrorResponse};
+use audit::{AuditRecord, AuditSink};
+use ca_file::CaFile;
+use clap::Parser;
+use cli::{Cli, Command, ServeArgs};
+use config::{Config, DeliveryMode, EffectiveConfig, ProducerOptions};
+use errors::PublishError;
+use metrics::Metrics;
+use negotiate::Format;
+use product_events::{
+    create_event_in, EventKind, FieldError, IdScheme, Product, ProductEvent, ProductPatch,
+    VersionScheme,
+};
+use publisher::{DryRunPublisher, KafkaPublisher, MessagePublisher};
+use rate_limit::{RateLimiter, API_KEY_HEADER};
+use retry::{jitter, RetryPolicy};
+use routing::TopicRouter;
+use selftest::SELFTEST_TIMEOUT;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-#[derive(Serialize, Deserialize, Clone)]
-pub struct Product {
-    id: Option<String>,
-    name: String,
-    r#type: String,
-    version: Option<String>,
-}
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+use utoipa::OpenApi;
+use webhook::FailureWebhook;
 
-#[derive(Serialize, Deserialize)]
-pub struct ProductEvent {
-    id: String,
-    name: String,
-    r#type: String,
-    version: String,
-    event: String,
-}
+/// HTTP request header the correlation id is read from.
+const HTTP_CORRELATION_ID_HEADER: &str = "X-Correlation-Id";
 
-pub struct ProductEventService {
-    producer: Arc<Mutex<FutureProducer>>,
-    topic: String,
-}
+/// How long `GET /health` waits for the broker to answer.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
 
-pub fn create_event(product: Product, event_type: &str) -> ProductEvent {
-    let version = increment_version(product.version);
-    ProductEvent {
-        id: product
-            .id
-            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
-        name: product.name,
-        r#type: product.r#type,
-        event: event_type.to_string(),
-        version,
-    }
+/// How long a `?sync=true` request waits for the producer to flush.
+const SYNC_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many events are kept per product for `/products/{id}/history`, the oldest are dropped.
+const MAX_HISTORY_PER_PRODUCT: usize = 100;
+
+pub struct ProductEventService {
+    publisher: Box<dyn MessagePublisher>,
+    retry_policy: RetryPolicy,
+    /// Follow DELETED events with a tombstone so log compaction removes the product.
+    tombstone_on_delete: bool,
+    /// The events published for each product id, oldest first, at most
+    /// `MAX_HISTORY_PER_PRODUCT` of them. With [`DeliveryMode::FireAndForget`] these include
+    /// events that were only enqueued and may never be delivered.
+    history: Mutex<HashMap<String, Vec<ProductEvent>>>,
+    /// Held while an event for the product id is versioned and published, so concurrent
+    /// changes to one product get distinct, increasing versions.
+    product_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    metrics: Metrics,
+    version_scheme: VersionScheme,
+    id_scheme: IdScheme,
+    /// Where every published event is recorded, see [`AuditSink`].
+    audit: Option<Box<dyn AuditSink>>,
+    /// Told about every event that could not be published, see [`FailureWebhook`].
+    failure_webhook: Option<FailureWebhook>,
+    /// Whether published events have been acknowledged or only enqueued, see [`DeliveryMode`].
+    delivery_mode: DeliveryMode,
 }
 
 impl ProductEventService {
-    async fn new(broker: &str, topic: &str) -> Self {
-        let producer: FutureProducer = ClientConfig::new()
-            .set("bootstrap.servers", broker)
-            .create()
-            .expect("Producer creation error");
-
+    fn new(publisher: Box<dyn MessagePublisher>, options: &ProducerOptions) -> Self {
         ProductEventService {
-            producer: Arc::new(Mutex::new(producer)),
-            topic: topic.to_string(),
+            publisher,
+            retry_policy: options.retry.clone(),
+            tombstone_on_delete: options.tombstone_on_delete,
+            history: Mutex::new(HashMap::new()),
+            product_locks: Mutex::new(HashMap::new()),
+            metrics: Metrics::new(),
+            version_scheme: VersionScheme::default(),
+            id_scheme: IdScheme::default(),
+            audit: None,
+            failure_webhook: None,
+            delivery_mode: options.delivery_mode,
         }
     }
 
+    /// Writes event versions in `scheme` instead of the default `v`-prefixed counter.
+    fn with_version_scheme(mut self, scheme: VersionScheme) -> Self {
+        self.version_scheme = scheme;
+        self
+    }
+
+    /// Generates ids in `scheme` for products created without one, instead of random UUIDs.
+    fn with_id_scheme(mut self, scheme: IdScheme) -> Self {
+        self.id_scheme = scheme;
+        self
+    }
+
+    /// Records every event once it is published, replays included.
+    fn with_audit_sink(mut self, sink: Box<dyn AuditSink>) -> Self {
+        self.audit = Some(sink);
+        self
+    }
+
+    /// Reports events that still fail to publish after retries to `webhook`.
+    fn with_failure_webhook(mut self, webhook: FailureWebhook) -> Self {
+        self.failure_webhook = Some(webhook);
+        self
+    }
+
     // pub fn create_event(&self, product: Product, event_type: &str) -> ProductEvent {
     //     let version = increment_version(product.version);
     //     ProductEvent {
@@ -65,247 +150,2301 @@ impl ProductEventService {
     //     }
     // }
 
-    async fn publish(&self, event: ProductEvent) {
-        let payload = serde_json::to_string(&event).unwrap();
-        let record = FutureRecord::<String, String>::to(&self.topic).payload(&payload);
-        let producer = self.producer.lock().await;
-        producer
-            .send(record, rdkafka::util::Timeout::Never)
+    /// Makes a single attempt at publishing the event, recording how long the broker took to
+    /// acknowledge it as `latency_ms` and in the publish metrics.
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            product_id = %event.id,
+            event_type = %event.event,
+            correlation_id,
+            latency_ms = tracing::field::Empty,
+        )
+    )]
+    async fn publish(
+        &self,
+        event: &ProductEvent,
+        correlation_id: &str,
+    ) -> Result<(), PublishError> {
+        let started = Instant::now();
+        let result = self.publisher.publish(event, correlation_id).await;
+        let elapsed = started.elapsed();
+        tracing::Span::current().record("latency_ms", elapsed.as_millis() as u64);
+        let outcome = match (&result, self.delivery_mode) {
+            (Err(_), _) => "failure",
+            (Ok(()), DeliveryMode::AwaitDelivery) => "success",
+            (Ok(()), DeliveryMode::FireAndForget) => "enqueued",
+        };
+        self.metrics
+            .record_publish(event.event.as_str(), outcome, elapsed);
+        if let Err(err) = &result {
+            self.metrics.record_delivery_failure(err.category());
+        }
+        result
+    }
+
+    /// Publishes a tombstone for the product, retrying transient failures like
+    /// `publish_with_retry`.
+    async fn publish_tombstone(&self, id: &str) -> Result<(), PublishError> {
+        let result = self
+            .retry_policy
+            .retry(
+                PublishError::is_retriable,
+                |delay| tokio::time::sleep(jitter(delay)),
+                |_| self.publisher.publish_tombstone(id),
+            )
+            .await;
+        if let Err(err) = &result {
+            error!(product_id = id, error = %err, "failed to publish product tombstone");
+        }
+        result
+    }
+
+    /// Publishes the event, retrying transient failures with exponential backoff according to
+    /// the service's `RetryPolicy`. Returns the published event.
+    async fn publish_with_retry(
+        &self,
+        event: ProductEvent,
+        correlation_id: &str,
+    ) -> Result<ProductEvent, PublishError> {
+        let result = self
+            .retry_policy
+            .retry(
+                PublishError::is_retriable,
+                |delay| tokio::time::sleep(jitter(delay)),
+                |attempt| {
+                    if attempt > 1 {
+                        warn!(
+                            product_id = %event.id,
+                            attempt,
+                            max_attempts = self.retry_policy.max_attempts,
+                            "retrying publish of product event"
+                        );
+                    }
+                    self.publish(&event, correlation_id)
+                },
+            )
+            .await;
+        if let Err(err) = &result {
+            error!(
+                product_id = %event.id,
+                event_type = %event.event,
+                error = %err,
+                "failed to publish product event"
+            );
+            self.publisher
+                .dead_letter(&event, correlation_id, err)
+                .await;
+            if let Some(webhook) = &self.failure_webhook {
+                webhook.notify(&event, correlation_id, err).await;
+            }
+        }
+        result?;
+
+        self.audit(&event);
+        let mut history = self.history.lock().await;
+        let events = history.entry(event.id.clone()).or_default();
+        if events.len() >= MAX_HISTORY_PER_PRODUCT {
+            events.remove(0);
+        }
+        events.push(event.clone());
+        Ok(event)
+    }
+
+    /// Appends the published `event` to the audit log, if there is one. A failed write is only
+    /// logged, as the event has been published all the same.
+    fn audit(&self, event: &ProductEvent) {
+        let Some(sink) = &self.audit else {
+            return;
+        };
+        let acknowledged = self.delivery_mode == DeliveryMode::AwaitDelivery;
+        let record = AuditRecord::published(event, self.publisher.topic(event), acknowledged);
+        if let Err(err) = sink.record(&record) {
+            error!(product_id = %event.id, error = %err, "failed to write the audit log");
+        }
+    }
+
+    /// Publishes the last event published for the product again, unchanged and marked as
+    /// replayed. Returns the replayed event, or `None` when no event is known for the product.
+    #[tracing::instrument(skip(self, correlation_id), fields(correlation_id))]
+    async fn replay(
+        &self,
+        id: &str,
+        correlation_id: &str,
+    ) -> Result<Option<ProductEvent>, PublishError> {
+        let Some(event) = self.latest(id).await else {
+            return Ok(None);
+        };
+        self.retry_policy
+            .retry(
+                PublishError::is_retriable,
+                |delay| tokio::time::sleep(jitter(delay)),
+                |_| self.publisher.replay(&event, correlation_id),
+            )
+            .await?;
+        self.audit(&event);
+        Ok(Some(event))
+    }
+
+    /// Publishes the event for a change to `product`. Changes to the same product are
+    /// published one at a time, and each continues the version of the last event published
+    /// for the product; the version the client sent is only used for products this service
+    /// has not published an event for yet.
+    async fn publish_change(
+        &self,
+        product: Product,
+        kind: EventKind,
+        correlation_id: &str,
+    ) -> Result<ProductEvent, PublishError> {
+        // products without an id get a fresh one, so nothing else can be changing them
+        let _guard = match &product.id {
+            Some(id) => Some(self.lock_product(id).await),
+            None => None,
+        };
+        self.publish_change_locked(product, kind, correlation_id)
             .await
-            .unwrap();
     }
 
-    async fn create(&self, product: Product) {
-        let event = create_event(product, "CREATED");
-        self.publish(event).await;
+    /// [`publish_change`](Self::publish_change), for a caller already holding the product's
+    /// lock.
+    async fn publish_change_locked(
+        &self,
+        mut product: Product,
+        kind: EventKind,
+        correlation_id: &str,
+    ) -> Result<ProductEvent, PublishError> {
+        if let Some(id) = &product.id {
+            if let Some(latest) = self.latest(id).await {
+                product.version = Some(latest.version);
+            }
+        }
+        let event = create_event_in(self.version_scheme, self.id_scheme, product, kind);
+        self.publish_with_retry(event, correlation_id).await
     }
 
-    async fn update(&self, product: Product) {
-        let event = create_event(product, "UPDATED");
-        self.publish(event).await;
+    async fn lock_product(&self, id: &str) -> OwnedMutexGuard<()> {
+        let lock = self
+            .product_locks
+            .lock()
+            .await
+            .entry(id.to_string())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
     }
 
-    async fn delete(&self, product: Product) {
-        let event = create_event(product, "DELETED");
-        self.publish(event).await;
+    /// `status` for a published change, or `202 Accepted` when its event was only enqueued,
+    /// see [`DeliveryMode::FireAndForget`].
+    fn published_status(&self, status: StatusCode) -> StatusCode {
+        match self.delivery_mode {
+            DeliveryMode::AwaitDelivery => status,
+            DeliveryMode::FireAndForget => StatusCode::ACCEPTED,
+        }
+    }
+
+    /// Returns the last event published for the product, if any.
+    async fn latest(&self, id: &str) -> Option<ProductEvent> {
+        self.history
+            .lock()
+            .await
+            .get(id)
+            .and_then(|events| events.last())
+            .cloned()
+    }
+
+    /// Returns the events published for the product, newest first, or `None` when there are
+    /// none.
+    async fn history(&self, id: &str) -> Option<Vec<ProductEvent>> {
+        self.history
+            .lock()
+            .await
+            .get(id)
+            .map(|events| events.iter().rev().cloned().collect())
+    }
+
+    /// Whether the broker can be reached within `timeout`.
+    async fn broker_reachable(&self, timeout: Duration) -> bool {
+        self.publisher.is_reachable(timeout).await
+    }
+
+    /// Waits until every buffered message has been delivered or `timeout` elapses.
+    async fn flush(&self, timeout: Duration) -> Result<(), PublishError> {
+        self.publisher.flush(timeout).await
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(product_id = product.id.as_deref(), event_type = %EventKind::Created, correlation_id)
+    )]
+    async fn create(
+        &self,
+        product: Product,
+        correlation_id: &str,
+    ) -> Result<ProductEvent, PublishError> {
+        self.publish_change(product, EventKind::Created, correlation_id)
+            .await
     }
-}
 
-fn increment_version(version: Option<String>) -> String {
-    match version {
-        Some(v) => {
-            let num: u32 = v[1..].parse().unwrap();
-            format!("v{}", num + 1)
+    /// Publishes a CREATED event for every product concurrently. Returns the published event,
+    /// or the error, for each product in order.
+    #[tracing::instrument(skip_all, fields(count = products.len(), correlation_id))]
+    async fn create_many(
+        &self,
+        products: Vec<Product>,
+        correlation_id: &str,
+    ) -> Vec<Result<ProductEvent, PublishError>> {
+        let publishes = products
+            .into_iter()
+            .map(|product| self.publish_change(product, EventKind::Created, correlation_id));
+        futures::future::join_all(publishes).await
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(product_id = product.id.as_deref(), event_type = %EventKind::Updated, correlation_id)
+    )]
+    async fn update(
+        &self,
+        product: Product,
+        correlation_id: &str,
+    ) -> Result<ProductEvent, PublishError> {
+        self.publish_change(product, EventKind::Updated, correlation_id)
+            .await
+    }
+
+    /// Like [`update`](Self::update), but only if the last event published for the product is
+    /// at the version in `if_match`, checked under the product's lock so no other change can
+    /// slip in between.
+    #[tracing::instrument(
+        skip_all,
+        fields(product_id = product.id.as_deref(), event_type = %EventKind::Updated, correlation_id)
+    )]
+    async fn update_if_match(
+        &self,
+        product: Product,
+        if_match: &str,
+        correlation_id: &str,
+    ) -> Result<ProductEvent, ApiError> {
+        let id = product.id.clone().unwrap_or_default();
+        let _guard = self.lock_product(&id).await;
+        let current = self.latest(&id).await.map(|event| event.version);
+        if !version_matches(if_match, current.as_deref()) {
+            return Err(ApiError::VersionMismatch {
+                expected: if_match.to_string(),
+                actual: current,
+            });
         }
-        None => "v1".to_string(),
+        Ok(self
+            .publish_change_locked(product, EventKind::Updated, correlation_id)
+            .await?)
     }
+
+    /// Publishes an UPDATED event for the product with `patch` merged over its last event.
+    /// Returns `None` when no event is known for the product, or its last event deleted it.
+    #[tracing::instrument(
+        skip(self, patch, correlation_id),
+        fields(event_type = %EventKind::Updated, correlation_id)
+    )]
+    async fn patch(
+        &self,
+        id: &str,
+        patch: ProductPatch,
+        correlation_id: &str,
+    ) -> Result<Option<ProductEvent>, ApiError> {
+        let _guard = self.lock_product(id).await;
+        let Some(latest) = self
+            .latest(id)
+            .await
+            .filter(|event| event.event != EventKind::Deleted)
+        else {
+            return Ok(None);
+        };
+        let product = patch.merged_over(&latest);
+        product.validate()?;
+        let event = create_event_in(
+            self.version_scheme,
+            self.id_scheme,
+            product,
+            EventKind::Updated,
+        );
+        Ok(Some(self.publish_with_retry(event, correlation_id).await?))
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(product_id = product.id.as_deref(), event_type = %EventKind::Deleted, correlation_id)
+    )]
+    async fn delete(&self, product: Product, correlation_id: &str) -> Result<(), PublishError> {
+        let event = self
+            .publish_change(product, EventKind::Deleted, correlation_id)
+            .await?;
+        let id = event.id;
+        if self.tombstone_on_delete {
+            self.publish_tombstone(&id).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether an `If-Match` value accepts a product's `current` version: `*` accepts any version,
+/// anything else must be the version itself, quoted or not.
+fn version_matches(if_match: &str, current: Option<&str>) -> bool {
+    let Some(current) = current else {
+        return false;
+    };
+    let expected = if_match.trim();
+    expected == "*" || expected.trim_matches('"') == current
+}
+
+/// Returns the request's `X-Correlation-Id`, or a new id when the caller did not send one.
+fn extract_or_generate_correlation_id(req: &HttpRequest) -> String {
+    req.headers()
+        .get(HTTP_CORRELATION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.trim().is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
 }
 
+/// OpenAPI description of the product endpoints, served at `GET /openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Product events API"),
+    paths(create_product, update_product, patch_product, delete_product),
+    components(schemas(
+        Product,
+        ProductPatch,
+        ProductEvent,
+        ErrorResponse,
+        ErrorBody,
+        FieldError
+    ))
+)]
+struct ApiDoc;
+
+async fn openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Publishes a CREATED event for a new product.
+#[utoipa::path(
+    post,
+    path = "/products",
+    params(
+        ("X-Correlation-Id" = Option<String>, Header, description = "Copied onto the event"),
+        ("sync" = Option<bool>, Query, description = "Wait until every buffered event is delivered"),
+    ),
+    request_body = Product,
+    responses(
+        (status = 201, description = "CREATED event published",
+            content((ProductEvent = "application/json"), (ProductEvent = "application/xml")),
+            headers(("Location" = String, description = "Path of the created product"))),
+        (status = 202, description = "CREATED event enqueued, with `KAFKA_DELIVERY_MODE=fire-and-forget`",
+            content((ProductEvent = "application/json"), (ProductEvent = "application/xml"))),
+        (status = 400, description = "Invalid product", body = ErrorResponse),
+        (status = 413, description = "The event is larger than the payload limit", body = ErrorResponse),
+        (status = 500, description = "The event could not be published", body = ErrorResponse),
+        (status = 503, description = "The producer queue is full, retry after `Retry-After` seconds",
+            body = ErrorResponse, headers(("Retry-After" = u64))),
+    )
+)]
 async fn create_product(
+    req: HttpRequest,
     service: web::Data<Arc<ProductEventService>>,
+    delivery: web::Query<Delivery>,
     product: web::Json<Product>,
+) -> Result<HttpResponse, ApiError> {
+    product.validate()?;
+    let correlation_id = extract_or_generate_correlation_id(&req);
+    let event = service
+        .create(product.into_inner(), &correlation_id)
+        .await?;
+    delivery.settle(&service).await?;
+    let mut response = delivery.response(&service, StatusCode::CREATED);
+    response.insert_header((header::LOCATION, format!("/products/{}", event.id)));
+    Ok(Format::from_request(&req).respond(response, &event))
+}
+
+/// The `?sync=true` query parameter of the create, update and delete endpoints.
+///
+/// Every change already waits for the broker to acknowledge its own event. A sync request
+/// also flushes the producer before responding, so nothing the service buffered before it is
+/// still in flight either, trading latency for a stronger guarantee.
+#[derive(Debug, Default, Deserialize)]
+struct Delivery {
+    #[serde(default)]
+    sync: bool,
+}
+
+impl Delivery {
+    /// Flushes the producer if the request asked for synchronous delivery.
+    async fn settle(&self, service: &ProductEventService) -> Result<(), ApiError> {
+        if self.sync {
+            service.flush(SYNC_FLUSH_TIMEOUT).await?;
+        }
+        Ok(())
+    }
+
+    /// The response to a change that succeeded with `status`. Events that were only enqueued
+    /// get `202 Accepted` instead, unless the request waited for them to be flushed.
+    fn response(&self, service: &ProductEventService, status: StatusCode) -> HttpResponseBuilder {
+        if self.sync {
+            HttpResponse::build(status)
+        } else {
+            HttpResponse::build(service.published_status(status))
+        }
+    }
+}
+
+/// Limits JSON request bodies to `max_body_bytes`. Bodies that are too large or are not valid
+/// JSON for the route are rejected with `400` and an `invalid_body` error naming the problem.
+fn json_config(max_body_bytes: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(max_body_bytes)
+        .error_handler(|err, _req| {
+            let message = match &err {
+                JsonPayloadError::Overflow { limit }
+                | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                    format!("request body is larger than {} bytes", limit)
+                }
+                JsonPayloadError::ContentType => "expected a JSON request body".to_string(),
+                JsonPayloadError::Deserialize(err) => format!("invalid JSON body: {}", err),
+                err => format!("could not read request body: {}", err),
+            };
+            let response = ApiError::InvalidBody(message).error_response();
+            InternalError::from_response(err, response).into()
+        })
+}
+
+/// Outcome of one product in a `POST /products/bulk` request.
+#[derive(Serialize, Debug)]
+struct BulkItemResult {
+    index: usize,
+    id: Option<String>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorBody>,
+}
+
+impl BulkItemResult {
+    /// The item failed with `err`, reported with the status and `error` object it would get on
+    /// its own.
+    fn failed(index: usize, id: Option<String>, err: ApiError) -> Self {
+        BulkItemResult {
+            index,
+            id,
+            status: err.status_code().as_u16(),
+            error: Some(err.body()),
+        }
+    }
+}
+
+/// Creates every valid product in the batch and reports a status per item with
+/// `207 Multi-Status`. Invalid products are reported as `400` without failing the others.
+async fn create_products(
+    req: HttpRequest,
+    service: web::Data<Arc<ProductEventService>>,
+    products: web::Json<Vec<Product>>,
 ) -> impl Responder {
-    service.create(product.into_inner()).await;
-    HttpResponse::Created().finish()
+    let mut results = vec![];
+    let mut valid = vec![];
+    for (index, product) in products.into_inner().into_iter().enumerate() {
+        match product.validate() {
+            Ok(()) => valid.push((index, product)),
+            Err(err) => results.push(BulkItemResult::failed(index, product.id, err.into())),
+        }
+    }
+
+    let (indexes, valid): (Vec<_>, Vec<_>) = valid.into_iter().unzip();
+    let correlation_id = extract_or_generate_correlation_id(&req);
+    let published = service.create_many(valid, &correlation_id).await;
+    for (index, result) in indexes.into_iter().zip(published) {
+        results.push(match result {
+            Ok(event) => BulkItemResult {
+                index,
+                id: Some(event.id),
+                status: service.published_status(StatusCode::CREATED).as_u16(),
+                error: None,
+            },
+            Err(err) => BulkItemResult::failed(index, None, err.into()),
+        });
+    }
+    results.sort_by_key(|result| result.index);
+
+    HttpResponse::MultiStatus().json(results)
+}
+
+/// Uses the `{id}` path segment as the product id, rejecting a body that names another product.
+fn with_path_id(id: String, product: Product) -> Result<Product, ApiError> {
+    match &product.id {
+        Some(body_id) if *body_id != id => Err(ApiError::IdMismatch {
+            body_id: body_id.clone(),
+            path_id: id,
+        }),
+        _ => Ok(Product {
+            id: Some(id),
+            ..product
+        }),
+    }
 }
 
+/// Publishes an UPDATED event for the product with the given id. With `If-Match`, only if the
+/// product's last event is at that version.
+#[utoipa::path(
+    put,
+    path = "/products/{id}",
+    params(
+        ("id" = String, Path, description = "Product id"),
+        ("X-Correlation-Id" = Option<String>, Header, description = "Copied onto the event"),
+        ("If-Match" = Option<String>, Header, description = "The version the product must be at, or `*` for any"),
+        ("sync" = Option<bool>, Query, description = "Wait until every buffered event is delivered"),
+    ),
+    request_body = Product,
+    responses(
+        (status = 200, description = "UPDATED event published",
+            content((ProductEvent = "application/json"), (ProductEvent = "application/xml"))),
+        (status = 202, description = "UPDATED event enqueued, with `KAFKA_DELIVERY_MODE=fire-and-forget`",
+            content((ProductEvent = "application/json"), (ProductEvent = "application/xml"))),
+        (status = 400, description = "Invalid product", body = ErrorResponse),
+        (status = 409, description = "The body id does not match the path id", body = ErrorResponse),
+        (status = 412, description = "The product is not at the `If-Match` version", body = ErrorResponse),
+        (status = 413, description = "The event is larger than the payload limit", body = ErrorResponse),
+        (status = 500, description = "The event could not be published", body = ErrorResponse),
+        (status = 503, description = "The producer queue is full, retry after `Retry-After` seconds",
+            body = ErrorResponse, headers(("Retry-After" = u64))),
+    )
+)]
 async fn update_product(
+    req: HttpRequest,
     service: web::Data<Arc<ProductEventService>>,
+    id: web::Path<String>,
+    delivery: web::Query<Delivery>,
     product: web::Json<Product>,
-) -> impl Responder {
-    service.update(product.into_inner()).await;
-    HttpResponse::Ok().finish()
+) -> Result<HttpResponse, ApiError> {
+    let product = with_path_id(id.into_inner(), product.into_inner())?;
+    product.validate()?;
+    let correlation_id = extract_or_generate_correlation_id(&req);
+    let event = match req.headers().get(header::IF_MATCH) {
+        // a value that is not text cannot name a version, so it never matches
+        Some(if_match) => {
+            let if_match = if_match.to_str().unwrap_or_default();
+            service
+                .update_if_match(product, if_match, &correlation_id)
+                .await?
+        }
+        None => service.update(product, &correlation_id).await?,
+    };
+    delivery.settle(&service).await?;
+    let response = delivery.response(&service, StatusCode::OK);
+    Ok(Format::from_request(&req).respond(response, &event))
 }
 
+/// Publishes an UPDATED event for the product with the given id, changing only the fields in
+/// the body and keeping the rest from the product's last event.
+#[utoipa::path(
+    patch,
+    path = "/products/{id}",
+    params(
+        ("id" = String, Path, description = "Product id"),
+        ("X-Correlation-Id" = Option<String>, Header, description = "Copied onto the event"),
+        ("sync" = Option<bool>, Query, description = "Wait until every buffered event is delivered"),
+    ),
+    request_body = ProductPatch,
+    responses(
+        (status = 200, description = "UPDATED event published",
+            content((ProductEvent = "application/json"), (ProductEvent = "application/xml"))),
+        (status = 202, description = "UPDATED event enqueued, with `KAFKA_DELIVERY_MODE=fire-and-forget`",
+            content((ProductEvent = "application/json"), (ProductEvent = "application/xml"))),
+        (status = 400, description = "Invalid product", body = ErrorResponse),
+        (status = 404, description = "No event is known for the product", body = ErrorResponse),
+        (status = 413, description = "The event is larger than the payload limit", body = ErrorResponse),
+        (status = 500, description = "The event could not be published", body = ErrorResponse),
+        (status = 503, description = "The producer queue is full, retry after `Retry-After` seconds",
+            body = ErrorResponse, headers(("Retry-After" = u64))),
+    )
+)]
+async fn patch_product(
+    req: HttpRequest,
+    service: web::Data<Arc<ProductEventService>>,
+    id: web::Path<String>,
+    delivery: web::Query<Delivery>,
+    patch: web::Json<ProductPatch>,
+) -> Result<HttpResponse, ApiError> {
+    let correlation_id = extract_or_generate_correlation_id(&req);
+    match service
+        .patch(&id, patch.into_inner(), &correlation_id)
+        .await?
+    {
+        Some(event) => {
+            delivery.settle(&service).await?;
+            let response = delivery.response(&service, StatusCode::OK);
+            Ok(Format::from_request(&req).respond(response, &event))
+        }
+        None => Err(ApiError::NotFound(format!(
+            "no event is known for product {}",
+            id
+        ))),
+    }
+}
+
+/// Publishes a DELETED event for the product with the given id.
+#[utoipa::path(
+    delete,
+    path = "/products/{id}",
+    params(
+        ("id" = String, Path, description = "Product id"),
+        ("X-Correlation-Id" = Option<String>, Header, description = "Copied onto the event"),
+        ("sync" = Option<bool>, Query, description = "Wait until every buffered event is delivered"),
+    ),
+    request_body = Product,
+    responses(
+        (status = 200, description = "DELETED event published"),
+        (status = 202, description = "DELETED event enqueued, with `KAFKA_DELIVERY_MODE=fire-and-forget`"),
+        (status = 400, description = "Invalid product", body = ErrorResponse),
+        (status = 409, description = "The body id does not match the path id", body = ErrorResponse),
+        (status = 413, description = "The event is larger than the payload limit", body = ErrorResponse),
+        (status = 500, description = "The event could not be published", body = ErrorResponse),
+        (status = 503, description = "The producer queue is full, retry after `Retry-After` seconds",
+            body = ErrorResponse, headers(("Retry-After" = u64))),
+    )
+)]
 async fn delete_product(
+    req: HttpRequest,
     service: web::Data<Arc<ProductEventService>>,
+    id: web::Path<String>,
+    delivery: web::Query<Delivery>,
     product: web::Json<Product>,
-) -> impl Responder {
-    service.delete(product.into_inner()).await;
+) -> Result<HttpResponse, ApiError> {
+    let product = with_path_id(id.into_inner(), product.into_inner())?;
+    product.validate()?;
+    let correlation_id = extract_or_generate_correlation_id(&req);
+    service.delete(product, &correlation_id).await?;
+    delivery.settle(&service).await?;
+    Ok(delivery.response(&service, StatusCode::OK).finish())
+}
+
+async fn get_product(
+    req: HttpRequest,
+    service: web::Data<Arc<ProductEventService>>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    match service.latest(&id).await {
+        Some(event) => Ok(Format::from_request(&req).respond(HttpResponse::Ok(), &event)),
+        None => Err(ApiError::NotFound(format!(
+            "no event is known for product {}",
+            id
+        ))),
+    }
+}
+
+/// Every event published for the product, newest first, deletions included. With
+/// `KAFKA_DELIVERY_MODE=fire-and-forget` this includes events that were only enqueued, which
+/// may yet fail to be delivered.
+async fn product_history(
+    service: web::Data<Arc<ProductEventService>>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    match service.history(&id).await {
+        Some(events) => Ok(HttpResponse::Ok().json(events)),
+        None => Err(ApiError::NotFound(format!(
+            "no event is known for product {}",
+            id
+        ))),
+    }
+}
+
+/// Re-publishes the last event of the product, for troubleshooting consumers.
+async fn replay_product(
+    req: HttpRequest,
+    service: web::Data<Arc<ProductEventService>>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let correlation_id = extract_or_generate_correlation_id(&req);
+    match service.replay(&id, &correlation_id).await? {
+        Some(event) => Ok(Format::from_request(&req).respond(HttpResponse::Ok(), &event)),
+        None => Err(ApiError::NotFound(format!(
+            "no event is known for product {}",
+            id
+        ))),
+    }
+}
+
+/// The configuration the producer was created with, credentials redacted. Only served with
+/// `DEBUG_ENDPOINTS`.
+async fn debug_config(config: web::Data<EffectiveConfig>) -> impl Responder {
+    HttpResponse::Ok().json(config.get_ref())
+}
+
+/// Readiness: whether the Kafka broker is reachable.
+async fn health(service: web::Data<Arc<ProductEventService>>) -> impl Responder {
+    if service.broker_reachable(HEALTH_CHECK_TIMEOUT).await {
+        HttpResponse::Ok().json(json!({ "kafka": "up" }))
+    } else {
+        HttpResponse::ServiceUnavailable().json(json!({ "kafka": "down" }))
+    }
+}
+
+async fn metrics(service: web::Data<Arc<ProductEventService>>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(service.metrics.render())
+}
+
+/// Middleware recording the latency of every request in `http_request_duration_seconds`,
+/// labelled with the route template rather than the concrete path, so the number of series
+/// stays bounded. Requests that match no route are recorded as `unmatched`.
+async fn record_request_latency(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let service = req
+        .app_data::<web::Data<Arc<ProductEventService>>>()
+        .cloned();
+    let method = req.method().to_string();
+    let path = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let response = next.call(req).await;
+    if let Some(service) = service {
+        let status = match &response {
+            Ok(response) => response.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+        service
+            .metrics
+            .record_http_request(&method, &path, status.as_u16(), started.elapsed());
+    }
+    response
+}
+
+/// Middleware rate limiting the requests that change products (anything but `GET` and
+/// `HEAD`) per client, when a [`RateLimiter`] is configured. Clients are told apart by their
+/// `X-Api-Key` header, or else by their address, and get a `429` once over the limit.
+async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>();
+    let read_only = matches!(*req.method(), Method::GET | Method::HEAD);
+    if let (Some(limiter), false) = (limiter, read_only) {
+        let client = match req.headers().get(API_KEY_HEADER) {
+            Some(key) => format!("key:{}", String::from_utf8_lossy(key.as_bytes())),
+            None => match req.peer_addr() {
+                Some(addr) => format!("ip:{}", addr.ip()),
+                None => "unknown".to_string(),
+            },
+        };
+        if let Err(retry_after) = limiter.acquire(&client, Instant::now()) {
+            warn!(client, "rate limited a client");
+            let response = ApiError::RateLimited(retry_after).error_response();
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Liveness: the process is up and serving requests.
+async fn live() -> impl Responder {
     HttpResponse::Ok().finish()
 }
 
+/// Creates the topic if asked to, then the Kafka publisher with everything the configuration
+/// enables. Exits the process when any of that fails.
+async fn kafka_publisher(config: &Config) -> KafkaPublisher {
+    if let Some(settings) = &config.auto_create_topic {
+        for topic in config.all_topics() {
+            let created = admin::ensure_topic_exists(
+                &config.broker,
+                config.producer.security.as_ref(),
+                topic,
+                settings.partitions,
+                settings.replication_factor,
+            )
+            .await;
+            if let Err(err) = created {
+                error!(error = %err, topic, "failed to create the topic");
+                std::process::exit(1);
+            }
+        }
+    }
+    let startup = RetryPolicy {
+        max_attempts: config.startup_retries + 1,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(10),
+    };
+    let publisher = startup
+        .retry(
+            |_| true,
+            |delay| tokio::time::sleep(jitter(delay)),
+            |attempt| {
+                if attempt > 1 {
+                    warn!(attempt, "retrying creation of the Kafka producer");
+                }
+                futures::future::ready(KafkaPublisher::new(
+                    &config.broker,
+                    &config.topic,
+                    &config.producer,
+                ))
+            },
+        )
+        .await;
+    let publisher = match publisher {
+        Ok(publisher) => publisher.with_router(config.topics.clone()),
+        Err(err) => {
+            error!(error = %err, broker = %config.broker, "failed to create the Kafka producer");
+            std::process::exit(1);
+        }
+    };
+    let publisher = match &config.dlq_topic {
+        Some(topic) => publisher.with_dlq(topic),
+        None => publisher,
+    }
+    .with_json_case(config.event_json_case)
+    .with_pretty_json(config.pretty_json);
+    #[cfg(feature = "cloudevents")]
+    let publisher = publisher.with_cloudevents(config.cloudevents.clone());
+    #[cfg(feature = "avro")]
+    let publisher = match &config.schema_registry_url {
+        Some(url) => {
+            let registry = avro::SchemaRegistryClient::new(url);
+            match avro::AvroEncoder::register(&registry, &config.topic).await {
+                Ok(encoder) => publisher.with_serializer(Box::new(encoder)),
+                Err(err) => {
+                    error!(error = %err, "failed to register the ProductEvent Avro schema");
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => publisher,
+    };
+    publisher
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let broker = "localhost:9092";
-    let topic = "products";
-    let service = Arc::new(ProductEventService::new(broker, topic).await);
+    // RUST_LOG controls the log level, e.g. `RUST_LOG=provider_rust_kafka=debug`
+    // logs go to stderr, leaving stdout to the audit log (`AUDIT_SINK=stdout`)
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    match Cli::parse()
+        .command
+        .unwrap_or(Command::Serve(ServeArgs::default()))
+    {
+        Command::Serve(args) => serve(args).await,
+        Command::Verify(args) => {
+            let overrides = args.overrides();
+            let lookup = cli::lookup_with(&overrides, |name| std::env::var(name).ok());
+            if let Err(err) = verify::verify(lookup, args.pact_file).await {
+                error!("{:#}", err);
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
 
-    HttpServer::new(move || {
+/// Publishes a synthetic event to a temporary topic with `publisher` and reads it back, so a
+/// broker that cannot be reached, rejects our credentials or cannot take our events is found
+/// before any traffic is served. Exits the process when the round trip fails.
+async fn self_test(config: &Config, publisher: KafkaPublisher) -> KafkaPublisher {
+    let topic = selftest::selftest_topic(&config.topic);
+    let security = config.producer.security.as_ref();
+    if let Err(err) = admin::ensure_topic_exists(&config.broker, security, &topic, 1, -1).await {
+        error!(error = %err, topic = %topic, "failed to create the self-test topic");
+        std::process::exit(1);
+    }
+    let publisher = publisher.with_router(TopicRouter::new(&topic));
+    let event = selftest::synthetic_event();
+    let receive = selftest::receive(config, &topic, &event.id);
+    let result = selftest::round_trip(&publisher, &event, receive, SELFTEST_TIMEOUT).await;
+    if let Err(err) = admin::delete_topic(&config.broker, security, &topic).await {
+        warn!(error = %err, topic = %topic, "failed to delete the self-test topic");
+    }
+    match result {
+        Ok(latency) => info!(latency_ms = latency.as_millis() as u64, "self-test passed"),
+        Err(err) => {
+            error!(error = %err, "self-test failed");
+            std::process::exit(1);
+        }
+    }
+    publisher.with_router(config.topics.clone())
+}
+
+/// Runs the HTTP API until it is stopped, then flushes any events still buffered.
+async fn serve(args: ServeArgs) -> std::io::Result<()> {
+    let overrides = args.overrides();
+    let lookup = cli::lookup_with(&overrides, |name| std::env::var(name).ok());
+    let mut config = match Config::from_lookup(lookup) {
+        Ok(config) => config,
+        Err(err) => {
+            error!(error = %err, "invalid configuration");
+            std::process::exit(1);
+        }
+    };
+    // held until the producer has flushed, dropping it removes the CA file
+    let _ca_file = match config.producer.security.as_mut().map(CaFile::materialize) {
+        Some(Ok(ca_file)) => {
+            if let Some(ca_file) = &ca_file {
+                info!(path = %ca_file.path().display(), "wrote KAFKA_SSL_CA_PEM to a file");
+            }
+            ca_file
+        }
+        Some(Err(err)) => {
+            error!(error = %err, "failed to write KAFKA_SSL_CA_PEM to a file");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let publisher: Box<dyn MessagePublisher> = if config.dry_run {
+        warn!("DRY_RUN is set, product events are logged instead of published to Kafka");
+        Box::new(DryRunPublisher::new(config.topics.clone()).with_json_case(config.event_json_case))
+    } else {
+        let publisher = kafka_publisher(&config).await;
+        if config.selftest {
+            Box::new(self_test(&config, publisher).await)
+        } else {
+            Box::new(publisher)
+        }
+    };
+    let service = ProductEventService::new(publisher, &config.producer)
+        .with_version_scheme(config.version_scheme)
+        .with_id_scheme(config.id_scheme);
+    let service = match &config.audit_sink {
+        Some(target) => match target.open() {
+            Ok(sink) => service.with_audit_sink(sink),
+            Err(err) => {
+                error!(error = %err, "failed to open the audit log");
+                std::process::exit(1);
+            }
+        },
+        None => service,
+    };
+    let service = match &config.failure_webhook_url {
+        Some(url) => service.with_failure_webhook(FailureWebhook::new(url)),
+        None => service,
+    };
+    let service = Arc::new(service);
+    // one limiter for all workers, so the limit holds per client rather than per worker
+    let rate_limiter = config
+        .http_rate_limit_per_sec
+        .map(|limit| web::Data::new(RateLimiter::per_second(limit)));
+    let effective_config = config
+        .debug_endpoints
+        .then(|| web::Data::new(config.effective()));
+
+    let app_service = service.clone();
+    // actix stops the server gracefully on Ctrl-C / SIGTERM, after which we flush anything
+    // the producer still has buffered so those events are not lost.
+    let server = HttpServer::new(move || {
+        let rate_limiter = rate_limiter.clone();
+        let effective_config = effective_config.clone();
         App::new()
-            .app_data(web::Data::new(service.clone()))
+            .wrap(from_fn(rate_limit))
+            .wrap(from_fn(record_request_latency))
+            .configure(move |cfg| {
+                if let Some(rate_limiter) = rate_limiter {
+                    cfg.app_data(rate_limiter);
+                }
+                if let Some(effective_config) = effective_config {
+                    cfg.app_data(effective_config)
+                        .route("/debug/config", web::get().to(debug_config));
+                }
+            })
+            .app_data(web::Data::new(app_service.clone()))
+            .app_data(json_config(config.http_max_body_bytes))
+            .route("/health", web::get().to(health))
+            .route("/live", web::get().to(live))
+            .route("/metrics", web::get().to(metrics))
+            .route("/openapi.json", web::get().to(openapi))
             .route("/products", web::post().to(create_product))
+            .route("/products/bulk", web::post().to(create_products))
+            .route("/products/{id}", web::get().to(get_product))
             .route("/products/{id}", web::put().to(update_product))
+            .route("/products/{id}", web::patch().to(patch_product))
             .route("/products/{id}", web::delete().to(delete_product))
-    })
-    .bind("127.0.0.1:8081")?
-    .run()
-    .await
+            .route("/products/{id}/history", web::get().to(product_history))
+            .route("/products/{id}/replay", web::post().to(replay_product))
+    });
+    let server = match config.http_workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    server
+        .bind(&config.http_bind_addr)
+        .map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!(
+                    "failed to bind HTTP server to {}: {}",
+                    config.http_bind_addr, err
+                ),
+            )
+        })?
+        .run()
+        .await?;
+
+    let _ = service.flush(Duration::from_secs(10)).await;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::{create_event, Product};
-    use actix_web::http::header::HeaderName;
-    use actix_web::http::header::HeaderValue;
-    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-    use async_trait::async_trait;
-    use base64::{engine::general_purpose, Engine as _};
+    use crate::audit::{AuditRecord, FileAuditSink};
+    use crate::config::{Config, DeliveryMode};
+    use crate::errors::PublishError;
+    use crate::publisher::{
+        DryRunPublisher, KafkaPublisher, MessagePublisher, RecordingPublisher,
+        CORRELATION_ID_HEADER, DLQ_ERROR_HEADER,
+    };
+    use crate::rate_limit::RateLimiter;
+    use crate::routing::TopicRouter;
+    use crate::webhook::FailureWebhook;
+    use crate::{
+        create_product, create_products, debug_config, delete_product,
+        extract_or_generate_correlation_id, get_product, json_config, live, openapi, patch_product,
+        product_history, rate_limit, record_request_latency, replay_product, update_product,
+        with_path_id, ProducerOptions, ProductEventService, RetryPolicy,
+    };
+    use actix_web::http::header::{self, HeaderValue};
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, read_body, read_body_json, TestRequest};
+    use actix_web::{web, App};
     use expectest::prelude::*;
-    use maplit::*;
-    use pact_models::provider_states::ProviderState;
-    use pact_verifier::{
-        callback_executors::ProviderStateExecutor, verify_provider_async, FilterInfo,
-        NullRequestFilterExecutor, PactSource, ProviderInfo, ProviderTransport,
-        VerificationOptions,
+    use product_events::{
+        create_event, EventKind, FieldError, Product, ProductEvent, VersionScheme,
+        EVENT_TYPE_HEADER,
     };
+    use rdkafka::consumer::{BaseConsumer, Consumer};
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use rdkafka::message::{Headers, Message};
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::types::{RDKafkaApiKey, RDKafkaRespErr};
+    use rdkafka::{Offset, TopicPartitionList};
     use serde_json::json;
     use serde_json::Value;
-    use std::{collections::HashMap, env, path::PathBuf, sync::Arc};
-    use tokio::sync::oneshot;
-    #[derive(Debug)]
-    struct DummyProviderStateExecutor;
-
-    #[async_trait]
-    impl ProviderStateExecutor for DummyProviderStateExecutor {
-        async fn call(
-            self: Arc<Self>,
-            _interaction_id: Option<String>,
-            _provider_state: &ProviderState,
-            _setup: bool,
-            _client: std::option::Option<&reqwest::Client>,
-        ) -> anyhow::Result<HashMap<String, Value>> {
-            Ok(hashmap! {})
-        }
-
-        fn teardown(self: &Self) -> bool {
-            return false;
-        }
-    }
-
-    async fn start_message_proxy() -> oneshot::Sender<()> {
-        async fn handle_request(
-            req: HttpRequest,
-            body: web::Json<serde_json::Value>,
-        ) -> impl Responder {
-            println!("Incoming request path: {}", req.path());
-            println!("Incoming request path: {}", req.method());
-            println!("Incoming request body: {}", body);
-            println!("Incoming request body: {}", body["description"]);
-
-            match body["description"].as_str() {
-                Some("a product event update") => {
-                    let product = Product {
-                        id: Some("some-uuid-1234-5678".to_string()),
-                        name: "Some Product".to_string(),
-                        r#type: "Product Range".to_string(),
-                        version: Some("v1".to_string()),
-                    };
-                    let event_type = "UPDATED";
-                    let product_event = create_event(product, event_type);
-                    let mut response = HttpResponse::Ok().json(product_event);
-                    let metadata = json!({
-                      "kafka_topic": "products"
-                    });
-                    let encoded_metadata =
-                        general_purpose::STANDARD.encode(metadata.to_string());
-                    response.headers_mut().insert(
-                        HeaderName::from_static("pact-message-metadata"),
-                        HeaderValue::from_str(&encoded_metadata).unwrap(),
-                    );
-                    response
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use utoipa::openapi::HttpMethod;
+
+    #[tokio::test]
+    async fn dead_letters_events_that_cannot_be_published() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("products", 1, 1).unwrap();
+        cluster.create_topic("products-dlq", 1, 1).unwrap();
+        // a permanent, non-retriable failure for the first produce request only
+        cluster.request_errors(
+            RDKafkaApiKey::Produce,
+            &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_MSG_SIZE_TOO_LARGE],
+        );
+        let producer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        let publisher =
+            KafkaPublisher::with_producer(producer, "products", &ProducerOptions::default())
+                .with_dlq("products-dlq");
+        let service = ProductEventService::new(Box::new(publisher), &ProducerOptions::default());
+
+        let product = Product {
+            id: Some("42".to_string()),
+            ..product("Some Product", "Product Range", None)
+        };
+        expect!(service.create(product, "some-correlation-id").await).to(be_err());
+
+        let consumer: BaseConsumer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("group.id", "dlq-test")
+            .create()
+            .unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions
+            .add_partition_offset("products-dlq", 0, Offset::Beginning)
+            .unwrap();
+        consumer.assign(&partitions).unwrap();
+        let message = (0..100)
+            .find_map(|_| consumer.poll(Duration::from_millis(100)))
+            .expect("no dead letter was produced")
+            .unwrap();
+
+        let event: Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+        expect!(event["id"].as_str()).to(be_some().value("42"));
+        let error = message
+            .headers()
+            .and_then(|headers| headers.iter().find(|h| h.key == DLQ_ERROR_HEADER))
+            .and_then(|header| header.value);
+        expect!(error.is_some()).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn deletes_follow_the_event_with_a_tombstone() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("products", 1, 1).unwrap();
+        let producer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        let options = ProducerOptions {
+            tombstone_on_delete: true,
+            ..ProducerOptions::default()
+        };
+        let publisher = KafkaPublisher::with_producer(producer, "products", &options);
+        let service = ProductEventService::new(Box::new(publisher), &options);
+
+        let product = Product {
+            id: Some("42".to_string()),
+            ..product("Some Product", "Product Range", Some("v1"))
+        };
+        expect!(service.delete(product, "some-correlation-id").await).to(be_ok());
+
+        let consumer: BaseConsumer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("group.id", "tombstone-test")
+            .create()
+            .unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions
+            .add_partition_offset("products", 0, Offset::Beginning)
+            .unwrap();
+        consumer.assign(&partitions).unwrap();
+        let messages: Vec<_> = (0..100)
+            .filter_map(|_| consumer.poll(Duration::from_millis(100)))
+            .map(|message| message.unwrap().detach())
+            .take(2)
+            .collect();
+
+        expect!(messages.len()).to(be_equal_to(2));
+        let event_type = messages[0]
+            .headers()
+            .and_then(|headers| headers.iter().find(|h| h.key == EVENT_TYPE_HEADER))
+            .and_then(|header| header.value);
+        expect!(event_type).to(be_some().value("DELETED".as_bytes()));
+        expect!(messages[1].key()).to(be_some().value("42".as_bytes()));
+        expect!(messages[1].payload()).to(be_none());
+    }
+
+    #[tokio::test]
+    async fn records_carry_the_request_correlation_id() {
+        let publisher =
+            KafkaPublisher::new("localhost:9092", "products", &ProducerOptions::default()).unwrap();
+        let event = create_event(
+            product("Some Product", "Product Range", None),
+            EventKind::Created,
+        );
+        let payload = serde_json::to_vec(&event).unwrap();
+
+        let req = TestRequest::default()
+            .insert_header(("X-Correlation-Id", "request-1234"))
+            .to_http_request();
+        let correlation_id = extract_or_generate_correlation_id(&req);
+        let record = publisher.record(&event, &payload, &correlation_id);
+
+        let header = record
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.iter().find(|h| h.key == CORRELATION_ID_HEADER))
+            .and_then(|header| header.value);
+        expect!(header).to(be_some().value("request-1234".as_bytes()));
+
+        let generated =
+            extract_or_generate_correlation_id(&TestRequest::default().to_http_request());
+        expect!(generated.is_empty()).to(be_false());
+        expect!(generated).to_not(be_equal_to("request-1234"));
+    }
+
+    /// A service that publishes to `publisher` instead of Kafka.
+    fn recording_service(publisher: RecordingPublisher) -> ProductEventService {
+        ProductEventService::new(Box::new(publisher), &ProducerOptions::default())
+    }
+
+    fn product(name: &str, r#type: &str, version: Option<&str>) -> Product {
+        Product {
+            id: None,
+            name: name.to_string(),
+            r#type: r#type.into(),
+            version: version.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates_get_increasing_versions() {
+        let publisher = RecordingPublisher::default();
+        let service = recording_service(publisher.clone());
+        let update = || Product {
+            id: Some("42".to_string()),
+            ..product("Some Product", "Product Range", Some("v1"))
+        };
+
+        let (first, second) = futures::join!(
+            service.update(update(), "first"),
+            service.update(update(), "second")
+        );
+        let mut versions = vec![first.unwrap().version, second.unwrap().version];
+        versions.sort();
+        expect!(versions).to(be_equal_to(vec!["v2".to_string(), "v3".to_string()]));
+
+        // a stale client version does not roll the product back
+        let event = service.update(update(), "third").await.unwrap();
+        expect!(event.version).to(be_equal_to("v4"));
+    }
+
+    #[tokio::test]
+    async fn versions_events_in_the_configured_scheme() {
+        let publisher = RecordingPublisher::default();
+        let service = recording_service(publisher.clone());
+        let event = service
+            .update(product("Some Product", "Product Range", Some("v1")), "id")
+            .await;
+        expect!(event.map(|event| event.version)).to(be_ok().value("v2"));
+
+        let service = recording_service(publisher).with_version_scheme(VersionScheme::PlainInt);
+        let event = service
+            .update(product("Some Product", "Product Range", Some("v1")), "id")
+            .await;
+        expect!(event.map(|event| event.version)).to(be_ok().value("2"));
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_products_with_bad_request() {
+        let service = recording_service(RecordingPublisher::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products", web::post().to(create_product)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(json!({ "name": "", "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        expect!(response.status().as_u16()).to(be_equal_to(400));
+        let body: Value = read_body_json(response).await;
+        expect!(body).to(be_equal_to(json!({
+            "error": {
+                "code": "invalid_product",
+                "message": "invalid fields: name",
+                "fields": [FieldError { field: "name", message: "must not be empty" }]
+            }
+        })));
+    }
+
+    #[actix_web::test]
+    async fn rejects_malformed_and_oversized_bodies_with_bad_request() {
+        let service = recording_service(RecordingPublisher::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .app_data(json_config(64))
+                .route("/products", web::post().to(create_product)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(r#"{ "name": "Some Product", "#)
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(400));
+        let body: Value = read_body_json(response).await;
+        expect!(body["error"]["code"].as_str()).to(be_some().value("invalid_body"));
+        let message = body["error"]["message"].as_str().unwrap_or_default();
+        expect!(message.starts_with("invalid JSON body: ")).to(be_true());
+
+        let name = "x".repeat(100);
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(json!({ "name": name, "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(400));
+        let body: Value = read_body_json(response).await;
+        expect!(body).to(be_equal_to(json!({
+            "error": {
+                "code": "invalid_body",
+                "message": "request body is larger than 64 bytes"
+            }
+        })));
+    }
+
+    #[actix_web::test]
+    async fn rejects_oversized_events_with_payload_too_large() {
+        let options = ProducerOptions {
+            max_payload_bytes: 1024,
+            ..ProducerOptions::default()
+        };
+        let publisher = KafkaPublisher::new("localhost:9092", "products", &options).unwrap();
+        let service = ProductEventService::new(Box::new(publisher), &options);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products", web::post().to(create_product)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(json!({ "name": "x".repeat(2048), "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        expect!(response.status().as_u16()).to(be_equal_to(413));
+    }
+
+    /// Fails every publish as librdkafka does when its producer queue is full.
+    struct QueueFullPublisher;
+
+    #[async_trait::async_trait]
+    impl MessagePublisher for QueueFullPublisher {
+        async fn publish(
+            &self,
+            _event: &ProductEvent,
+            _correlation_id: &str,
+        ) -> Result<(), PublishError> {
+            Err(PublishError::Delivery(KafkaError::MessageProduction(
+                RDKafkaErrorCode::QueueFull,
+            )))
+        }
+
+        async fn publish_tombstone(&self, _id: &str) -> Result<(), PublishError> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn asks_clients_to_back_off_when_the_producer_queue_is_full() {
+        let options = ProducerOptions {
+            retry: RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            },
+            ..ProducerOptions::default()
+        };
+        let service = ProductEventService::new(Box::new(QueueFullPublisher), &options);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products", web::post().to(create_product)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(json!({ "name": "Some Product", "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        expect!(response.status().as_u16()).to(be_equal_to(503));
+        expect!(response.headers().get(header::RETRY_AFTER))
+            .to(be_some().value(HeaderValue::from_static("1")));
+        let body: Value = read_body_json(response).await;
+        expect!(body).to(be_equal_to(json!({
+            "error": {
+                "code": "queue_full",
+                "message": "failed to deliver event: Message production error: QueueFull (Local: Queue full)"
+            }
+        })));
+    }
+
+    #[actix_web::test]
+    async fn reports_events_lost_after_retries_to_the_failure_webhook() {
+        let (received, mut failures) = tokio::sync::mpsc::unbounded_channel::<Value>();
+        let server = actix_web::HttpServer::new(move || {
+            let received = received.clone();
+            App::new().route(
+                "/failures",
+                web::post().to(move |body: web::Json<Value>| {
+                    let _ = received.send(body.into_inner());
+                    async { actix_web::HttpResponse::Ok().finish() }
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/failures", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let options = ProducerOptions {
+            retry: RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            },
+            ..ProducerOptions::default()
+        };
+        let service = ProductEventService::new(Box::new(QueueFullPublisher), &options)
+            .with_failure_webhook(FailureWebhook::new(&url));
+        let product = Product {
+            id: Some("42".to_string()),
+            ..product("Some Product", "Product Range", None)
+        };
+        expect!(service.create(product, "some-correlation-id").await).to(be_err());
+
+        let failure = tokio::time::timeout(Duration::from_secs(5), failures.recv()).await;
+        handle.stop(false).await;
+        expect!(failure.unwrap()).to(be_some().value(json!({
+            "id": "42",
+            "event": "CREATED",
+            "version": "v1",
+            "error": "failed to deliver event: Message production error: QueueFull (Local: Queue full)",
+            "correlation_id": "some-correlation-id"
+        })));
+    }
+
+    #[actix_web::test]
+    async fn rejects_changes_over_the_rate_limit() {
+        let service = ProductEventService::new(
+            Box::new(RecordingPublisher::default()),
+            &ProducerOptions::default(),
+        );
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(rate_limit))
+                .app_data(web::Data::new(Arc::new(service)))
+                .app_data(web::Data::new(RateLimiter::per_second(2)))
+                .route("/products", web::post().to(create_product))
+                .route("/products/{id}", web::get().to(get_product)),
+        )
+        .await;
+        let create = |api_key: &str| {
+            TestRequest::post()
+                .uri("/products")
+                .insert_header(("X-Api-Key", api_key))
+                .set_json(json!({ "name": "Some Product", "type": "Product Range" }))
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            let response = call_service(&app, create("some-client")).await;
+            expect!(response.status().as_u16()).to(be_equal_to(201));
+        }
+        let response = call_service(&app, create("some-client")).await;
+        expect!(response.status().as_u16()).to(be_equal_to(429));
+        expect!(response.headers().get(header::RETRY_AFTER))
+            .to(be_some().value(HeaderValue::from_static("1")));
+
+        // other clients and reads are not held back
+        let response = call_service(&app, create("another-client")).await;
+        expect!(response.status().as_u16()).to(be_equal_to(201));
+        let request = TestRequest::get().uri("/products/42").to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(404));
+    }
+
+    #[actix_web::test]
+    async fn liveness_always_succeeds() {
+        let app = init_service(App::new().route("/live", web::get().to(live))).await;
+        let response = call_service(&app, TestRequest::get().uri("/live").to_request()).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+    }
+
+    #[actix_web::test]
+    async fn serves_an_openapi_spec_for_the_product_routes() {
+        let app = init_service(App::new().route("/openapi.json", web::get().to(openapi))).await;
+        let request = TestRequest::get().uri("/openapi.json").to_request();
+        let spec: utoipa::openapi::OpenApi =
+            serde_json::from_value(read_body_json(call_service(&app, request).await).await)
+                .unwrap();
+
+        let operation = |path, method| spec.paths.get_path_operation(path, method);
+        expect!(operation("/products", HttpMethod::Post).is_some()).to(be_true());
+        expect!(operation("/products/{id}", HttpMethod::Put).is_some()).to(be_true());
+        expect!(operation("/products/{id}", HttpMethod::Patch).is_some()).to(be_true());
+        let delete = operation("/products/{id}", HttpMethod::Delete);
+        let responses = &delete.unwrap().responses.responses;
+        expect!(responses.contains_key("400")).to(be_true());
+        expect!(responses.contains_key("409")).to(be_true());
+    }
+
+    #[actix_web::test]
+    async fn bulk_create_reports_invalid_items_per_index() {
+        let service = recording_service(RecordingPublisher::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/bulk", web::post().to(create_products)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products/bulk")
+            .set_json(json!([
+                { "id": "1", "name": "", "type": "Product Range" },
+                { "id": "2", "name": "Some Product", "type": "" }
+            ]))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        expect!(response.status().as_u16()).to(be_equal_to(207));
+        let body: Value = read_body_json(response).await;
+        expect!(body).to(be_equal_to(json!([
+            {
+                "index": 0,
+                "id": "1",
+                "status": 400,
+                "error": {
+                    "code": "invalid_product",
+                    "message": "invalid fields: name",
+                    "fields": [{ "field": "name", "message": "must not be empty" }]
+                }
+            },
+            {
+                "index": 1,
+                "id": "2",
+                "status": 400,
+                "error": {
+                    "code": "invalid_product",
+                    "message": "invalid fields: type",
+                    "fields": [{ "field": "type", "message": "must not be empty" }]
                 }
-                _ => HttpResponse::NotFound().finish(),
             }
+        ])));
+    }
+
+    #[tokio::test]
+    async fn concurrent_publishes_all_complete() {
+        // every request to the mock broker takes half a second, so twenty sends made one after
+        // the other would take at least ten
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("products", 1, 1).unwrap();
+        cluster
+            .broker_round_trip_time(1, Duration::from_millis(500))
+            .unwrap();
+        let producer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        let options = ProducerOptions::default();
+        let publisher = KafkaPublisher::with_producer(producer, "products", &options);
+        let service = ProductEventService::new(Box::new(publisher), &options);
+        let products = (0..20)
+            .map(|n| product(&format!("Product {}", n), "Product Range", None))
+            .collect();
+
+        let started = Instant::now();
+        let results = tokio::time::timeout(
+            Duration::from_secs(30),
+            service.create_many(products, "some-correlation-id"),
+        )
+        .await
+        .expect("publishes did not complete");
+
+        expect!(results.len()).to(be_equal_to(20));
+        expect!(results.iter().all(|result| result.is_ok())).to(be_true());
+        expect!(started.elapsed()).to(be_less_than(Duration::from_secs(8)));
+    }
+
+    #[test]
+    fn path_id_fills_in_a_missing_body_id() {
+        let product = with_path_id("42".to_string(), product("Some Product", "Range", None));
+        expect!(product.ok().and_then(|product| product.id)).to(be_some().value("42"));
+    }
+
+    #[actix_web::test]
+    async fn rejects_a_body_id_that_disagrees_with_the_path() {
+        let service = recording_service(RecordingPublisher::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/{id}", web::put().to(update_product)),
+        )
+        .await;
+
+        let request = TestRequest::put()
+            .uri("/products/42")
+            .set_json(json!({ "id": "99", "name": "Some Product", "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        expect!(response.status().as_u16()).to(be_equal_to(409));
+    }
+
+    #[actix_web::test]
+    async fn returns_the_last_known_event_for_a_product() {
+        let service = recording_service(RecordingPublisher::default());
+        let mut product = product("Some Product", "Product Range", Some("v1"));
+        product.id = Some("some-uuid-1234-5678".to_string());
+        let event = create_event(product, EventKind::Updated);
+        service
+            .history
+            .lock()
+            .await
+            .insert(event.id.clone(), vec![event]);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/{id}", web::get().to(get_product)),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .uri("/products/some-uuid-1234-5678")
+            .to_request();
+        let body: Value = read_body_json(call_service(&app, request).await).await;
+        expect!(body["event"].as_str()).to(be_some().value("UPDATED"));
+        expect!(body["version"].as_str()).to(be_some().value("v2"));
+
+        let request = TestRequest::get().uri("/products/unknown").to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(404));
+    }
+
+    #[actix_web::test]
+    async fn creates_and_updates_respond_with_the_published_event() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(recording_service(
+                    RecordingPublisher::default(),
+                ))))
+                .route("/products", web::post().to(create_product))
+                .route("/products/{id}", web::put().to(update_product)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(json!({ "name": "Some Product", "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(201));
+        let location = response.headers().get("Location").cloned();
+        let body: Value = read_body_json(response).await;
+        let id = body["id"].as_str().unwrap_or_default().to_string();
+        expect!(id.is_empty()).to(be_false());
+        expect!(body["version"].as_str()).to(be_some().value("v1"));
+        expect!(body["event"].as_str()).to(be_some().value("CREATED"));
+        expect!(location)
+            .to(be_some().value(HeaderValue::from_str(&format!("/products/{}", id)).unwrap()));
+
+        let request = TestRequest::put()
+            .uri(&format!("/products/{}", id))
+            .set_json(json!({ "name": "Some Product", "type": "Product Range", "version": "v1" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let body: Value = read_body_json(response).await;
+        expect!(body["id"].as_str()).to(be_some().value(id.as_str()));
+        expect!(body["version"].as_str()).to(be_some().value("v2"));
+    }
+
+    #[actix_web::test]
+    async fn updates_with_if_match_only_when_the_version_is_current() {
+        let publisher = RecordingPublisher::default();
+        let service = recording_service(publisher.clone());
+        let mut product = product("Some Product", "Product Range", None);
+        product.id = Some("42".to_string());
+        expect!(service.create(product, "some-correlation-id").await).to(be_ok());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/{id}", web::put().to(update_product)),
+        )
+        .await;
+        let put = |if_match: Option<&str>| {
+            let mut request = TestRequest::put()
+                .uri("/products/42")
+                .set_json(json!({ "name": "Renamed Product", "type": "Product Range" }));
+            if let Some(if_match) = if_match {
+                request = request.insert_header((header::IF_MATCH, if_match));
+            }
+            request.to_request()
+        };
+
+        let response = call_service(&app, put(Some("v2"))).await;
+        expect!(response.status().as_u16()).to(be_equal_to(412));
+        let body: Value = read_body_json(response).await;
+        expect!(body["error"]["code"].as_str()).to(be_some().value("version_mismatch"));
+        expect!(body["error"]["message"].as_str())
+            .to(be_some().value("product is at version v1, not v2"));
+        expect!(publisher.published().len()).to(be_equal_to(1));
+
+        let response = call_service(&app, put(Some("v1"))).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let body: Value = read_body_json(response).await;
+        expect!(body["version"].as_str()).to(be_some().value("v2"));
+
+        let response = call_service(&app, put(Some("\"v2\""))).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+
+        let response = call_service(&app, put(None)).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let body: Value = read_body_json(response).await;
+        expect!(body["version"].as_str()).to(be_some().value("v4"));
+        expect!(publisher.published().len()).to(be_equal_to(4));
+    }
+
+    #[actix_web::test]
+    async fn returns_the_history_of_a_product_newest_first() {
+        let service = recording_service(RecordingPublisher::default());
+        let mut product = product("Some Product", "Product Range", None);
+        product.id = Some("42".to_string());
+        expect!(service.create(product.clone(), "some-correlation-id").await).to(be_ok());
+        for name in ["Renamed Product", "Renamed Again"] {
+            product.name = name.to_string();
+            expect!(service.update(product.clone(), "some-correlation-id").await).to(be_ok());
         }
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/{id}/history", web::get().to(product_history)),
+        )
+        .await;
+
+        let request = TestRequest::get().uri("/products/42/history").to_request();
+        let body: Value = read_body_json(call_service(&app, request).await).await;
+        let history: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| (event["event"].as_str(), event["version"].as_str()))
+            .collect();
+        expect!(history).to(be_equal_to(vec![
+            (Some("UPDATED"), Some("v3")),
+            (Some("UPDATED"), Some("v2")),
+            (Some("CREATED"), Some("v1")),
+        ]));
+
+        let request = TestRequest::get().uri("/products/43/history").to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(404));
+    }
+
+    #[actix_web::test]
+    async fn patches_merge_over_the_last_known_product() {
+        let publisher = RecordingPublisher::default();
+        let service = recording_service(publisher.clone());
+        let mut product = product("Some Product", "Product Range", Some("v1"));
+        product.id = Some("42".to_string());
+        expect!(service.update(product, "some-correlation-id").await).to(be_ok());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/{id}", web::patch().to(patch_product)),
+        )
+        .await;
 
-        let (tx, rx) = oneshot::channel();
-        let server = HttpServer::new(|| {
-            App::new().route("/pact-messages", web::post().to(handle_request))
+        let request = TestRequest::patch()
+            .uri("/products/42")
+            .set_json(json!({ "name": "Renamed Product" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let body: Value = read_body_json(response).await;
+        expect!(body).to(be_equal_to(json!({
+            "id": "42",
+            "name": "Renamed Product",
+            "type": "Product Range",
+            "version": "v3",
+            "event": "UPDATED"
+        })));
+        expect!(publisher.published().len()).to(be_equal_to(2));
+    }
+
+    #[actix_web::test]
+    async fn patching_a_product_without_a_known_state_is_not_found() {
+        let publisher = RecordingPublisher::default();
+        let service = recording_service(publisher.clone());
+        let mut product = product("Some Product", "Product Range", Some("v1"));
+        product.id = Some("42".to_string());
+        expect!(service.delete(product, "some-correlation-id").await).to(be_ok());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/{id}", web::patch().to(patch_product)),
+        )
+        .await;
+
+        for id in ["unknown", "42"] {
+            let request = TestRequest::patch()
+                .uri(&format!("/products/{}", id))
+                .set_json(json!({ "name": "Renamed Product" }))
+                .to_request();
+            let response = call_service(&app, request).await;
+            expect!(response.status().as_u16()).to(be_equal_to(404));
+            let body: Value = read_body_json(response).await;
+            expect!(body["error"]["code"].as_str()).to(be_some().value("not_found"));
+        }
+        expect!(publisher.published().len()).to(be_equal_to(1));
+    }
+
+    #[actix_web::test]
+    async fn sync_requests_flush_the_producer_before_responding() {
+        let publisher = RecordingPublisher::default();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(recording_service(
+                    publisher.clone(),
+                ))))
+                .route("/products", web::post().to(create_product))
+                .route("/products/{id}", web::put().to(update_product))
+                .route("/products/{id}", web::delete().to(delete_product)),
+        )
+        .await;
+        let body = json!({ "id": "42", "name": "Some Product", "type": "Product Range" });
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(201));
+        expect!(publisher.flushes()).to(be_equal_to(0));
+
+        let request = TestRequest::put()
+            .uri("/products/42?sync=true")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(200));
+        expect!(publisher.flushes()).to(be_equal_to(1));
+        let request = TestRequest::delete()
+            .uri("/products/42?sync=true")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(200));
+        expect!(publisher.flushes()).to(be_equal_to(2));
+
+        let request = TestRequest::put()
+            .uri("/products/42?sync=false")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(200));
+        expect!(publisher.flushes()).to(be_equal_to(2));
+        expect!(publisher.published().len()).to(be_equal_to(4));
+    }
+
+    #[actix_web::test]
+    async fn fire_and_forget_changes_are_accepted() {
+        let options = ProducerOptions {
+            delivery_mode: DeliveryMode::FireAndForget,
+            ..ProducerOptions::default()
+        };
+        let service = ProductEventService::new(Box::new(RecordingPublisher::default()), &options);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products", web::post().to(create_product))
+                .route("/products/bulk", web::post().to(create_products))
+                .route("/products/{id}", web::put().to(update_product))
+                .route("/products/{id}", web::delete().to(delete_product)),
+        )
+        .await;
+        let body = json!({ "id": "42", "name": "Some Product", "type": "Product Range" });
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(&body)
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(202));
+        expect!(response.headers().get(header::LOCATION))
+            .to(be_some().value(HeaderValue::from_static("/products/42")));
+        let request = TestRequest::put()
+            .uri("/products/42")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(202));
+        let request = TestRequest::delete()
+            .uri("/products/42")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(202));
+        let request = TestRequest::post()
+            .uri("/products/bulk")
+            .set_json(json!([body]))
+            .to_request();
+        let results: Value = read_body_json(call_service(&app, request).await).await;
+        expect!(results[0]["status"].as_u64()).to(be_some().value(202));
+
+        // a sync request has waited for the event to be delivered
+        let request = TestRequest::put()
+            .uri("/products/42?sync=true")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(200));
+    }
+
+    #[actix_web::test]
+    async fn returns_the_event_as_xml_when_asked_to() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(recording_service(
+                    RecordingPublisher::default(),
+                ))))
+                .route("/products", web::post().to(create_product))
+                .route("/products/{id}", web::get().to(get_product)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .insert_header(("Accept", "application/xml"))
+            .set_json(json!({ "id": "42", "name": "Some Product", "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(201));
+        expect!(response.headers().get(header::CONTENT_TYPE))
+            .to(be_some().value(HeaderValue::from_static("application/xml")));
+        let body = read_body(response).await;
+        expect!(std::str::from_utf8(&body).unwrap()).to(be_equal_to(
+            "<ProductEvent><id>42</id><name>Some Product</name><type>Product Range</type>\
+             <version>v1</version><event>CREATED</event></ProductEvent>",
+        ));
+
+        let request = TestRequest::get().uri("/products/42").to_request();
+        let body: Value = read_body_json(call_service(&app, request).await).await;
+        expect!(body["event"].as_str()).to(be_some().value("CREATED"));
+    }
+
+    #[actix_web::test]
+    async fn serves_the_producer_config_without_credentials() {
+        let config = Config::from_lookup(|name| match name {
+            "KAFKA_SECURITY_PROTOCOL" => Some("SASL_SSL".to_string()),
+            "KAFKA_SASL_MECHANISM" => Some("PLAIN".to_string()),
+            "KAFKA_SASL_USERNAME" => Some("workshop".to_string()),
+            "KAFKA_SASL_PASSWORD" => Some("hunter2".to_string()),
+            "KAFKA_ACKS" => Some("all".to_string()),
+            _ => None,
         })
-        .bind("127.0.0.1:8090")
-        .expect("Failed to bind server")
-        .run();
-        let server_handle = server.handle();
-        // let _ = server.await;
-        tokio::spawn(async move {
-            let _ = server.await;
-            rx.await.ok();
-            server_handle.stop(true).await;
-        });
+        .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(config.effective()))
+                .route("/debug/config", web::get().to(debug_config)),
+        )
+        .await;
 
-        tx
+        let request = TestRequest::get().uri("/debug/config").to_request();
+        let body = read_body(call_service(&app, request).await).await;
+        expect!(std::str::from_utf8(&body).unwrap().contains("hunter2")).to(be_false());
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        expect!(body["broker"].as_str()).to(be_some().value(config.broker.as_str()));
+        expect!(body["topic"].as_str()).to(be_some().value(config.topic.as_str()));
+        let producer = &body["producer"];
+        expect!(producer["sasl.password"].as_str()).to(be_some().value("***"));
+        expect!(producer["sasl.username"].as_str()).to(be_some().value("workshop"));
+        expect!(producer["acks"].as_str()).to(be_some().value("all"));
+        expect!(producer["client.id"].as_str()).to(be_some().value("product-event-service"));
+        expect!(producer["compression.type"].as_str()).to(be_some());
     }
 
+    #[actix_web::test]
+    async fn records_request_latency_by_route_template() {
+        let service = Arc::new(recording_service(RecordingPublisher::default()));
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(record_request_latency))
+                .app_data(web::Data::new(service.clone()))
+                .route("/products/{id}", web::get().to(get_product)),
+        )
+        .await;
+
+        for id in ["42", "43"] {
+            let request = TestRequest::get()
+                .uri(&format!("/products/{}", id))
+                .to_request();
+            expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(404));
+        }
+        let request = TestRequest::get().uri("/nowhere").to_request();
+        call_service(&app, request).await;
+
+        let rendered = service.metrics.render();
+        expect!(rendered.contains(
+            r#"http_request_duration_seconds_count{method="GET",path="/products/{id}",status="404"} 2"#
+        ))
+        .to(be_true());
+        expect!(rendered.contains(r#"path="unmatched",status="404"} 1"#)).to(be_true());
+        expect!(rendered.contains("/products/42")).to(be_false());
+    }
+
+    #[actix_web::test]
+    async fn replays_the_last_event_of_a_product() {
+        let publisher = RecordingPublisher::default();
+        let service = recording_service(publisher.clone());
+        let mut product = product("Some Product", "Product Range", Some("v1"));
+        product.id = Some("42".to_string());
+        expect!(service.update(product, "some-correlation-id").await).to(be_ok());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products/{id}/replay", web::post().to(replay_product)),
+        )
+        .await;
+
+        let request = TestRequest::post().uri("/products/42/replay").to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+
+        let published = publisher.published();
+        expect!(published.len()).to(be_equal_to(2));
+        expect!(&published[1]).to(be_equal_to(&published[0]));
+        expect!(published[1].version.as_str()).to(be_equal_to("v2"));
+
+        let request = TestRequest::post()
+            .uri("/products/unknown/replay")
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(404));
+    }
 
     #[tokio::test]
-    async fn verifies_api_produces_correct_messages_for_consumers() {
-
-        let shutdown_tx = start_message_proxy().await;
-
-        /// Get the path to one of our sample *.json files.
-        fn fixture_path(path: &str) -> PathBuf {
-            env::current_dir()
-                .expect("could not find current working directory")
-                .join("..")
-                .join("consumer-rust-kafka")
-                .join("target")
-                .join("pacts")
-                .join(path)
-                .to_owned()
-        }
-        let pact_file = fixture_path(
-            "pactflow-example-consumer-rust-kafka-pactflow-example-provider-rust-kafka.json",
-        );
+    async fn records_every_published_event_in_the_audit_log() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let publisher = DryRunPublisher::new(TopicRouter::new("products"));
+        let service = ProductEventService::new(Box::new(publisher), &ProducerOptions::default())
+            .with_audit_sink(Box::new(FileAuditSink::open(&path).unwrap()));
 
-        #[allow(deprecated)]
-        let provider_info = ProviderInfo {
-            name: "pactflow-example-provider-rust-kafka".to_string(),
-            host: "127.0.0.1".to_string(),
-            port: Some(8090),
-            transports: vec![ProviderTransport {
-                transport: "async-message".to_string(),
-                port: Some(8090),
-                path: Some("/pact-messages".to_string()),
-                scheme: Some("http".to_string()),
-            }],
-            ..ProviderInfo::default()
+        let product = Product {
+            id: Some("42".to_string()),
+            ..product("Some Product", "Product Range", Some("v1"))
         };
+        service.create(product.clone(), "id").await.unwrap();
+        service.update(product, "id").await.unwrap();
 
-        let pact_source = PactSource::File(pact_file.to_string_lossy().to_string());
-
-        let verification_options: VerificationOptions<NullRequestFilterExecutor> =
-            VerificationOptions::default();
-        let provider_state_executor = Arc::new(DummyProviderStateExecutor {});
-
-        let result = verify_provider_async(
-            provider_info,
-            vec![pact_source],
-            FilterInfo::None,
-            vec![],
-            &verification_options,
-            None,
-            &provider_state_executor,
-            None,
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<AuditRecord> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        expect!(records.iter().all(|record| record.acknowledged)).to(be_true());
+        let summary: Vec<_> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.id.as_str(),
+                    record.event.as_str(),
+                    record.topic.as_deref(),
+                    record.version.as_str(),
+                )
+            })
+            .collect();
+        expect!(summary).to(be_equal_to(vec![
+            ("42", "CREATED", Some("products"), "v2"),
+            ("42", "UPDATED", Some("products"), "v3"),
+        ]));
+    }
+
+    #[actix_web::test]
+    async fn dry_runs_succeed_without_a_producer() {
+        let publisher = DryRunPublisher::new(TopicRouter::new("products"));
+        let options = ProducerOptions {
+            tombstone_on_delete: true,
+            ..ProducerOptions::default()
+        };
+        let service = Arc::new(ProductEventService::new(Box::new(publisher), &options));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(service.clone()))
+                .route("/products", web::post().to(create_product))
+                .route("/products/{id}", web::delete().to(delete_product)),
         )
         .await;
 
-        // shutdown our message proxy
-        shutdown_tx
-        .send(())
-        .expect("Failed to send shutdown signal");
-
-        // check the verification results
-        match result {
-            Ok(res) => {
-                if res.result {
-                    expect!(res.result).to(be_equal_to(true));
-                } else {
-                    panic!("Pact verification failed");
-                }
-            },
-            Err(error) => panic!("failed to get pact verification execution result {}",error),
+        let body = json!({ "id": "42", "name": "Some Product", "type": "Product Range" });
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(201));
+        let request = TestRequest::delete()
+            .uri("/products/42")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(200));
+        expect!(service.latest("42").await.map(|event| event.event))
+            .to(be_some().value(EventKind::Deleted));
+    }
+
+    #[actix_web::test]
+    async fn handlers_publish_through_the_message_publisher() {
+        let publisher = RecordingPublisher::default();
+        let options = ProducerOptions {
+            tombstone_on_delete: true,
+            ..ProducerOptions::default()
+        };
+        let service = ProductEventService::new(Box::new(publisher.clone()), &options);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products", web::post().to(create_product))
+                .route("/products/{id}", web::delete().to(delete_product)),
+        )
+        .await;
+
+        let body = json!({ "id": "42", "name": "Some Product", "type": "Product Range" });
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(201));
+        let request = TestRequest::delete()
+            .uri("/products/42")
+            .set_json(&body)
+            .to_request();
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(200));
+
+        let events: Vec<_> = publisher
+            .published()
+            .into_iter()
+            .map(|event| (event.id, event.event))
+            .collect();
+        expect!(events).to(be_equal_to(vec![
+            ("42".to_string(), EventKind::Created),
+            ("42".to_string(), EventKind::Deleted),
+        ]));
+        expect!(publisher.tombstones()).to(be_equal_to(vec!["42".to_string()]));
+    }
+
+    /// Starts Redpanda in a container, returning the container and its bootstrap servers.
+    /// The broker has to advertise the host port Docker maps it to, which is only known once
+    /// the container runs, so the container waits for a start script that names that port.
+    #[cfg(feature = "kafka-tests")]
+    async fn start_redpanda() -> (
+        testcontainers::ContainerAsync<testcontainers::GenericImage>,
+        String,
+    ) {
+        use testcontainers::core::{ExecCommand, IntoContainerPort, WaitFor};
+        use testcontainers::runners::AsyncRunner;
+        use testcontainers::{GenericImage, ImageExt};
+
+        let container = GenericImage::new("docker.redpanda.com/redpandadata/redpanda", "v24.1.7")
+            .with_exposed_port(9092.tcp())
+            .with_entrypoint("sh")
+            .with_wait_for(WaitFor::Nothing)
+            .with_cmd([
+                "-c",
+                "while [ ! -f /tmp/start.sh ]; do sleep 0.1; done; sh /tmp/start.sh",
+            ])
+            .start()
+            .await
+            .expect("failed to start Redpanda, is Docker running?");
+        let port = container.get_host_port_ipv4(9092.tcp()).await.unwrap();
+        let start = format!(
+            "exec rpk redpanda start --mode dev-container --smp 1 \
+             --kafka-addr PLAINTEXT://0.0.0.0:9092 \
+             --advertise-kafka-addr PLAINTEXT://127.0.0.1:{}",
+            port
+        );
+        container
+            .exec(
+                ExecCommand::new(["sh", "-c", &format!("echo '{}' > /tmp/start.sh", start)])
+                    .with_container_ready_conditions(vec![WaitFor::message_on_stderr(
+                        "Successfully started Redpanda!",
+                    )]),
+            )
+            .await
+            .unwrap();
+        (container, format!("127.0.0.1:{}", port))
+    }
+
+    #[cfg(feature = "kafka-tests")]
+    #[actix_web::test]
+    async fn publishes_created_products_to_a_real_broker() {
+        let (_redpanda, broker) = start_redpanda().await;
+        crate::admin::ensure_topic_exists(&broker, None, "products", 1, 1)
+            .await
+            .unwrap();
+        let options = ProducerOptions::default();
+        let publisher = KafkaPublisher::new(&broker, "products", &options).unwrap();
+        let service = ProductEventService::new(Box::new(publisher), &options);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(service)))
+                .route("/products", web::post().to(create_product)),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/products")
+            .set_json(json!({ "name": "Some Product", "type": "Product Range" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        expect!(response.status().as_u16()).to(be_equal_to(201));
+        let created: Value = read_body_json(response).await;
+
+        let consumer: BaseConsumer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", &broker)
+            .set("group.id", "kafka-tests")
+            .create()
+            .unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions
+            .add_partition_offset("products", 0, Offset::Beginning)
+            .unwrap();
+        consumer.assign(&partitions).unwrap();
+        let message = (0..300)
+            .find_map(|_| consumer.poll(Duration::from_millis(100)))
+            .expect("the event never reached the broker")
+            .unwrap();
+
+        let event: Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+        expect!(&event).to(be_equal_to(&created));
+        expect!(event["name"].as_str()).to(be_some().value("Some Product"));
+        expect!(event["event"].as_str()).to(be_some().value("CREATED"));
+        expect!(message.key()).to(be_some().value(created["id"].as_str().unwrap().as_bytes()));
+    }
+
+    /// Keyed by product id, every event for a product lands on one partition, so consumers
+    /// read them in the order they were published even on a multi-partition topic.
+    #[cfg(feature = "kafka-tests")]
+    #[tokio::test]
+    async fn events_for_a_product_are_consumed_in_version_order() {
+        let (_redpanda, broker) = start_redpanda().await;
+        crate::admin::ensure_topic_exists(&broker, None, "products", 3, 1)
+            .await
+            .unwrap();
+        let options = ProducerOptions::default();
+        let publisher = KafkaPublisher::new(&broker, "products", &options).unwrap();
+        let service = ProductEventService::new(Box::new(publisher), &options);
+        let mut product = product("Some Product", "Product Range", None);
+        product.id = Some("42".to_string());
+        service.create(product.clone(), "ordering").await.unwrap();
+        service.update(product.clone(), "ordering").await.unwrap();
+        service.update(product, "ordering").await.unwrap();
+
+        let consumer: BaseConsumer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", &broker)
+            .set("group.id", "kafka-tests-ordering")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer.subscribe(&["products"]).unwrap();
+        let mut consumed = vec![];
+        for _ in 0..300 {
+            if consumed.len() == 3 {
+                break;
+            }
+            if let Some(message) = consumer.poll(Duration::from_millis(100)) {
+                let message = message.unwrap();
+                let event: Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+                consumed.push((
+                    message.partition(),
+                    event["event"].as_str().unwrap_or_default().to_string(),
+                    event["version"].as_str().unwrap_or_default().to_string(),
+                ));
+            }
         }
+
+        let partitions: Vec<_> = consumed.iter().map(|(partition, ..)| *partition).collect();
+        expect!(partitions.windows(2).all(|pair| pair[0] == pair[1])).to(be_true());
+        let events: Vec<_> = consumed
+            .iter()
+            .map(|(_, event, version)| (event.as_str(), version.as_str()))
+            .collect();
+        expect!(events).to(be_equal_to(vec![
+            ("CREATED", "v1"),
+            ("UPDATED", "v2"),
+            ("UPDATED", "v3"),
+        ]));
     }
 }
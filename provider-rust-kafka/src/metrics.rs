@@ -0,0 +1,150 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+/// Prometheus metrics for published product events and the HTTP API, exposed at
+/// `GET /metrics`.
+pub struct Metrics {
+    registry: Registry,
+    published: IntCounterVec,
+    publish_duration: HistogramVec,
+    delivery_failures: IntCounterVec,
+    http_request_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let published = IntCounterVec::new(
+            Opts::new(
+                "product_events_published_total",
+                "Publish attempts for product events, by event type and result",
+            ),
+            &["event_type", "result"],
+        )
+        .expect("valid counter definition");
+        let publish_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "product_event_publish_duration_seconds",
+                "Time taken to publish a product event to Kafka, by event type",
+            ),
+            &["event_type"],
+        )
+        .expect("valid histogram definition");
+        let delivery_failures = IntCounterVec::new(
+            Opts::new(
+                "product_event_delivery_failures_total",
+                "Failed publish attempts for product events, by error category",
+            ),
+            &["category"],
+        )
+        .expect("valid counter definition");
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to answer an HTTP request, by method, route and status",
+            ),
+            &["method", "path", "status"],
+        )
+        .expect("valid histogram definition");
+        registry
+            .register(Box::new(published.clone()))
+            .expect("counter registered once");
+        registry
+            .register(Box::new(publish_duration.clone()))
+            .expect("histogram registered once");
+        registry
+            .register(Box::new(delivery_failures.clone()))
+            .expect("counter registered once");
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .expect("histogram registered once");
+
+        Metrics {
+            registry,
+            published,
+            publish_duration,
+            delivery_failures,
+            http_request_duration,
+        }
+    }
+
+    /// Records a single publish attempt, labelled with its `result`: `success`, `enqueued` for
+    /// an event only handed to the producer (`KAFKA_DELIVERY_MODE=fire-and-forget`), or
+    /// `failure`.
+    pub fn record_publish(&self, event_type: &str, result: &str, duration: Duration) {
+        self.published
+            .with_label_values(&[event_type, result])
+            .inc();
+        self.publish_duration
+            .with_label_values(&[event_type])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Records a failed publish attempt under its error category, see
+    /// [`PublishError::category`](crate::errors::PublishError::category).
+    pub fn record_delivery_failure(&self, category: &str) {
+        self.delivery_failures.with_label_values(&[category]).inc();
+    }
+
+    /// Records how long an HTTP request took. `path` must be the route template, e.g.
+    /// `/products/{id}`, so every product shares one series.
+    pub fn record_http_request(&self, method: &str, path: &str, status: u16, duration: Duration) {
+        self.http_request_duration
+            .with_label_values(&[method, path, &status.to_string()])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding does not fail");
+        String::from_utf8(buffer).expect("text encoding is UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use expectest::prelude::*;
+    use std::time::Duration;
+
+    #[test]
+    fn counts_successes_and_failures_separately() {
+        let metrics = Metrics::new();
+        metrics.record_publish("CREATED", "success", Duration::from_millis(5));
+        metrics.record_publish("CREATED", "success", Duration::from_millis(5));
+        metrics.record_publish("UPDATED", "enqueued", Duration::from_millis(5));
+        metrics.record_publish("DELETED", "failure", Duration::from_millis(5));
+        metrics.record_delivery_failure("timeout");
+
+        let rendered = metrics.render();
+        expect!(rendered.contains(
+            r#"product_events_published_total{event_type="CREATED",result="success"} 2"#
+        ))
+        .to(be_true());
+        expect!(rendered.contains(
+            r#"product_events_published_total{event_type="UPDATED",result="enqueued"} 1"#
+        ))
+        .to(be_true());
+        expect!(rendered.contains(
+            r#"product_events_published_total{event_type="DELETED",result="failure"} 1"#
+        ))
+        .to(be_true());
+        expect!(rendered
+            .contains(r#"product_event_publish_duration_seconds_count{event_type="CREATED"} 2"#))
+        .to(be_true());
+        expect!(rendered
+            .contains(r#"product_event_delivery_failures_total{category="timeout"} 1"#))
+        .to(be_true());
+    }
+}
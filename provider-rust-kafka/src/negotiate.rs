@@ -0,0 +1,77 @@
+use actix_web::http::header::{Accept, Header};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+use tracing::error;
+
+/// Media types a response body can be written as, picked from the request's `Accept` header.
+/// Only HTTP responses are negotiated, events on Kafka are always JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// `application/xml` or `text/xml`, for clients that cannot read JSON.
+    Xml,
+}
+
+impl Format {
+    /// The most preferred format the client accepts, JSON when it accepts neither or sent no
+    /// `Accept` header at all.
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let Ok(accept) = Accept::parse(req) else {
+            return Format::Json;
+        };
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match mime.essence_str() {
+                "application/xml" | "text/xml" => Some(Format::Xml),
+                "application/json" | "*/*" | "application/*" => Some(Format::Json),
+                _ => None,
+            })
+            .unwrap_or(Format::Json)
+    }
+
+    /// Finishes `response` with `body` in this format.
+    pub fn respond<T: Serialize>(
+        &self,
+        mut response: HttpResponseBuilder,
+        body: &T,
+    ) -> HttpResponse {
+        match self {
+            Format::Json => response.json(body),
+            Format::Xml => match quick_xml::se::to_string(body) {
+                Ok(xml) => response.content_type("application/xml").body(xml),
+                Err(err) => {
+                    error!(error = %err, "failed to serialize the response as XML");
+                    HttpResponse::InternalServerError().finish()
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Format;
+    use actix_web::test::TestRequest;
+    use expectest::prelude::*;
+
+    #[test]
+    fn picks_the_most_preferred_known_format() {
+        let format = |accept: Option<&str>| {
+            let request = match accept {
+                Some(accept) => TestRequest::default().insert_header(("Accept", accept)),
+                None => TestRequest::default(),
+            };
+            Format::from_request(&request.to_http_request())
+        };
+
+        expect!(format(None)).to(be_equal_to(Format::Json));
+        expect!(format(Some("application/xml"))).to(be_equal_to(Format::Xml));
+        expect!(format(Some("text/xml"))).to(be_equal_to(Format::Xml));
+        expect!(format(Some("application/json, application/xml;q=0.5")))
+            .to(be_equal_to(Format::Json));
+        expect!(format(Some("application/json;q=0.5, application/xml")))
+            .to(be_equal_to(Format::Xml));
+        expect!(format(Some("text/html"))).to(be_equal_to(Format::Json));
+    }
+}
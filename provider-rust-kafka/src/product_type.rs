@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// The kind of product. On the wire this is the same free-form string the contracts have
+/// always used (`"Product Range"`, `"Food"`), and any other value is kept as `Other`, so
+/// existing messages still deserialize and round-trip unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub enum ProductType {
+    /// `"Product Range"`
+    Range,
+    /// `"Food"`
+    Food,
+    /// Any type this service does not know about. Rejected by
+    /// [`Product::validate_strict`](crate::Product::validate_strict).
+    Other(String),
+}
+
+impl ProductType {
+    pub fn is_known(&self) -> bool {
+        !matches!(self, ProductType::Other(_))
+    }
+}
+
+impl fmt::Display for ProductType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProductType::Range => f.write_str("Product Range"),
+            ProductType::Food => f.write_str("Food"),
+            ProductType::Other(other) => f.write_str(other),
+        }
+    }
+}
+
+impl FromStr for ProductType {
+    type Err = Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value {
+            "Product Range" => ProductType::Range,
+            "Food" => ProductType::Food,
+            other => ProductType::Other(other.to_string()),
+        })
+    }
+}
+
+impl From<&str> for ProductType {
+    fn from(value: &str) -> Self {
+        let Ok(product_type) = value.parse();
+        product_type
+    }
+}
+
+impl From<String> for ProductType {
+    fn from(value: String) -> Self {
+        value.as_str().into()
+    }
+}
+
+impl From<ProductType> for String {
+    fn from(value: ProductType) -> Self {
+        match value {
+            ProductType::Other(other) => other,
+            known => known.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProductType;
+    use expectest::prelude::*;
+    use serde_json::json;
+
+    #[test]
+    fn keeps_the_existing_wire_format() {
+        for (value, expected) in [
+            ("Product Range", ProductType::Range),
+            ("Food", ProductType::Food),
+            ("Range", ProductType::Other("Range".to_string())),
+        ] {
+            let parsed: ProductType = serde_json::from_value(json!(value)).unwrap();
+            expect!(&parsed).to(be_equal_to(&expected));
+            expect!(serde_json::to_value(&parsed).unwrap()).to(be_equal_to(json!(value)));
+            expect!(parsed.to_string()).to(be_equal_to(value));
+            expect!(value.parse::<ProductType>()).to(be_ok().value(expected));
+        }
+        expect!(ProductType::Range.is_known()).to(be_true());
+        expect!(ProductType::Other("Gadget".to_string()).is_known()).to(be_false());
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::DateTime;
+use product_events::{ProductEvent, UnknownEventKind};
+use prost::Message;
+
+/// Types generated by `build.rs` from `proto/product_event.proto`.
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/io.pactflow.example.kafka.rs"));
+}
+
+impl From<&ProductEvent> for generated::ProductEvent {
+    fn from(event: &ProductEvent) -> Self {
+        generated::ProductEvent {
+            id: event.id.clone(),
+            name: event.name.clone(),
+            r#type: event.r#type.to_string(),
+            version: event.version.clone(),
+            event: event.event.to_string(),
+            occurred_at: event
+                .occurred_at
+                .map(|at| at.to_rfc3339())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<generated::ProductEvent> for ProductEvent {
+    type Error = UnknownEventKind;
+
+    fn try_from(event: generated::ProductEvent) -> Result<Self, Self::Error> {
+        Ok(ProductEvent {
+            id: event.id,
+            name: event.name,
+            r#type: event.r#type.into(),
+            version: event.version,
+            event: event.event.parse()?,
+            occurred_at: DateTime::parse_from_rfc3339(&event.occurred_at).ok(),
+        })
+    }
+}
+
+/// Encodes the event as protobuf bytes.
+pub fn encode(event: &ProductEvent) -> Vec<u8> {
+    generated::ProductEvent::from(event).encode_to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, generated};
+    use expectest::prelude::*;
+    use product_events::{create_event, EventKind, Product, ProductEvent, ProductType};
+    use prost::Message;
+
+    #[test]
+    fn round_trips_product_events() {
+        let mut event = create_event(
+            Product {
+                id: Some("some-uuid-1234-5678".to_string()),
+                name: "Some Product".to_string(),
+                r#type: ProductType::Range,
+                version: Some("v1".to_string()),
+            },
+            EventKind::Updated,
+        );
+
+        event.occurred_at = Some("2024-05-01T12:00:00+02:00".parse().unwrap());
+
+        let bytes = encode(&event);
+        let decoded =
+            ProductEvent::try_from(generated::ProductEvent::decode(bytes.as_slice()).unwrap())
+                .unwrap();
+
+        expect!(decoded).to(be_equal_to(event));
+    }
+}
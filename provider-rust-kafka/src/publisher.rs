@@ -0,0 +1,865 @@
+use crate::config::{DeliveryMode, PartitionStrategy, ProducerOptions};
+use crate::errors::PublishError;
+use crate::routing::TopicRouter;
+use crate::serializer::{EventSerializer, JsonSerializer};
+use async_trait::async_trait;
+use product_events::{
+    EventKind, JsonCase, ProductEvent, EVENT_TYPE_HEADER, SCHEMA_VERSION, SCHEMA_VERSION_HEADER,
+};
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, KafkaResult};
+use rdkafka::message::{Header, OwnedHeaders, ToBytes};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::util::Timeout;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Header on dead-lettered records carrying the error that made the publish fail.
+pub const DLQ_ERROR_HEADER: &str = "dlq-error";
+
+/// Header carrying the id that ties a Kafka record back to the HTTP request that produced it.
+pub const CORRELATION_ID_HEADER: &str = "correlation-id";
+
+/// Header carrying the media type of the payload, see [`EventSerializer::content_type`].
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+/// Header marking a record as a re-publish of an event that was already published once.
+pub const REPLAYED_HEADER: &str = "replayed";
+
+/// How much longer than `send_timeout` a send waits for librdkafka, which gives up on the
+/// record after `send_timeout` (`message.timeout.ms`), to report the outcome itself.
+const DELIVERY_REPORT_GRACE: Duration = Duration::from_secs(1);
+
+/// Adds the `replayed: true` header to a record.
+fn mark_replayed<'a>(
+    mut record: FutureRecord<'a, String, Vec<u8>>,
+) -> FutureRecord<'a, String, Vec<u8>> {
+    let headers = record.headers.take().unwrap_or_default().insert(Header {
+        key: REPLAYED_HEADER,
+        value: Some("true"),
+    });
+    record.headers(headers)
+}
+
+/// Sends product events to a message broker. `ProductEventService` retries, records metrics
+/// and keeps the latest events on top of this, so implementations only make single attempts.
+#[async_trait]
+pub trait MessagePublisher: Send + Sync {
+    /// Makes a single attempt at publishing the event.
+    async fn publish(&self, event: &ProductEvent, correlation_id: &str)
+        -> Result<(), PublishError>;
+
+    /// Makes a single attempt at publishing an event again, unchanged, marked with the
+    /// `replayed` header. Publishes it like any other event by default.
+    async fn replay(&self, event: &ProductEvent, correlation_id: &str) -> Result<(), PublishError> {
+        self.publish(event, correlation_id).await
+    }
+
+    /// Makes a single attempt at publishing a tombstone for the product, so log compaction
+    /// eventually removes it.
+    async fn publish_tombstone(&self, id: &str) -> Result<(), PublishError>;
+
+    /// Keeps an event that still failed after every retry. Failures here are only logged, the
+    /// caller still sees the original error. Drops the event by default.
+    async fn dead_letter(&self, _event: &ProductEvent, _correlation_id: &str, _err: &PublishError) {
+    }
+
+    /// Whether the broker can be reached within `timeout`.
+    async fn is_reachable(&self, _timeout: Duration) -> bool {
+        true
+    }
+
+    /// The topic `event` is published to, if the publisher has topics.
+    fn topic(&self, _event: &ProductEvent) -> Option<&str> {
+        None
+    }
+
+    /// Waits until every buffered message has been delivered or `timeout` elapses.
+    async fn flush(&self, _timeout: Duration) -> Result<(), PublishError> {
+        Ok(())
+    }
+}
+
+/// Publishes product events to Kafka.
+pub struct KafkaPublisher {
+    /// `FutureProducer` is a cheap, thread-safe handle, so concurrent publishes share it
+    /// without locking.
+    producer: FutureProducer,
+    router: TopicRouter,
+    partitioning: PartitionStrategy,
+    /// Encoded events larger than this are rejected without being sent.
+    max_payload_bytes: usize,
+    /// How long a send may wait for the broker before failing with `PublishError::Timeout`.
+    send_timeout: Duration,
+    /// Whether `publish` waits for the broker's acknowledgement, see [`DeliveryMode`].
+    delivery_mode: DeliveryMode,
+    /// Topic that events are written to once all publish attempts have failed.
+    dlq_topic: Option<String>,
+    /// Key naming of JSON payloads, dead letters included.
+    json_case: JsonCase,
+    /// Indent JSON payloads, dead letters included, see [`KafkaPublisher::with_pretty_json`].
+    pretty_json: bool,
+    /// Encodes event payloads, see [`KafkaPublisher::with_serializer`].
+    serializer: Box<dyn EventSerializer>,
+    /// Adds CloudEvents attributes, as headers in binary mode.
+    #[cfg(feature = "cloudevents")]
+    cloudevents: crate::cloudevents::CloudEventsOptions,
+}
+
+impl KafkaPublisher {
+    /// Creates the producer, failing when librdkafka rejects the configuration.
+    pub fn new(broker: &str, topic: &str, options: &ProducerOptions) -> KafkaResult<Self> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", broker);
+        options.apply(&mut config);
+        let producer: FutureProducer = config.create()?;
+        Ok(Self::with_producer(producer, topic, options))
+    }
+
+    pub fn with_producer(producer: FutureProducer, topic: &str, options: &ProducerOptions) -> Self {
+        KafkaPublisher {
+            producer,
+            router: TopicRouter::new(topic),
+            partitioning: options.partitioning,
+            max_payload_bytes: options.max_payload_bytes,
+            send_timeout: options.send_timeout,
+            delivery_mode: options.delivery_mode,
+            dlq_topic: None,
+            json_case: JsonCase::default(),
+            pretty_json: false,
+            serializer: Box::new(JsonSerializer::default()),
+            #[cfg(feature = "cloudevents")]
+            cloudevents: crate::cloudevents::CloudEventsOptions::default(),
+        }
+        .with_built_in_serializer()
+    }
+
+    /// Publishes each event to the topic `router` picks for its event type.
+    pub fn with_router(self, router: TopicRouter) -> Self {
+        KafkaPublisher { router, ..self }
+    }
+
+    /// Writes events that still fail after all retries to `topic`, as JSON with a
+    /// `dlq-error` header, instead of dropping them.
+    pub fn with_dlq(self, topic: &str) -> Self {
+        KafkaPublisher {
+            dlq_topic: Some(topic.to_string()),
+            ..self
+        }
+    }
+
+    /// Names the keys of JSON payloads in `case`. Protobuf and CloudEvents payloads keep their
+    /// own field names. Resets the serializer to the built-in one.
+    pub fn with_json_case(self, json_case: JsonCase) -> Self {
+        KafkaPublisher { json_case, ..self }.with_built_in_serializer()
+    }
+
+    /// Indents JSON payloads over several lines, for reading them in a console consumer. Off by
+    /// default, as it makes every record larger. Resets the serializer to the built-in one.
+    pub fn with_pretty_json(self, pretty_json: bool) -> Self {
+        KafkaPublisher {
+            pretty_json,
+            ..self
+        }
+        .with_built_in_serializer()
+    }
+
+    /// Resets the serializer to the built-in one.
+    #[cfg(feature = "cloudevents")]
+    pub fn with_cloudevents(self, options: crate::cloudevents::CloudEventsOptions) -> Self {
+        KafkaPublisher {
+            cloudevents: options,
+            ..self
+        }
+        .with_built_in_serializer()
+    }
+
+    /// Encodes event payloads with `serializer`, e.g. Avro, instead of the built-in format.
+    /// Dead letters are always JSON.
+    pub fn with_serializer(self, serializer: Box<dyn EventSerializer>) -> Self {
+        KafkaPublisher { serializer, ..self }
+    }
+
+    /// Uses the format the service was built for: a structured CloudEvent when built with the
+    /// `cloudevents` feature in structured mode, then protobuf when built with the `protobuf`
+    /// feature, and JSON otherwise.
+    fn with_built_in_serializer(self) -> Self {
+        #[cfg(feature = "cloudevents")]
+        if self.cloudevents.mode == crate::cloudevents::CloudEventsMode::Structured {
+            let serializer = crate::serializer::CloudEventsSerializer(self.cloudevents.clone());
+            return self.with_serializer(Box::new(serializer));
+        }
+        #[cfg(feature = "protobuf")]
+        return self.with_serializer(Box::new(crate::serializer::ProtobufSerializer));
+        #[cfg(not(feature = "protobuf"))]
+        {
+            let serializer = JsonSerializer {
+                json_case: self.json_case,
+                pretty: self.pretty_json,
+            };
+            self.with_serializer(Box::new(serializer))
+        }
+    }
+
+    /// Builds the Kafka record for an event, always keyed by product id so that every event of
+    /// a product, and its tombstone, share a key and compaction keeps only the latest. See
+    /// [`PartitionStrategy`] for which strategies keep per-product order.
+    /// The record is timestamped with the event's `occurred_at` when it has one.
+    pub fn record<'a>(
+        &'a self,
+        event: &'a ProductEvent,
+        payload: &'a Vec<u8>,
+        correlation_id: &'a str,
+    ) -> FutureRecord<'a, String, Vec<u8>> {
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: EVENT_TYPE_HEADER,
+                value: Some(event.event.as_str()),
+            })
+            .insert(Header {
+                key: SCHEMA_VERSION_HEADER,
+                value: Some(&SCHEMA_VERSION.to_string()),
+            })
+            .insert(Header {
+                key: CORRELATION_ID_HEADER,
+                value: Some(correlation_id),
+            })
+            .insert(Header {
+                key: CONTENT_TYPE_HEADER,
+                value: Some(self.serializer.content_type()),
+            });
+        #[cfg(feature = "cloudevents")]
+        let headers = self.cloudevents.headers(event, headers);
+        let mut record = FutureRecord::<String, Vec<u8>>::to(self.router.topic_for(event.event))
+            .payload(payload)
+            .headers(headers);
+        if let Some(occurred_at) = event.occurred_at {
+            record = record.timestamp(occurred_at.timestamp_millis());
+        }
+        let record = record.key(&event.id);
+        match self.partitioning {
+            PartitionStrategy::KeyHash | PartitionStrategy::RoundRobin => record,
+            PartitionStrategy::Fixed(partition) => record.partition(partition),
+        }
+    }
+
+    /// Builds a tombstone for a product: a record keyed by its id with no payload, sent to the
+    /// partition its events go to.
+    fn tombstone_record<'a>(&'a self, id: &'a str) -> FutureRecord<'a, str, ()> {
+        let record = FutureRecord::to(self.router.topic_for(EventKind::Deleted)).key(id);
+        match self.partitioning {
+            PartitionStrategy::Fixed(partition) => record.partition(partition),
+            _ => record,
+        }
+    }
+
+    /// Serializes the event payload with the publisher's [`EventSerializer`].
+    fn encode(&self, event: &ProductEvent) -> Result<Vec<u8>, PublishError> {
+        Ok(self.serializer.serialize(event)?)
+    }
+
+    /// Sends a record, waiting at most `send_timeout` for room in the producer queue and for
+    /// the broker's acknowledgement. librdkafka drops the record itself once `send_timeout`
+    /// has passed, reporting `MessageTimedOut`; only if it has not done so within
+    /// [`DELIVERY_REPORT_GRACE`] more does the send fail with [`PublishError::Timeout`], with
+    /// the record possibly still queued.
+    async fn deliver<K, P>(
+        &self,
+        record: FutureRecord<'_, K, P>,
+    ) -> Result<(i32, i64), PublishError>
+    where
+        K: ToBytes + ?Sized,
+        P: ToBytes + ?Sized,
+    {
+        let send = self
+            .producer
+            .send(record, Timeout::After(self.send_timeout));
+        match tokio::time::timeout(self.send_timeout + DELIVERY_REPORT_GRACE, send).await {
+            Ok(delivery) => delivery
+                .map(|delivery| (delivery.partition, delivery.offset))
+                .map_err(|(err, _)| PublishError::from(err)),
+            Err(_) => Err(PublishError::Timeout(self.send_timeout)),
+        }
+    }
+
+    /// Hands a record to the producer without waiting for the broker, see
+    /// [`DeliveryMode::FireAndForget`]. Only a record the producer cannot queue fails, a failed
+    /// delivery is logged once librdkafka gives up on it.
+    fn enqueue(&self, record: FutureRecord<'_, String, Vec<u8>>) -> Result<(), PublishError> {
+        let product_id = record.key.cloned().unwrap_or_default();
+        let delivery = self
+            .producer
+            .send_result(record)
+            .map_err(|(err, _)| PublishError::from(err))?;
+        tokio::spawn(async move {
+            if let Ok(Err((err, _))) = delivery.await {
+                error!(product_id, error = %err, "failed to deliver product event");
+            }
+        });
+        Ok(())
+    }
+
+    /// Encodes and sends the event, first checking it against the JSON schema when built with
+    /// the `validate-schema` feature. Payloads over `max_payload_bytes` are never sent.
+    async fn send(
+        &self,
+        event: &ProductEvent,
+        correlation_id: &str,
+        replayed: bool,
+    ) -> Result<(), PublishError> {
+        #[cfg(feature = "validate-schema")]
+        if let Err(err) = crate::schema::validate_event(event) {
+            error!(product_id = %event.id, error = %err, "product event failed schema validation");
+            return Err(PublishError::SchemaViolation(err));
+        }
+        let payload = self.encode(event)?;
+        if payload.len() > self.max_payload_bytes {
+            return Err(PublishError::PayloadTooLarge {
+                size: payload.len(),
+                limit: self.max_payload_bytes,
+            });
+        }
+        let mut record = self.record(event, &payload, correlation_id);
+        if replayed {
+            record = mark_replayed(record);
+        }
+        let topic = record.topic;
+        if self.delivery_mode == DeliveryMode::FireAndForget {
+            self.enqueue(record)?;
+            info!(topic, replayed, "enqueued product event");
+            return Ok(());
+        }
+        let (partition, offset) = self.deliver(record).await?;
+        info!(
+            topic,
+            partition, offset, replayed, "published product event"
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessagePublisher for KafkaPublisher {
+    async fn publish(
+        &self,
+        event: &ProductEvent,
+        correlation_id: &str,
+    ) -> Result<(), PublishError> {
+        self.send(event, correlation_id, false).await
+    }
+
+    async fn replay(&self, event: &ProductEvent, correlation_id: &str) -> Result<(), PublishError> {
+        self.send(event, correlation_id, true).await
+    }
+
+    async fn publish_tombstone(&self, id: &str) -> Result<(), PublishError> {
+        self.deliver(self.tombstone_record(id)).await?;
+        info!(product_id = id, "published product tombstone");
+        Ok(())
+    }
+
+    async fn dead_letter(&self, event: &ProductEvent, correlation_id: &str, err: &PublishError) {
+        let Some(dlq_topic) = &self.dlq_topic else {
+            return;
+        };
+        let dead_letter = JsonSerializer {
+            json_case: self.json_case,
+            pretty: self.pretty_json,
+        };
+        let payload = match dead_letter.serialize(event) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!(product_id = %event.id, error = %err, "failed to serialize dead letter");
+                return;
+            }
+        };
+        let reason = err.to_string();
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: EVENT_TYPE_HEADER,
+                value: Some(event.event.as_str()),
+            })
+            .insert(Header {
+                key: SCHEMA_VERSION_HEADER,
+                value: Some(&SCHEMA_VERSION.to_string()),
+            })
+            .insert(Header {
+                key: CORRELATION_ID_HEADER,
+                value: Some(correlation_id),
+            })
+            .insert(Header {
+                key: DLQ_ERROR_HEADER,
+                value: Some(&reason),
+            });
+        let record = FutureRecord::to(dlq_topic)
+            .key(&event.id)
+            .payload(&payload)
+            .headers(headers);
+        match self.deliver(record).await {
+            Ok(_) => warn!(product_id = %event.id, dlq_topic, "dead-lettered product event"),
+            Err(err) => error!(
+                product_id = %event.id,
+                dlq_topic,
+                error = %err,
+                "failed to dead-letter product event"
+            ),
+        }
+    }
+
+    fn topic(&self, event: &ProductEvent) -> Option<&str> {
+        Some(self.router.topic_for(event.event))
+    }
+
+    /// Whether the broker answers a metadata request within `timeout`.
+    async fn is_reachable(&self, timeout: Duration) -> bool {
+        let producer = self.producer.clone();
+        // fetch_metadata blocks, so keep it off the async worker threads
+        tokio::task::spawn_blocking(move || {
+            producer
+                .client()
+                .fetch_metadata(None, Timeout::After(timeout))
+                .is_ok()
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    async fn flush(&self, timeout: Duration) -> Result<(), PublishError> {
+        let producer = self.producer.clone();
+        // flush blocks for up to `timeout`, so keep it off the async worker threads
+        let result = tokio::task::spawn_blocking(move || producer.flush(Timeout::After(timeout)))
+            .await
+            .unwrap_or(Err(KafkaError::Canceled));
+        match &result {
+            Ok(()) => info!("flushed pending product events"),
+            Err(err) => error!(
+                error = %err,
+                in_flight = self.producer.in_flight_count(),
+                "failed to flush product events"
+            ),
+        }
+        Ok(result?)
+    }
+}
+
+/// Logs every event with the topic it would go to instead of publishing it, for running the
+/// HTTP API without a broker (`DRY_RUN=true`). No producer is created.
+pub struct DryRunPublisher {
+    router: TopicRouter,
+    json_case: JsonCase,
+}
+
+impl DryRunPublisher {
+    pub fn new(router: TopicRouter) -> Self {
+        DryRunPublisher {
+            router,
+            json_case: JsonCase::default(),
+        }
+    }
+
+    /// Logs payloads with their keys named in `case`, as [`KafkaPublisher::with_json_case`].
+    pub fn with_json_case(self, json_case: JsonCase) -> Self {
+        DryRunPublisher { json_case, ..self }
+    }
+}
+
+#[async_trait]
+impl MessagePublisher for DryRunPublisher {
+    async fn publish(
+        &self,
+        event: &ProductEvent,
+        correlation_id: &str,
+    ) -> Result<(), PublishError> {
+        let payload = String::from_utf8_lossy(&self.json_case.to_vec(event)?).into_owned();
+        info!(
+            topic = self.router.topic_for(event.event),
+            correlation_id, payload, "dry run, not publishing product event"
+        );
+        Ok(())
+    }
+
+    async fn publish_tombstone(&self, id: &str) -> Result<(), PublishError> {
+        info!(
+            topic = self.router.topic_for(EventKind::Deleted),
+            product_id = id,
+            "dry run, not publishing product tombstone"
+        );
+        Ok(())
+    }
+
+    fn topic(&self, event: &ProductEvent) -> Option<&str> {
+        Some(self.router.topic_for(event.event))
+    }
+}
+
+/// Test double that keeps everything it is asked to publish in memory. Clones share the same
+/// recordings, so a test can hand one to a `ProductEventService` and inspect another.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingPublisher {
+    published: std::sync::Arc<std::sync::Mutex<Vec<ProductEvent>>>,
+    tombstones: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    flushes: std::sync::Arc<std::sync::Mutex<usize>>,
+}
+
+#[cfg(test)]
+impl RecordingPublisher {
+    pub fn published(&self) -> Vec<ProductEvent> {
+        self.published.lock().unwrap().clone()
+    }
+
+    pub fn tombstones(&self) -> Vec<String> {
+        self.tombstones.lock().unwrap().clone()
+    }
+
+    pub fn flushes(&self) -> usize {
+        *self.flushes.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MessagePublisher for RecordingPublisher {
+    async fn publish(
+        &self,
+        event: &ProductEvent,
+        _correlation_id: &str,
+    ) -> Result<(), PublishError> {
+        self.published.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+
+    async fn publish_tombstone(&self, id: &str) -> Result<(), PublishError> {
+        self.tombstones.lock().unwrap().push(id.to_string());
+        Ok(())
+    }
+
+    async fn flush(&self, _timeout: Duration) -> Result<(), PublishError> {
+        *self.flushes.lock().unwrap() += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        mark_replayed, KafkaPublisher, MessagePublisher, CONTENT_TYPE_HEADER, REPLAYED_HEADER,
+    };
+    use crate::config::{Batching, DeliveryMode, PartitionStrategy, ProducerOptions};
+    use crate::errors::PublishError;
+    use crate::routing::TopicRouter;
+    use crate::serializer::{EventSerializer, SerializeError};
+    use expectest::prelude::*;
+    #[cfg(not(any(feature = "protobuf", feature = "cloudevents")))]
+    use product_events::JsonCase;
+    use product_events::{
+        create_event, EventKind, Product, ProductEvent, ProductType, EVENT_TYPE_HEADER,
+        SCHEMA_VERSION_HEADER,
+    };
+    use rdkafka::error::RDKafkaErrorCode;
+    use rdkafka::message::Headers;
+    #[cfg(not(any(feature = "protobuf", feature = "cloudevents")))]
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    fn product(id: Option<&str>) -> Product {
+        Product {
+            id: id.map(str::to_string),
+            name: "Some Product".to_string(),
+            r#type: ProductType::Range,
+            version: Some("v1".to_string()),
+        }
+    }
+
+    fn publisher(options: &ProducerOptions) -> KafkaPublisher {
+        KafkaPublisher::new("localhost:9092", "products", options).unwrap()
+    }
+
+    #[tokio::test]
+    async fn oversized_events_are_rejected_before_sending() {
+        let publisher = publisher(&ProducerOptions {
+            max_payload_bytes: 1024,
+            ..ProducerOptions::default()
+        });
+        let event = create_event(
+            Product {
+                name: "x".repeat(2048),
+                ..product(Some("42"))
+            },
+            EventKind::Created,
+        );
+
+        let result = publisher.publish(&event, "some-correlation-id").await;
+
+        match result {
+            Err(PublishError::PayloadTooLarge { size, limit }) => {
+                expect!(size > 2048).to(be_true());
+                expect!(limit).to(be_equal_to(1024));
+            }
+            other => panic!("expected PayloadTooLarge, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_give_up_after_the_send_timeout() {
+        // nothing listens on port 1, so the record is never acknowledged
+        let options = ProducerOptions {
+            send_timeout: Duration::from_millis(200),
+            ..ProducerOptions::default()
+        };
+        let publisher = KafkaPublisher::new("127.0.0.1:1", "products", &options).unwrap();
+        expect!(publisher.send_timeout).to(be_equal_to(Duration::from_millis(200)));
+
+        let started = Instant::now();
+        let event = create_event(product(Some("42")), EventKind::Created);
+        let result = publisher.publish(&event, "some-correlation-id").await;
+
+        match result {
+            Err(PublishError::Delivery(err)) => {
+                expect!(err.rdkafka_error_code())
+                    .to(be_some().value(RDKafkaErrorCode::MessageTimedOut));
+            }
+            other => panic!("expected MessageTimedOut, got {:?}", other),
+        }
+        expect!(started.elapsed() < Duration::from_secs(5)).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn fire_and_forget_publishes_do_not_wait_for_delivery() {
+        // nothing listens on port 1, so the record is never acknowledged
+        let options = ProducerOptions {
+            send_timeout: Duration::from_secs(10),
+            delivery_mode: DeliveryMode::FireAndForget,
+            ..ProducerOptions::default()
+        };
+        let publisher = KafkaPublisher::new("127.0.0.1:1", "products", &options).unwrap();
+
+        let started = Instant::now();
+        let event = create_event(product(Some("42")), EventKind::Created);
+        let result = publisher.publish(&event, "some-correlation-id").await;
+
+        expect!(result).to(be_ok());
+        expect!(started.elapsed() < Duration::from_secs(1)).to(be_true());
+    }
+
+    #[test]
+    fn invalid_producer_config_is_an_error() {
+        let options = ProducerOptions {
+            batching: Batching {
+                linger_ms: Some(1_000_000_000),
+                ..Batching::default()
+            },
+            ..ProducerOptions::default()
+        };
+
+        let result = KafkaPublisher::new("localhost:9092", "products", &options);
+        expect!(result.is_err()).to(be_true());
+    }
+
+    #[test]
+    fn records_for_the_same_product_share_a_key() {
+        let publisher = publisher(&ProducerOptions::default());
+        let created = create_event(product(Some("some-uuid-1234-5678")), EventKind::Created);
+        let updated = create_event(product(Some("some-uuid-1234-5678")), EventKind::Updated);
+        let created_payload = serde_json::to_vec(&created).unwrap();
+        let updated_payload = serde_json::to_vec(&updated).unwrap();
+
+        let created_record = publisher.record(&created, &created_payload, "some-correlation-id");
+        let updated_record = publisher.record(&updated, &updated_payload, "some-correlation-id");
+
+        expect!(created_record.key).to(be_some().value(&"some-uuid-1234-5678".to_string()));
+        expect!(created_record.key).to(be_equal_to(updated_record.key));
+    }
+
+    #[test]
+    fn every_event_type_is_keyed_by_product_id_for_compaction() {
+        let id = "some-uuid-1234-5678";
+        for partitioning in [
+            PartitionStrategy::KeyHash,
+            PartitionStrategy::RoundRobin,
+            PartitionStrategy::Fixed(0),
+        ] {
+            let publisher = publisher(&ProducerOptions {
+                partitioning,
+                ..ProducerOptions::default()
+            });
+            let keys: Vec<_> = EventKind::ALL
+                .into_iter()
+                .map(|kind| {
+                    let event = create_event(product(Some(id)), kind);
+                    let payload = serde_json::to_vec(&event).unwrap();
+                    publisher
+                        .record(&event, &payload, "some-correlation-id")
+                        .key
+                        .cloned()
+                })
+                .collect();
+            expect!(keys).to(be_equal_to(vec![Some(id.to_string()); 3]));
+            expect!(publisher.tombstone_record(id).key).to(be_some().value(id));
+        }
+    }
+
+    #[test]
+    fn records_follow_the_partition_strategy() {
+        let event = create_event(product(None), EventKind::Created);
+        let payload = serde_json::to_vec(&event).unwrap();
+        let options = |partitioning| ProducerOptions {
+            partitioning,
+            ..ProducerOptions::default()
+        };
+
+        let round_robin = publisher(&options(PartitionStrategy::RoundRobin));
+        let record = round_robin.record(&event, &payload, "some-correlation-id");
+        expect!(record.key).to(be_some().value(&event.id));
+        expect!(record.partition).to(be_none());
+
+        let fixed = publisher(&options(PartitionStrategy::Fixed(2)));
+        let record = fixed.record(&event, &payload, "some-correlation-id");
+        expect!(record.key).to(be_some().value(&event.id));
+        expect!(record.partition).to(be_some().value(2));
+    }
+
+    #[test]
+    fn records_go_to_the_topic_routed_for_their_event_type() {
+        let publisher = publisher(&ProducerOptions::default()).with_router(
+            TopicRouter::new("products").route(EventKind::Deleted, "product-tombstones"),
+        );
+
+        let created = create_event(product(None), EventKind::Created);
+        let deleted = create_event(product(None), EventKind::Deleted);
+        let payload = serde_json::to_vec(&created).unwrap();
+        let created_record = publisher.record(&created, &payload, "some-correlation-id");
+        expect!(created_record.topic).to(be_equal_to("products"));
+        let deleted_record = publisher.record(&deleted, &payload, "some-correlation-id");
+        expect!(deleted_record.topic).to(be_equal_to("product-tombstones"));
+    }
+
+    #[test]
+    fn records_carry_the_event_type_header() {
+        let publisher = publisher(&ProducerOptions::default());
+        let event = create_event(product(None), EventKind::Deleted);
+        let payload = serde_json::to_vec(&event).unwrap();
+
+        let record = publisher.record(&event, &payload, "some-correlation-id");
+
+        let header = record
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.iter().find(|h| h.key == EVENT_TYPE_HEADER))
+            .and_then(|header| header.value);
+        expect!(header).to(be_some().value("DELETED".as_bytes()));
+    }
+
+    #[test]
+    fn records_carry_the_schema_version_header() {
+        let publisher = publisher(&ProducerOptions::default());
+        let event = create_event(product(None), EventKind::Created);
+        let payload = serde_json::to_vec(&event).unwrap();
+
+        let record = publisher.record(&event, &payload, "some-correlation-id");
+
+        let header = record
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.iter().find(|h| h.key == SCHEMA_VERSION_HEADER))
+            .and_then(|header| header.value);
+        expect!(header).to(be_some().value("1".as_bytes()));
+    }
+
+    #[test]
+    fn records_are_timestamped_with_the_event_time() {
+        let publisher = publisher(&ProducerOptions::default());
+        let mut event = create_event(product(Some("42")), EventKind::Updated);
+        let payload = serde_json::to_vec(&event).unwrap();
+        expect!(publisher.record(&event, &payload, "id").timestamp).to(be_none());
+
+        event.occurred_at = Some("2024-05-01T12:00:00+02:00".parse().unwrap());
+        let record = publisher.record(&event, &payload, "id");
+        expect!(record.timestamp).to(be_some().value(1_714_557_600_000));
+    }
+
+    #[cfg(not(any(feature = "protobuf", feature = "cloudevents")))]
+    #[test]
+    fn json_payloads_name_keys_in_the_configured_case() {
+        let event = create_event(product(Some("42")), EventKind::Updated);
+        let encoded = |publisher: KafkaPublisher| -> serde_json::Value {
+            serde_json::from_slice(&publisher.encode(&event).unwrap()).unwrap()
+        };
+
+        let snake = encoded(publisher(&ProducerOptions::default()));
+        expect!(snake.get("type")).to(be_some());
+        let camel = publisher(&ProducerOptions::default()).with_json_case(JsonCase::Camel);
+        let camel = encoded(camel);
+        expect!(camel.get("productType")).to(be_some().value(&json!("Product Range")));
+        expect!(camel.get("type")).to(be_none());
+    }
+
+    #[cfg(not(any(feature = "protobuf", feature = "cloudevents")))]
+    #[test]
+    fn indents_json_payloads_when_asked_to() {
+        let event = create_event(product(Some("42")), EventKind::Updated);
+        let compact = publisher(&ProducerOptions::default())
+            .encode(&event)
+            .unwrap();
+        expect!(compact.contains(&b'\n')).to(be_false());
+
+        let pretty = publisher(&ProducerOptions::default())
+            .with_pretty_json(true)
+            .encode(&event)
+            .unwrap();
+        expect!(pretty.contains(&b'\n')).to(be_true());
+        expect!(serde_json::from_slice::<ProductEvent>(&pretty)).to(be_ok().value(event));
+    }
+
+    /// Writes events as a line of comma-separated values.
+    struct CsvSerializer;
+
+    impl EventSerializer for CsvSerializer {
+        fn content_type(&self) -> &str {
+            "text/csv"
+        }
+
+        fn serialize(&self, event: &ProductEvent) -> Result<Vec<u8>, SerializeError> {
+            let fields = [&event.id, &event.name, &event.version];
+            Ok(format!("{},{}", fields.map(String::as_str).join(","), event.event).into_bytes())
+        }
+    }
+
+    #[test]
+    fn encodes_payloads_with_a_pluggable_serializer() {
+        let publisher =
+            publisher(&ProducerOptions::default()).with_serializer(Box::new(CsvSerializer));
+        let event = create_event(product(Some("42")), EventKind::Updated);
+
+        let payload = publisher.encode(&event).unwrap();
+        expect!(String::from_utf8(payload.clone()).unwrap())
+            .to(be_equal_to("42,Some Product,v2,UPDATED"));
+        let record = publisher.record(&event, &payload, "some-correlation-id");
+        let content_type = record
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.iter().find(|h| h.key == CONTENT_TYPE_HEADER))
+            .and_then(|header| header.value);
+        expect!(content_type).to(be_some().value("text/csv".as_bytes()));
+    }
+
+    #[test]
+    fn replayed_records_keep_their_headers_and_are_marked() {
+        let publisher = publisher(&ProducerOptions::default());
+        let event = create_event(product(Some("42")), EventKind::Updated);
+        let payload = serde_json::to_vec(&event).unwrap();
+
+        let record = mark_replayed(publisher.record(&event, &payload, "some-correlation-id"));
+
+        let header = |key: &str| {
+            record
+                .headers
+                .as_ref()
+                .and_then(|headers| headers.iter().find(|h| h.key == key))
+                .and_then(|header| header.value)
+        };
+        expect!(header(REPLAYED_HEADER)).to(be_some().value("true".as_bytes()));
+        expect!(header(EVENT_TYPE_HEADER)).to(be_some().value("UPDATED".as_bytes()));
+        expect!(record.key).to(be_some().value(&"42".to_string()));
+    }
+}
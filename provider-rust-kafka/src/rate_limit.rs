@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Request header identifying a client by API key rather than by address.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Buckets kept before full ones, of clients that have gone quiet, are dropped.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// A token bucket per client (`HTTP_RATE_LIMIT_PER_SEC`): every client may make `limit`
+/// requests at once, and gets `limit` more each second after that.
+pub struct RateLimiter {
+    limit: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn per_second(limit: u32) -> Self {
+        RateLimiter {
+            limit: f64::from(limit),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket at `now`. When the bucket is empty, returns how
+    /// long until the next token.
+    pub fn acquire(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.limit);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.limit,
+            refilled_at: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.limit))
+        }
+    }
+
+    /// The tokens in `bucket` at `now`, capped at `limit`.
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.limit).min(self.limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use expectest::prelude::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn rejects_requests_over_the_limit_until_the_bucket_refills() {
+        let limiter = RateLimiter::per_second(3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            expect!(limiter.acquire("ip:10.0.0.1", start)).to(be_ok());
+        }
+        let retry_after = limiter.acquire("ip:10.0.0.1", start).unwrap_err();
+        expect!(retry_after.as_millis()).to(be_equal_to(333));
+        // every client has a bucket of its own
+        expect!(limiter.acquire("ip:10.0.0.2", start)).to(be_ok());
+
+        let later = start + Duration::from_millis(400);
+        expect!(limiter.acquire("ip:10.0.0.1", later)).to(be_ok());
+        expect!(limiter.acquire("ip:10.0.0.1", later)).to(be_err());
+    }
+}
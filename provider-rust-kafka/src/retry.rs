@@ -0,0 +1,151 @@
+use std::future::Future;
+use std::time::Duration;
+
+/// How often, and how patiently, a failed publish is retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for every retry after that.
+    pub base_delay: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The un-jittered delay before retry number `retry` (starting at 1).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error `is_retriable` rejects, or
+    /// `max_attempts` is reached. `sleep` is handed the backoff delay before each retry so
+    /// callers can add jitter, and tests can observe the schedule without waiting.
+    pub async fn retry<T, E, Op, OpFut, Sleep, SleepFut>(
+        &self,
+        is_retriable: impl Fn(&E) -> bool,
+        mut sleep: Sleep,
+        mut operation: Op,
+    ) -> Result<T, E>
+    where
+        Op: FnMut(u32) -> OpFut,
+        OpFut: Future<Output = Result<T, E>>,
+        Sleep: FnMut(Duration) -> SleepFut,
+        SleepFut: Future<Output = ()>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation(attempt).await {
+                Err(err) if attempt < self.max_attempts && is_retriable(&err) => {
+                    sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// "Equal jitter": keeps half of the delay and randomises the other half, so that clients that
+/// failed together do not all retry at the same moment.
+pub fn jitter(delay: Duration) -> Duration {
+    let half = delay / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{jitter, RetryPolicy};
+    use expectest::prelude::*;
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        }
+    }
+
+    #[tokio::test]
+    async fn backs_off_exponentially_and_gives_up_after_max_attempts() {
+        let sleeps = RefCell::new(vec![]);
+        let attempts = RefCell::new(0);
+
+        let result: Result<(), &str> = policy()
+            .retry(
+                |_| true,
+                |delay| {
+                    sleeps.borrow_mut().push(delay);
+                    async {}
+                },
+                |_| {
+                    *attempts.borrow_mut() += 1;
+                    async { Err("queue full") }
+                },
+            )
+            .await;
+
+        expect!(result).to(be_err().value("queue full"));
+        expect!(*attempts.borrow()).to(be_equal_to(5));
+        expect!(sleeps.into_inner()).to(be_equal_to(vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+            Duration::from_millis(500),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn fails_fast_on_non_retriable_errors_and_stops_on_success() {
+        let attempts = RefCell::new(0);
+        let result: Result<(), &str> = policy()
+            .retry(
+                |err| *err != "message too large",
+                |_| async {},
+                |_| {
+                    *attempts.borrow_mut() += 1;
+                    async { Err("message too large") }
+                },
+            )
+            .await;
+        expect!(result).to(be_err());
+        expect!(*attempts.borrow()).to(be_equal_to(1));
+
+        let result: Result<u32, &str> = policy()
+            .retry(
+                |_| true,
+                |_| async {},
+                |attempt| async move {
+                    if attempt < 3 {
+                        Err("timed out")
+                    } else {
+                        Ok(attempt)
+                    }
+                },
+            )
+            .await;
+        expect!(result).to(be_ok().value(3));
+    }
+
+    #[test]
+    fn jitter_stays_within_half_and_full_delay() {
+        let delay = Duration::from_millis(400);
+        for _ in 0..100 {
+            let jittered = jitter(delay);
+            expect!(jittered >= Duration::from_millis(200) && jittered <= delay).to(be_true());
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use product_events::EventKind;
+use std::collections::HashMap;
+
+/// Picks the topic each product event is published to by its event type, e.g. sending
+/// `DELETED` events to a compacted tombstone topic. Event types without a route go to the
+/// default topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicRouter {
+    default: String,
+    routes: HashMap<EventKind, String>,
+}
+
+impl TopicRouter {
+    /// A router that sends every event to `default`.
+    pub fn new(default: &str) -> Self {
+        TopicRouter {
+            default: default.to_string(),
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Sends events of `kind` to `topic` instead of the default topic.
+    pub fn route(mut self, kind: EventKind, topic: &str) -> Self {
+        self.routes.insert(kind, topic.to_string());
+        self
+    }
+
+    pub fn topic_for(&self, kind: EventKind) -> &str {
+        self.routes
+            .get(&kind)
+            .map(String::as_str)
+            .unwrap_or(&self.default)
+    }
+
+    /// Every topic events may be published to: the default topic first, then the routed
+    /// topics in name order, each listed once.
+    pub fn topics(&self) -> Vec<&str> {
+        let mut routed: Vec<&str> = self.routes.values().map(String::as_str).collect();
+        routed.sort_unstable();
+        routed.dedup();
+        routed.retain(|topic| *topic != self.default);
+        let mut topics = vec![self.default.as_str()];
+        topics.extend(routed);
+        topics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopicRouter;
+    use expectest::prelude::*;
+    use product_events::EventKind;
+
+    #[test]
+    fn routes_event_types_and_falls_back_to_the_default_topic() {
+        let router = TopicRouter::new("products")
+            .route(EventKind::Created, "products")
+            .route(EventKind::Deleted, "product-tombstones");
+
+        expect!(router.topic_for(EventKind::Created)).to(be_equal_to("products"));
+        expect!(router.topic_for(EventKind::Updated)).to(be_equal_to("products"));
+        expect!(router.topic_for(EventKind::Deleted)).to(be_equal_to("product-tombstones"));
+
+        let single = TopicRouter::new("products");
+        for kind in EventKind::ALL {
+            expect!(single.topic_for(kind)).to(be_equal_to("products"));
+        }
+    }
+
+    #[test]
+    fn lists_every_topic_once() {
+        let router = TopicRouter::new("products")
+            .route(EventKind::Created, "products")
+            .route(EventKind::Updated, "product-updates")
+            .route(EventKind::Deleted, "product-tombstones");
+
+        expect!(router.topics()).to(be_equal_to(vec![
+            "products",
+            "product-tombstones",
+            "product-updates",
+        ]));
+        expect!(TopicRouter::new("products").topics()).to(be_equal_to(vec!["products"]));
+    }
+}
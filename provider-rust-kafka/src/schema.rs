@@ -0,0 +1,93 @@
+use jsonschema::Validator;
+use product_events::ProductEvent;
+use serde_json::Value;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// JSON schema every published `ProductEvent` must match, so consumers never see an event that
+/// has drifted from the contract.
+const PRODUCT_EVENT_SCHEMA: &str = include_str!("../schemas/product_event.schema.json");
+
+/// Returned when an event does not match the `ProductEvent` JSON schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    /// One message per violation, prefixed with the offending JSON pointer.
+    pub violations: Vec<String>,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "event does not match the schema: {}",
+            self.violations.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+fn validator() -> &'static Validator {
+    static VALIDATOR: OnceLock<Validator> = OnceLock::new();
+    VALIDATOR.get_or_init(|| {
+        let schema = serde_json::from_str(PRODUCT_EVENT_SCHEMA).expect("schema is valid JSON");
+        jsonschema::validator_for(&schema).expect("schema is a valid JSON schema")
+    })
+}
+
+/// Checks the event against the `ProductEvent` JSON schema.
+pub fn validate_event(event: &ProductEvent) -> Result<(), SchemaError> {
+    let value = serde_json::to_value(event).map_err(|err| SchemaError {
+        violations: vec![err.to_string()],
+    })?;
+    validate_json(&value)
+}
+
+fn validate_json(value: &Value) -> Result<(), SchemaError> {
+    let violations: Vec<String> = validator()
+        .iter_errors(value)
+        .map(|err| format!("{}: {}", err.instance_path(), err))
+        .collect();
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaError { violations })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_event, validate_json};
+    use expectest::prelude::*;
+    use product_events::{create_event, EventKind, Product, ProductType};
+    use serde_json::json;
+
+    #[test]
+    fn accepts_valid_events() {
+        let event = create_event(
+            Product {
+                id: Some("some-uuid-1234-5678".to_string()),
+                name: "Some Product".to_string(),
+                r#type: ProductType::Range,
+                version: Some("v1".to_string()),
+            },
+            EventKind::Created,
+        );
+
+        expect!(validate_event(&event)).to(be_ok());
+    }
+
+    #[test]
+    fn rejects_events_missing_a_required_field() {
+        let event = json!({
+            "id": "some-uuid-1234-5678",
+            "name": "Some Product",
+            "type": "Product Range",
+            "event": "CREATED"
+        });
+
+        let err = validate_json(&event).unwrap_err();
+        expect!(err.violations.len()).to(be_equal_to(1));
+        expect!(err.violations[0].contains("version")).to(be_true());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::config::Config;
+use crate::errors::PublishError;
+use crate::publisher::MessagePublisher;
+use product_events::{create_event, EventKind, Product, ProductEvent, ProductType};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// How long the self-test waits for its event to come back before failing.
+pub const SELFTEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Correlation id of the synthetic event, to tell it apart in the logs.
+const SELFTEST_CORRELATION_ID: &str = "selftest";
+
+/// Why the self-test failed.
+#[derive(Debug)]
+pub enum SelfTestError {
+    Publish(PublishError),
+    Consume(KafkaError),
+    /// The event did not come back within the timeout.
+    Timeout(Duration),
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestError::Publish(err) => write!(f, "failed to publish the test event: {}", err),
+            SelfTestError::Consume(err) => write!(f, "failed to consume the test event: {}", err),
+            SelfTestError::Timeout(timeout) => {
+                write!(f, "the test event did not come back within {:?}", timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SelfTestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SelfTestError::Publish(err) => Some(err),
+            SelfTestError::Consume(err) => Some(err),
+            SelfTestError::Timeout(_) => None,
+        }
+    }
+}
+
+/// A temporary topic, next to `topic`, for one run of the self-test.
+pub fn selftest_topic(topic: &str) -> String {
+    format!("{}-selftest-{}", topic, uuid::Uuid::new_v4())
+}
+
+/// An event for a product no one else knows about.
+pub fn synthetic_event() -> ProductEvent {
+    let product = Product {
+        id: Some(format!("selftest-{}", uuid::Uuid::new_v4())),
+        name: "Self-test Product".to_string(),
+        r#type: ProductType::Range,
+        version: None,
+    };
+    create_event(product, EventKind::Created)
+}
+
+/// Publishes `event` with `publisher`, then waits up to `timeout` for `receive` to read it
+/// back. Returns how long the round trip took.
+pub async fn round_trip(
+    publisher: &dyn MessagePublisher,
+    event: &ProductEvent,
+    receive: impl Future<Output = Result<(), SelfTestError>>,
+    timeout: Duration,
+) -> Result<Duration, SelfTestError> {
+    let started = Instant::now();
+    publisher
+        .publish(event, SELFTEST_CORRELATION_ID)
+        .await
+        .map_err(SelfTestError::Publish)?;
+    tokio::time::timeout(timeout, receive)
+        .await
+        .map_err(|_| SelfTestError::Timeout(timeout))??;
+    Ok(started.elapsed())
+}
+
+/// Reads `topic`, which has a single partition, from the beginning until the record keyed by
+/// `id` turns up. Connects with the same security settings as the producer.
+pub async fn receive(config: &Config, topic: &str, id: &str) -> Result<(), SelfTestError> {
+    let mut client = ClientConfig::new();
+    client
+        .set("bootstrap.servers", &config.broker)
+        .set("group.id", format!("{}-consumer", topic))
+        .set("enable.auto.commit", "false");
+    if let Some(security) = &config.producer.security {
+        security.apply(&mut client);
+    }
+    let consumer: StreamConsumer = client.create().map_err(SelfTestError::Consume)?;
+    let mut partitions = TopicPartitionList::new();
+    partitions
+        .add_partition_offset(topic, 0, Offset::Beginning)
+        .map_err(SelfTestError::Consume)?;
+    consumer
+        .assign(&partitions)
+        .map_err(SelfTestError::Consume)?;
+    loop {
+        let message = consumer.recv().await.map_err(SelfTestError::Consume)?;
+        if message.key() == Some(id.as_bytes()) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{round_trip, synthetic_event, SelfTestError};
+    use crate::publisher::RecordingPublisher;
+    use expectest::prelude::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn succeeds_once_the_published_event_is_read_back() {
+        let publisher = RecordingPublisher::default();
+        let event = synthetic_event();
+        let receive = async {
+            expect!(publisher.published()).to(be_equal_to(vec![event.clone()]));
+            Ok(())
+        };
+
+        let result = round_trip(&publisher, &event, receive, Duration::from_secs(1)).await;
+        expect!(result).to(be_ok());
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_event_does_not_come_back_in_time() {
+        let publisher = RecordingPublisher::default();
+        let event = synthetic_event();
+        let receive = futures::future::pending();
+
+        let result = round_trip(&publisher, &event, receive, Duration::from_millis(10)).await;
+        expect!(matches!(result, Err(SelfTestError::Timeout(_)))).to(be_true());
+        expect!(publisher.published().len()).to(be_equal_to(1));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::errors::PublishError;
+use product_events::{JsonCase, ProductEvent};
+use std::fmt;
+
+/// Errors raised while encoding a `ProductEvent` as a record payload.
+#[derive(Debug)]
+pub enum SerializeError {
+    Json(serde_json::Error),
+    #[cfg(feature = "avro")]
+    Avro(crate::avro::AvroError),
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::Json(err) => write!(f, "{}", err),
+            #[cfg(feature = "avro")]
+            SerializeError::Avro(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SerializeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SerializeError::Json(err) => Some(err),
+            #[cfg(feature = "avro")]
+            SerializeError::Avro(err) => Some(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for SerializeError {
+    fn from(err: serde_json::Error) -> Self {
+        SerializeError::Json(err)
+    }
+}
+
+impl From<SerializeError> for PublishError {
+    fn from(err: SerializeError) -> Self {
+        match err {
+            SerializeError::Json(err) => PublishError::Serialization(err),
+            #[cfg(feature = "avro")]
+            SerializeError::Avro(err) => PublishError::Avro(err),
+        }
+    }
+}
+
+/// Encodes events as record payloads. The publisher sends `content_type` as the record's
+/// `content-type` header, so consumers can tell the formats apart.
+pub trait EventSerializer: Send + Sync {
+    fn content_type(&self) -> &str;
+    fn serialize(&self, event: &ProductEvent) -> Result<Vec<u8>, SerializeError>;
+}
+
+/// JSON, with keys named in the configured [`JsonCase`]. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonSerializer {
+    pub json_case: JsonCase,
+    /// Indents the JSON, for reading payloads in a console consumer.
+    pub pretty: bool,
+}
+
+impl EventSerializer for JsonSerializer {
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn serialize(&self, event: &ProductEvent) -> Result<Vec<u8>, SerializeError> {
+        if self.pretty {
+            Ok(self.json_case.to_vec_pretty(event)?)
+        } else {
+            Ok(self.json_case.to_vec(event)?)
+        }
+    }
+}
+
+/// Protobuf, as described by `proto/product_event.proto`.
+#[cfg(feature = "protobuf")]
+pub struct ProtobufSerializer;
+
+#[cfg(feature = "protobuf")]
+impl EventSerializer for ProtobufSerializer {
+    fn content_type(&self) -> &str {
+        "application/x-protobuf"
+    }
+
+    fn serialize(&self, event: &ProductEvent) -> Result<Vec<u8>, SerializeError> {
+        Ok(crate::protobuf::encode(event))
+    }
+}
+
+/// A structured-mode CloudEvent, with the event as its JSON `data`.
+#[cfg(feature = "cloudevents")]
+pub struct CloudEventsSerializer(pub crate::cloudevents::CloudEventsOptions);
+
+#[cfg(feature = "cloudevents")]
+impl EventSerializer for CloudEventsSerializer {
+    fn content_type(&self) -> &str {
+        "application/cloudevents+json"
+    }
+
+    fn serialize(&self, event: &ProductEvent) -> Result<Vec<u8>, SerializeError> {
+        Ok(self.0.envelope(event)?)
+    }
+}
+
+/// Confluent-framed Avro, with the id of the schema registered at startup.
+#[cfg(feature = "avro")]
+impl EventSerializer for crate::avro::AvroEncoder {
+    fn content_type(&self) -> &str {
+        "application/vnd.apache.avro+binary"
+    }
+
+    fn serialize(&self, event: &ProductEvent) -> Result<Vec<u8>, SerializeError> {
+        self.encode(event).map_err(SerializeError::Avro)
+    }
+}
@@ -0,0 +1,119 @@
+use crate::version::is_valid_version;
+use crate::Product;
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+/// A single invalid field on an incoming request.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: &'static str,
+}
+
+/// Returned when a `Product` fails validation, listing every offending field.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ValidationError {
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<&str> = self.errors.iter().map(|error| error.field).collect();
+        write!(f, "invalid fields: {}", fields.join(", "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Product {
+    /// Checks that the product has a name and type, and that any supplied version is a
+    /// `vN` or semver version.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.check(false)
+    }
+
+    /// Like [`Product::validate`], but also rejects types other than the known
+    /// [`ProductType`](crate::ProductType)s.
+    pub fn validate_strict(&self) -> Result<(), ValidationError> {
+        self.check(true)
+    }
+
+    fn check(&self, strict: bool) -> Result<(), ValidationError> {
+        let mut errors = vec![];
+        if self.name.trim().is_empty() {
+            errors.push(FieldError {
+                field: "name",
+                message: "must not be empty",
+            });
+        }
+        if self.r#type.to_string().trim().is_empty() {
+            errors.push(FieldError {
+                field: "type",
+                message: "must not be empty",
+            });
+        } else if strict && !self.r#type.is_known() {
+            errors.push(FieldError {
+                field: "type",
+                message: "must be a known product type",
+            });
+        }
+        if let Some(version) = &self.version {
+            if !is_valid_version(version) {
+                errors.push(FieldError {
+                    field: "version",
+                    message: "must look like v1 or 1.0.0",
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { errors })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Product;
+    use expectest::prelude::*;
+
+    fn product(name: &str, r#type: &str, version: Option<&str>) -> Product {
+        Product {
+            id: None,
+            name: name.to_string(),
+            r#type: r#type.into(),
+            version: version.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn validates_product_fields() {
+        expect!(product("Some Product", "Product Range", None).validate()).to(be_ok());
+        expect!(product("Some Product", "Product Range", Some("v1")).validate()).to(be_ok());
+        expect!(product("Some Product", "Product Range", Some("1.2.3")).validate()).to(be_ok());
+
+        let field_errors = |product: Product| -> Vec<&'static str> {
+            let err = product.validate().unwrap_err();
+            err.errors.iter().map(|error| error.field).collect()
+        };
+        expect!(field_errors(product("", "Product Range", None))).to(be_equal_to(vec!["name"]));
+        expect!(field_errors(product("Some Product", " ", None))).to(be_equal_to(vec!["type"]));
+        let bad_version = product("Some Product", "Product Range", Some("one"));
+        expect!(field_errors(bad_version)).to(be_equal_to(vec!["version"]));
+        expect!(field_errors(product("", "", Some("v1.x"))))
+            .to(be_equal_to(vec!["name", "type", "version"]));
+    }
+
+    #[test]
+    fn strict_validation_rejects_unknown_types() {
+        expect!(product("Some Product", "Gadget", None).validate()).to(be_ok());
+        expect!(product("Some Product", "Food", None).validate_strict()).to(be_ok());
+        let err = product("Some Product", "Gadget", None)
+            .validate_strict()
+            .unwrap_err();
+        expect!(err.errors[0].message).to(be_equal_to("must be a known product type"));
+    }
+}
@@ -0,0 +1,1120 @@
+use crate::retry::RetryPolicy;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use anyhow::Context;
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use maplit::*;
+use pact_models::http_utils::HttpAuth;
+use pact_models::provider_states::ProviderState;
+use pact_verifier::{
+    callback_executors::ProviderStateExecutor,
+    selectors::json_to_selectors,
+    verification_result::{VerificationExecutionResult, VerificationMismatchResult},
+    verify_provider_async, ConsumerVersionSelector, FilterInfo, NullRequestFilterExecutor,
+    PactSource, ProviderInfo, ProviderTransport, PublishOptions, VerificationOptions,
+};
+use product_events::{
+    create_event, EventKind, Product, ProductEvent, ProductType, EVENT_TYPE_HEADER, SCHEMA_VERSION,
+    SCHEMA_VERSION_HEADER,
+};
+use serde_json::json;
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+/// The pact the consumer tests write, verified when no Pact Broker is configured.
+pub const DEFAULT_PACT_FILE: &str = "../consumer-rust-kafka/target/pacts/\
+    pactflow-example-consumer-rust-kafka-pactflow-example-provider-rust-kafka.json";
+
+/// Products seeded by provider states, read by the message proxy when it builds a message.
+type ProductStore = web::Data<Mutex<HashMap<String, Product>>>;
+
+/// Sets up the products that provider states describe:
+///
+/// * `a product with id {id} exists` (or an `id` parameter) seeds that product
+/// * `a product exists` seeds a product with a generated id
+///
+/// The seeded id is returned as `productId`, the value consumer pacts inject with a
+/// `fromProviderState` generator (`${productId}`). The verifier only applies such generators
+/// to HTTP requests, so for messages the proxy builds the event from the seeded product, which
+/// gives it the injected id.
+#[derive(Debug)]
+struct ProductStateExecutor {
+    store: ProductStore,
+}
+
+impl ProductStateExecutor {
+    fn seed(&self, id: String) -> HashMap<String, Value> {
+        let product = Product {
+            id: Some(id.clone()),
+            name: "Some Product".to_string(),
+            r#type: ProductType::Range,
+            version: Some("v1".to_string()),
+        };
+        self.store.lock().unwrap().insert(id.clone(), product);
+        hashmap! { "productId".to_string() => json!(id) }
+    }
+}
+
+#[async_trait]
+impl ProviderStateExecutor for ProductStateExecutor {
+    async fn call(
+        self: Arc<Self>,
+        _interaction_id: Option<String>,
+        provider_state: &ProviderState,
+        setup: bool,
+        _client: std::option::Option<&reqwest::Client>,
+    ) -> anyhow::Result<HashMap<String, Value>> {
+        if !setup {
+            self.store.lock().unwrap().clear();
+            return Ok(hashmap! {});
+        }
+
+        let param_id = provider_state.params.get("id").and_then(Value::as_str);
+        let named_id = provider_state
+            .name
+            .strip_prefix("a product with id ")
+            .and_then(|rest| rest.strip_suffix(" exists"));
+        match (param_id.or(named_id), provider_state.name.as_str()) {
+            (Some(id), _) => Ok(self.seed(id.to_string())),
+            // the verifier passes an unnamed state for interactions without any
+            (None, "") => Ok(hashmap! {}),
+            (None, "a product exists") => Ok(self.seed(uuid::Uuid::new_v4().to_string())),
+            (None, name) => Err(anyhow::anyhow!("unknown provider state {:?}", name)),
+        }
+    }
+
+    fn teardown(&self) -> bool {
+        true
+    }
+}
+
+/// Response header the verifier reads message metadata from, as base64-encoded JSON.
+const MESSAGE_METADATA_HEADER: &str = "pact-message-metadata";
+
+/// Metadata of the Kafka record `event` is published as: its topic, its key and its
+/// `event-type` and `schema-version` headers.
+fn message_metadata(topic: &str, event: &ProductEvent) -> Value {
+    json!({
+        "kafka_topic": topic,
+        "kafka_key": event.id,
+        EVENT_TYPE_HEADER: event.event,
+        SCHEMA_VERSION_HEADER: SCHEMA_VERSION.to_string(),
+    })
+}
+
+fn encode_message_metadata(value: &Value) -> String {
+    general_purpose::STANDARD.encode(value.to_string())
+}
+
+async fn handle_message_request(
+    req: HttpRequest,
+    store: ProductStore,
+    body: web::Json<serde_json::Value>,
+) -> impl Responder {
+    debug!(method = %req.method(), path = req.path(), body = %body, "message request");
+
+    let kind = match body["description"].as_str() {
+        Some("a product event create") => EventKind::Created,
+        Some("a product event update") => EventKind::Updated,
+        Some("a product event delete") => EventKind::Deleted,
+        _ => return HttpResponse::NotFound().finish(),
+    };
+
+    let product = message_product(&body, &store);
+    // products without an id get a fresh one, as the API gives them
+    let product_event = create_event(product, kind);
+    let metadata = message_metadata("products", &product_event);
+    let mut response = HttpResponse::Ok().json(product_event);
+    response.headers_mut().insert(
+        HeaderName::from_static(MESSAGE_METADATA_HEADER),
+        HeaderValue::from_str(&encode_message_metadata(&metadata)).unwrap(),
+    );
+    response
+}
+
+/// The product a message is produced for. A product supplied with the interaction wins over
+/// one seeded by a provider state, and any `id`, `name`, `type` or `version` parameter of the
+/// interaction's provider states is applied on top. Fields left unset get defaults.
+fn message_product(body: &Value, store: &ProductStore) -> Product {
+    let supplied = serde_json::from_value::<Product>(body["product"].clone()).ok();
+    let seeded = || store.lock().unwrap().values().next().cloned();
+    let product = supplied.or_else(seeded).unwrap_or_else(|| Product {
+        id: None,
+        name: "Some Product".to_string(),
+        r#type: ProductType::Range,
+        version: Some("v1".to_string()),
+    });
+
+    let mut fields = serde_json::to_value(&product).unwrap();
+    let states = body["providerStates"].as_array().into_iter().flatten();
+    for params in states.filter_map(|state| state["params"].as_object()) {
+        for field in ["id", "name", "type", "version"] {
+            if let Some(value) = params.get(field) {
+                fields[field] = value.clone();
+            }
+        }
+    }
+    serde_json::from_value(fields).unwrap_or(product)
+}
+
+/// Serves a product seeded by a provider state, for request/response interactions.
+async fn handle_product_request(store: ProductStore, id: web::Path<String>) -> HttpResponse {
+    match store.lock().unwrap().get(id.as_str()) {
+        Some(product) => HttpResponse::Ok().json(product),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Where the message proxy listens, on a port the OS assigns.
+const PROXY_BIND_ADDR: &str = "127.0.0.1:0";
+
+/// How binding the message proxy is retried: `PACT_PROXY_BIND_ATTEMPTS` attempts, 3 by
+/// default, a short while apart.
+fn proxy_bind_retries(lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<RetryPolicy> {
+    let max_attempts = match lookup("PACT_PROXY_BIND_ATTEMPTS") {
+        Some(value) => value
+            .parse()
+            .ok()
+            .filter(|attempts| *attempts > 0)
+            .with_context(|| {
+                format!("PACT_PROXY_BIND_ATTEMPTS must be a positive number, not {value:?}")
+            })?,
+        None => 3,
+    };
+    Ok(RetryPolicy {
+        max_attempts,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+    })
+}
+
+/// Starts the message proxy on `addr`, returning the port it listens on and a sender that
+/// shuts the proxy down. A failed bind is retried according to `retries`. Besides messages it
+/// serves `GET /products/{id}`, so pacts with request/response interactions can be verified
+/// against it too.
+async fn start_message_proxy(
+    store: ProductStore,
+    addr: &str,
+    retries: &RetryPolicy,
+) -> anyhow::Result<(u16, oneshot::Sender<()>)> {
+    let (tx, rx) = oneshot::channel();
+    let server = retries
+        .retry(
+            |_| true,
+            tokio::time::sleep,
+            |attempt| {
+                if attempt > 1 {
+                    warn!(attempt, addr, "retrying to bind the message proxy");
+                }
+                let store = store.clone();
+                futures::future::ready(
+                    HttpServer::new(move || {
+                        App::new()
+                            .app_data(store.clone())
+                            .route("/pact-messages", web::post().to(handle_message_request))
+                            .route("/products/{id}", web::get().to(handle_product_request))
+                    })
+                    .bind(addr),
+                )
+            },
+        )
+        .await
+        .with_context(|| format!("failed to bind the message proxy to {}", addr))?;
+    let port = server.addrs()[0].port();
+    let server = server.run();
+    let server_handle = server.handle();
+    // let _ = server.await;
+    tokio::spawn(async move {
+        let _ = server.await;
+        rx.await.ok();
+        server_handle.stop(true).await;
+    });
+
+    Ok((port, tx))
+}
+
+const PROVIDER_NAME: &str = "pactflow-example-provider-rust-kafka";
+
+/// The transports the proxy on `port` serves: `async-message` for message interactions and
+/// `http` for request/response ones.
+fn proxy_transports(port: u16) -> Vec<ProviderTransport> {
+    vec![
+        ProviderTransport {
+            transport: "async-message".to_string(),
+            port: Some(port),
+            path: Some("/pact-messages".to_string()),
+            scheme: Some("http".to_string()),
+        },
+        ProviderTransport {
+            transport: "http".to_string(),
+            port: Some(port),
+            path: None,
+            scheme: Some("http".to_string()),
+        },
+    ]
+}
+
+/// The provider, reached over `transports`. The verifier picks the transport of each
+/// interaction by its kind, each transport may listen on a port of its own.
+fn provider_info(transports: Vec<ProviderTransport>) -> ProviderInfo {
+    #[allow(deprecated)]
+    ProviderInfo {
+        name: PROVIDER_NAME.to_string(),
+        host: "127.0.0.1".to_string(),
+        port: transports.first().and_then(|transport| transport.port),
+        transports,
+        ..ProviderInfo::default()
+    }
+}
+
+/// Selects which consumer pacts are fetched from the broker. `PACT_CONSUMER_VERSION_SELECTORS`
+/// takes a JSON array of selectors in the broker's format, for example
+///
+/// ```json
+/// [{ "mainBranch": true }, { "deployedOrReleased": true, "environment": "production" }]
+/// ```
+///
+/// Each selector may set `mainBranch`, `branch`, `matchingBranch`, `fallbackBranch`,
+/// `deployedOrReleased`, `deployed`, `released`, `environment`, `consumer`, `tag`, `latest`
+/// and `fallbackTag`.
+/// Without it only the pacts from each consumer's main branch are verified.
+fn consumer_version_selectors(
+    lookup: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<Vec<ConsumerVersionSelector>> {
+    match lookup("PACT_CONSUMER_VERSION_SELECTORS") {
+        Some(selectors) => serde_json::from_str(&selectors)
+            .with_context(|| "PACT_CONSUMER_VERSION_SELECTORS must be a JSON array of selectors"),
+        None => Ok(json_to_selectors(vec![json!({ "mainBranch": true })])),
+    }
+}
+
+/// Narrows verification to the interactions whose description matches the regular
+/// expression in `PACT_FILTER_DESCRIPTION`, so one message can be iterated on at a time.
+fn verification_filter(lookup: impl Fn(&str) -> Option<String>) -> FilterInfo {
+    match lookup("PACT_FILTER_DESCRIPTION") {
+        Some(description) => FilterInfo::Description(description),
+        None => FilterInfo::None,
+    }
+}
+
+/// Verifies against the Pact Broker at `PACT_BROKER_URL` when it is set, publishing the
+/// results as `PROVIDER_VERSION` if that is set too. Without a broker every `*.json` pact file
+/// in `PACT_DIR` is verified, one source per file, or else the local pact file written by the
+/// consumer tests, and nothing is published.
+fn verification_sources(
+    lookup: impl Fn(&str) -> Option<String>,
+    pact_file: String,
+) -> anyhow::Result<(Vec<PactSource>, Option<PublishOptions>)> {
+    let Some(broker_url) = lookup("PACT_BROKER_URL") else {
+        let sources = match lookup("PACT_DIR") {
+            Some(dir) => pact_files(&dir)?,
+            None => vec![PactSource::File(pact_file)],
+        };
+        return Ok((sources, None));
+    };
+    let source = PactSource::BrokerWithDynamicConfiguration {
+        provider_name: PROVIDER_NAME.to_string(),
+        broker_url,
+        enable_pending: false,
+        include_wip_pacts_since: None,
+        provider_tags: vec![],
+        provider_branch: None,
+        selectors: consumer_version_selectors(&lookup)?,
+        auth: lookup("PACT_BROKER_TOKEN").map(HttpAuth::Token),
+        links: vec![],
+    };
+    let publish_options = lookup("PROVIDER_VERSION").map(|version| PublishOptions {
+        provider_version: Some(version),
+        ..PublishOptions::default()
+    });
+    Ok((vec![source], publish_options))
+}
+
+/// The `*.json` files in `dir`, in name order. Fails when there are none.
+fn pact_files(dir: &str) -> anyhow::Result<Vec<PactSource>> {
+    let entries = std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir))?;
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read {}", dir))?
+            .path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+        {
+            files.push(path.display().to_string());
+        }
+    }
+    if files.is_empty() {
+        anyhow::bail!("no pact files found in {}", dir);
+    }
+    files.sort();
+    Ok(files.into_iter().map(PactSource::File).collect())
+}
+
+/// Names `source` in failures: the path of a pact file, the kind of source otherwise.
+fn source_name(source: &PactSource) -> String {
+    match source {
+        PactSource::File(file) => file.clone(),
+        source => source.to_string(),
+    }
+}
+
+/// Verifies the messages this provider produces against the consumer pacts, serving them
+/// through a local message proxy, along with any request/response interactions. Pacts come
+/// from the broker, `PACT_DIR` or `pact_file`, see [`verification_sources`], and each source
+/// is verified on its own. `PACT_JUNIT_OUTPUT` names a file to write a JUnit report of all of
+/// them to. Fails with every failing interaction and its mismatches, by source.
+pub async fn verify(
+    lookup: impl Fn(&str) -> Option<String>,
+    pact_file: String,
+) -> anyhow::Result<()> {
+    let (pact_sources, publish_options) = verification_sources(&lookup, pact_file)?;
+
+    let store = ProductStore::new(Mutex::new(HashMap::new()));
+    let retries = proxy_bind_retries(&lookup)?;
+    let (port, shutdown_tx) = start_message_proxy(store.clone(), PROXY_BIND_ADDR, &retries).await?;
+    let provider_info = provider_info(proxy_transports(port));
+
+    let verification_options: VerificationOptions<NullRequestFilterExecutor> =
+        VerificationOptions::default();
+    let provider_state_executor = Arc::new(ProductStateExecutor { store });
+
+    let mut results = vec![];
+    for pact_source in pact_sources {
+        let result = verify_provider_async(
+            provider_info.clone(),
+            vec![pact_source.clone()],
+            verification_filter(&lookup),
+            vec![],
+            &verification_options,
+            publish_options.as_ref(),
+            &provider_state_executor,
+            None,
+        )
+        .await;
+        results.push((source_name(&pact_source), result));
+    }
+
+    // shutdown our message proxy
+    let _ = shutdown_tx.send(());
+
+    // check the verification results
+    let mut report = VerificationExecutionResult::new();
+    let mut failures = vec![];
+    for (source, result) in results {
+        let result = result.with_context(|| {
+            format!(
+                "failed to get pact verification execution result for {}",
+                source
+            )
+        })?;
+        if !result.result {
+            failures.push(format!(
+                "{}:\n{}",
+                source,
+                verification_failures(&result).join("\n")
+            ));
+        }
+        report.result &= result.result;
+        report.errors.extend(result.errors);
+        report
+            .interaction_results
+            .extend(result.interaction_results);
+    }
+    if let Some(path) = lookup("PACT_JUNIT_OUTPUT") {
+        std::fs::write(&path, junit_report(&report))
+            .with_context(|| format!("failed to write JUnit report to {}", path))?;
+    }
+    if !failures.is_empty() {
+        anyhow::bail!("Pact verification failed:\n{}", failures.join("\n"));
+    }
+    Ok(())
+}
+
+/// One line per failed interaction: its description and every mismatch or error.
+fn verification_failures(result: &VerificationExecutionResult) -> Vec<String> {
+    result
+        .errors
+        .iter()
+        .map(|(description, mismatch)| {
+            format!(
+                "{}\n  {}",
+                description,
+                mismatch_messages(mismatch).join("\n  ")
+            )
+        })
+        .collect()
+}
+
+fn mismatch_messages(mismatch: &VerificationMismatchResult) -> Vec<String> {
+    match mismatch {
+        VerificationMismatchResult::Mismatches { mismatches, .. } => mismatches
+            .iter()
+            .map(|mismatch| mismatch.description())
+            .collect(),
+        VerificationMismatchResult::Error { error, .. } => vec![error.clone()],
+    }
+}
+
+/// Renders the verification as a JUnit XML report with a test case per interaction, for CI
+/// servers to pick up. Errors that do not belong to an interaction, such as a pact that
+/// failed to load, get a failed test case of their own.
+fn junit_report(result: &VerificationExecutionResult) -> String {
+    let mut cases = vec![];
+    for interaction in &result.interaction_results {
+        let failure = interaction
+            .result
+            .as_ref()
+            .err()
+            .map(|mismatch| mismatch_messages(&mismatch.into()));
+        cases.push((
+            interaction.description.clone(),
+            interaction.duration.as_secs_f64(),
+            failure,
+        ));
+    }
+    for (description, mismatch) in &result.errors {
+        if !cases.iter().any(|(name, _, _)| name == description) {
+            cases.push((description.clone(), 0.0, Some(mismatch_messages(mismatch))));
+        }
+    }
+
+    let failures = cases
+        .iter()
+        .filter(|(_, _, failure)| failure.is_some())
+        .count();
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+        xml_escape(PROVIDER_NAME),
+        cases.len(),
+        failures
+    );
+    for (name, seconds, failure) in cases {
+        xml.push_str(&format!(
+            "  <testcase name=\"{}\" time=\"{:.3}\">",
+            xml_escape(&name),
+            seconds
+        ));
+        if let Some(messages) = failure {
+            xml.push_str(&format!(
+                "<failure message=\"{}\">{}</failure>",
+                xml_escape(messages.first().map(String::as_str).unwrap_or_default()),
+                xml_escape(&messages.join("\n"))
+            ));
+        }
+        xml.push_str("</testcase>\n");
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use expectest::prelude::*;
+    use pact_verifier::{verification_result::VerificationInteractionResult, MismatchResult};
+    use std::env;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn provider_state_values_flow_into_the_produced_event() {
+        let store = ProductStore::new(Mutex::new(HashMap::new()));
+        let executor = Arc::new(ProductStateExecutor {
+            store: store.clone(),
+        });
+        let state = ProviderState::default("a product exists");
+        let values = executor
+            .clone()
+            .call(None, &state, true, None)
+            .await
+            .unwrap();
+        let id = values["productId"].as_str().unwrap().to_string();
+
+        let app = init_service(
+            App::new()
+                .app_data(store.clone())
+                .route("/pact-messages", web::post().to(handle_message_request)),
+        )
+        .await;
+        let request = TestRequest::post()
+            .uri("/pact-messages")
+            .set_json(json!({
+                "description": "a product event update",
+                "providerStates": [{ "name": "a product exists" }]
+            }))
+            .to_request();
+        let response = call_service(&app, request).await;
+        let header = response.headers().get(MESSAGE_METADATA_HEADER).unwrap();
+        let metadata = decode_message_metadata(header.to_str().unwrap()).unwrap();
+        let event: Value = read_body_json(response).await;
+        expect!(event["id"].as_str()).to(be_some().value(id.as_str()));
+        expect!(metadata["kafka_key"].as_str()).to(be_some().value(id.as_str()));
+
+        let state = ProviderState::default("a product with id 42 exists");
+        let values = executor.clone().call(None, &state, true, None).await;
+        expect!(values.unwrap().get("productId")).to(be_some().value(&json!("42")));
+        let unknown = ProviderState::default("the moon is full");
+        expect!(executor.clone().call(None, &unknown, true, None).await).to(be_err());
+        executor.call(None, &state, false, None).await.unwrap();
+        expect!(store.lock().unwrap().is_empty()).to(be_true());
+    }
+
+    #[actix_web::test]
+    async fn message_proxy_maps_descriptions_to_event_types() {
+        let store = ProductStore::new(Mutex::new(HashMap::new()));
+        let app = init_service(
+            App::new()
+                .app_data(store)
+                .route("/pact-messages", web::post().to(handle_message_request)),
+        )
+        .await;
+        let message = |body: Value| {
+            TestRequest::post()
+                .uri("/pact-messages")
+                .set_json(body)
+                .to_request()
+        };
+
+        for (description, event_type) in [
+            ("a product event create", "CREATED"),
+            ("a product event update", "UPDATED"),
+            ("a product event delete", "DELETED"),
+        ] {
+            let request = message(json!({ "description": description }));
+            let event: Value = read_body_json(call_service(&app, request).await).await;
+            expect!(event["event"].as_str()).to(be_some().value(event_type));
+        }
+
+        let request = message(json!({
+            "description": "a product event create",
+            "product": { "id": "42", "name": "Other Product", "type": "Range" }
+        }));
+        let event: Value = read_body_json(call_service(&app, request).await).await;
+        expect!(event["id"].as_str()).to(be_some().value("42"));
+        expect!(event["name"].as_str()).to(be_some().value("Other Product"));
+
+        let request = message(json!({
+            "description": "a product event update",
+            "providerStates": [
+                { "name": "a product with id 43 exists", "params": { "id": "43", "version": "v7" } }
+            ]
+        }));
+        let event: Value = read_body_json(call_service(&app, request).await).await;
+        expect!(event["id"].as_str()).to(be_some().value("43"));
+        expect!(event["name"].as_str()).to(be_some().value("Some Product"));
+        expect!(event["version"].as_str()).to(be_some().value("v8"));
+
+        let request = message(json!({ "description": "a product event archive" }));
+        expect!(call_service(&app, request).await.status().as_u16()).to(be_equal_to(404));
+    }
+
+    fn decode_message_metadata(header: &str) -> anyhow::Result<Value> {
+        let bytes = general_purpose::STANDARD
+            .decode(header)
+            .context("message metadata is not valid base64")?;
+        let json = String::from_utf8(bytes).context("message metadata is not valid UTF-8")?;
+        serde_json::from_str(&json).context("message metadata is not valid JSON")
+    }
+
+    #[actix_web::test]
+    async fn message_metadata_round_trips_through_the_proxy_header() {
+        let app = init_service(
+            App::new()
+                .app_data(ProductStore::new(Mutex::new(HashMap::new())))
+                .route("/pact-messages", web::post().to(handle_message_request)),
+        )
+        .await;
+        let request = TestRequest::post()
+            .uri("/pact-messages")
+            .set_json(json!({ "description": "a product event update" }))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        let header = response.headers().get(MESSAGE_METADATA_HEADER).unwrap();
+        let metadata = decode_message_metadata(header.to_str().unwrap()).unwrap();
+        let event: Value = read_body_json(response).await;
+        expect!(metadata).to(be_equal_to(json!({
+            "kafka_topic": "products",
+            "kafka_key": event["id"],
+            "event-type": "UPDATED",
+            "schema-version": "1"
+        })));
+    }
+
+    #[test]
+    fn rejects_malformed_message_metadata() {
+        let metadata = json!({ "kafka_topic": "products" });
+        let decoded = decode_message_metadata(&encode_message_metadata(&metadata));
+        expect!(decoded.unwrap()).to(be_equal_to(metadata));
+
+        let err = decode_message_metadata("not base64!").unwrap_err();
+        expect!(err.to_string()).to(be_equal_to("message metadata is not valid base64"));
+        let not_utf8 = general_purpose::STANDARD.encode([0xff, 0xfe]);
+        let err = decode_message_metadata(&not_utf8).unwrap_err();
+        expect!(err.to_string()).to(be_equal_to("message metadata is not valid UTF-8"));
+    }
+    #[test]
+    fn verifies_the_local_pact_file_unless_a_broker_is_configured() {
+        let (sources, publish_options) =
+            verification_sources(|_| None, "pact.json".to_string()).unwrap();
+        expect!(matches!(&sources[..], [PactSource::File(file)] if file == "pact.json"))
+            .to(be_true());
+        expect!(publish_options).to(be_none());
+
+        let (mut sources, publish_options) = verification_sources(
+            |name| match name {
+                "PACT_BROKER_URL" => Some("https://broker.example.com".to_string()),
+                "PACT_BROKER_TOKEN" => Some("secret".to_string()),
+                "PROVIDER_VERSION" => Some("abc123".to_string()),
+                _ => None,
+            },
+            "pact.json".to_string(),
+        )
+        .unwrap();
+        expect!(sources.len()).to(be_equal_to(1));
+        match sources.remove(0) {
+            PactSource::BrokerWithDynamicConfiguration {
+                broker_url, auth, ..
+            } => {
+                expect!(broker_url).to(be_equal_to("https://broker.example.com"));
+                expect!(matches!(auth, Some(HttpAuth::Token(token)) if token == "secret"))
+                    .to(be_true());
+            }
+            _ => panic!("expected a broker source"),
+        }
+        expect!(publish_options.and_then(|options| options.provider_version))
+            .to(be_some().value("abc123"));
+    }
+
+    #[test]
+    fn consumer_version_selectors_default_to_the_main_branch() {
+        let selectors = consumer_version_selectors(|_| None).unwrap();
+        expect!(selectors.len()).to(be_equal_to(1));
+        expect!(selectors[0].main_branch).to(be_some().value(true));
+
+        let selectors = consumer_version_selectors(|name| {
+            (name == "PACT_CONSUMER_VERSION_SELECTORS").then(|| {
+                r#"[{ "mainBranch": true },
+                    { "deployedOrReleased": true, "environment": "production" }]"#
+                    .to_string()
+            })
+        })
+        .unwrap();
+        expect!(selectors.len()).to(be_equal_to(2));
+        expect!(selectors[1].deployed_or_released).to(be_some().value(true));
+        expect!(selectors[1].environment.as_deref()).to(be_some().value("production"));
+    }
+
+    #[test]
+    fn rejects_malformed_consumer_version_selectors() {
+        let selectors = consumer_version_selectors(|name| {
+            (name == "PACT_CONSUMER_VERSION_SELECTORS").then(|| "{ not json".to_string())
+        });
+        expect!(selectors).to(be_err());
+    }
+
+    #[test]
+    fn filters_interactions_by_description_when_asked() {
+        expect!(verification_filter(|_| None)).to(be_equal_to(FilterInfo::None));
+        let filter = verification_filter(|name| {
+            (name == "PACT_FILTER_DESCRIPTION").then(|| "product event update".to_string())
+        });
+        expect!(filter).to(be_equal_to(FilterInfo::Description(
+            "product event update".to_string(),
+        )));
+    }
+
+    #[test]
+    fn verification_failures_name_the_interaction_and_mismatch() {
+        let mut result = VerificationExecutionResult::new();
+        result.result = false;
+        for (description, outcome) in [
+            ("a product event create", Ok(())),
+            (
+                "a product event delete",
+                Err(MismatchResult::Error(
+                    "Expected metadata key 'kafka_key' to be <42>".to_string(),
+                    None,
+                )),
+            ),
+        ] {
+            if let Err(mismatch) = &outcome {
+                result
+                    .errors
+                    .push((description.to_string(), mismatch.into()));
+            }
+            result
+                .interaction_results
+                .push(VerificationInteractionResult {
+                    interaction_id: None,
+                    interaction_key: None,
+                    description: description.to_string(),
+                    interaction_description: description.to_string(),
+                    result: outcome,
+                    pending: false,
+                    duration: Duration::from_millis(12),
+                });
+        }
+
+        expect!(verification_failures(&result)).to(be_equal_to(vec![
+            "a product event delete\n  Expected metadata key 'kafka_key' to be <42>".to_string(),
+        ]));
+        let report = junit_report(&result);
+        expect!(report.contains(r#"tests="2" failures="1""#)).to(be_true());
+        expect!(
+            report.contains(r#"<testcase name="a product event create" time="0.012"></testcase>"#)
+        )
+        .to(be_true());
+        expect!(report.contains(
+            "<failure message=\"Expected metadata key &apos;kafka_key&apos; to be &lt;42&gt;\">"
+        ))
+        .to(be_true());
+    }
+
+    #[tokio::test]
+    async fn freshly_generated_ids_verify_against_the_consumer_pact() {
+        let app = init_service(
+            App::new()
+                .app_data(ProductStore::new(Mutex::new(HashMap::new())))
+                .route("/pact-messages", web::post().to(handle_message_request)),
+        )
+        .await;
+        let mut ids = vec![];
+        for _ in 0..2 {
+            let request = TestRequest::post()
+                .uri("/pact-messages")
+                .set_json(json!({ "description": "a product event create" }))
+                .to_request();
+            let event: Value = read_body_json(call_service(&app, request).await).await;
+            let id = event["id"].as_str().unwrap().to_string();
+            expect!(uuid::Uuid::parse_str(&id)).to(be_ok());
+            ids.push(id);
+        }
+        expect!(&ids[0]).to_not(be_equal_to(&ids[1]));
+
+        let lookup = |name: &str| match name {
+            "PACT_FILTER_DESCRIPTION" => Some("a product event create".to_string()),
+            _ => None,
+        };
+        if let Err(err) = verify(lookup, DEFAULT_PACT_FILE.to_string()).await {
+            panic!("{:#}", err);
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_proxy_binds_to_a_free_port() {
+        let store = ProductStore::new(Mutex::new(HashMap::new()));
+        let retries = proxy_bind_retries(|_| None).unwrap();
+        expect!(retries.max_attempts).to(be_equal_to(3));
+
+        let (port, shutdown_tx) = start_message_proxy(store, PROXY_BIND_ADDR, &retries)
+            .await
+            .unwrap();
+        expect!(port).to_not(be_equal_to(0));
+        let _ = shutdown_tx.send(());
+    }
+
+    #[actix_web::test]
+    async fn gives_up_binding_the_proxy_after_the_configured_attempts() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap().to_string();
+        let retries = proxy_bind_retries(|name| match name {
+            "PACT_PROXY_BIND_ATTEMPTS" => Some("2".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        expect!(retries.max_attempts).to(be_equal_to(2));
+
+        let store = ProductStore::new(Mutex::new(HashMap::new()));
+        let err = start_message_proxy(store, &addr, &retries)
+            .await
+            .unwrap_err();
+        expect!(err.to_string()).to(be_equal_to(format!(
+            "failed to bind the message proxy to {}",
+            addr
+        )));
+        expect!(proxy_bind_retries(|_| Some("0".to_string()))).to(be_err());
+    }
+
+    #[test]
+    fn the_proxy_is_reached_over_each_transport() {
+        let info = provider_info(proxy_transports(9000));
+        let urls: Vec<_> = info
+            .transports
+            .iter()
+            .map(|transport| (transport.transport.as_str(), transport.base_url(&info.host)))
+            .collect();
+        expect!(urls).to(be_equal_to(vec![
+            (
+                "async-message",
+                "http://127.0.0.1:9000/pact-messages".to_string(),
+            ),
+            ("http", "http://127.0.0.1:9000".to_string()),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn verifies_message_and_http_interactions_in_one_run() {
+        let type_matcher = json!({ "combine": "AND", "matchers": [{ "match": "type" }] });
+        let pact = json!({
+            "consumer": { "name": "product-dashboard" },
+            "provider": { "name": PROVIDER_NAME },
+            "interactions": [
+                {
+                    "type": "Asynchronous/Messages",
+                    "description": "a product event create",
+                    "contents": {
+                        "content": {
+                            "id": "some-uuid-1234-5678",
+                            "type": "Product Range",
+                            "name": "Some Product",
+                            "version": "v2",
+                            "event": "CREATED"
+                        },
+                        "contentType": "application/json",
+                        "encoded": false
+                    },
+                    "matchingRules": {
+                        "body": { "$.id": type_matcher, "$.version": type_matcher }
+                    }
+                },
+                {
+                    "type": "Synchronous/HTTP",
+                    "description": "a request for product 42",
+                    "providerStates": [{ "name": "a product with id 42 exists" }],
+                    "request": { "method": "GET", "path": "/products/42" },
+                    "response": {
+                        "status": 200,
+                        "body": {
+                            "content": {
+                                "id": "42",
+                                "type": "Product Range",
+                                "name": "Some Product",
+                                "version": "v1"
+                            },
+                            "contentType": "application/json",
+                            "encoded": false
+                        }
+                    }
+                }
+            ],
+            "metadata": { "pactSpecification": { "version": "4.0" } }
+        });
+        let dir = env::temp_dir().join(format!("two-transports-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pact_file = dir.join("pact.json");
+        std::fs::write(&pact_file, pact.to_string()).unwrap();
+        let junit = dir.join("junit.xml");
+
+        let lookup = |name: &str| match name {
+            "PACT_JUNIT_OUTPUT" => Some(junit.display().to_string()),
+            _ => None,
+        };
+        let result = verify(lookup, pact_file.display().to_string()).await;
+        let report = std::fs::read_to_string(&junit).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        if let Err(err) = result {
+            panic!("{:#}", err);
+        }
+        expect!(report.contains("tests=\"2\" failures=\"0\"")).to(be_true());
+        expect!(report.contains("a product event create")).to(be_true());
+        expect!(report.contains("a request for product 42")).to(be_true());
+    }
+
+    /// A pact of `consumer` with one message, the product event published on create, which
+    /// is expected to be an `event` event.
+    fn product_created_pact(consumer: &str, event: &str) -> Value {
+        let type_matcher = json!({ "combine": "AND", "matchers": [{ "match": "type" }] });
+        json!({
+            "consumer": { "name": consumer },
+            "provider": { "name": PROVIDER_NAME },
+            "interactions": [{
+                "type": "Asynchronous/Messages",
+                "description": "a product event create",
+                "contents": {
+                    "content": {
+                        "id": "some-uuid-1234-5678",
+                        "type": "Product Range",
+                        "name": "Some Product",
+                        "version": "v1",
+                        "event": event
+                    },
+                    "contentType": "application/json",
+                    "encoded": false
+                },
+                "matchingRules": {
+                    "body": { "$.id": type_matcher, "$.version": type_matcher }
+                }
+            }],
+            "metadata": { "pactSpecification": { "version": "4.0" } }
+        })
+    }
+
+    #[tokio::test]
+    async fn verifies_every_pact_file_in_the_pact_dir() {
+        let dir = env::temp_dir().join(format!("pact-dir-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: String| std::fs::write(dir.join(name), contents);
+        write(
+            "dashboard.json",
+            product_created_pact("dashboard", "CREATED").to_string(),
+        )
+        .unwrap();
+        write(
+            "search.json",
+            product_created_pact("search", "CREATED").to_string(),
+        )
+        .unwrap();
+        write("README.md", "not a pact".to_string()).unwrap();
+        let junit = dir.join("junit.xml");
+        let lookup = |name: &str| match name {
+            "PACT_DIR" => Some(dir.display().to_string()),
+            "PACT_JUNIT_OUTPUT" => Some(junit.display().to_string()),
+            _ => None,
+        };
+
+        let result = verify(lookup, DEFAULT_PACT_FILE.to_string()).await;
+        if let Err(err) = result {
+            panic!("{:#}", err);
+        }
+        let report = std::fs::read_to_string(&junit).unwrap();
+        expect!(report.contains("tests=\"2\" failures=\"0\"")).to(be_true());
+
+        write(
+            "search.json",
+            product_created_pact("search", "DELETED").to_string(),
+        )
+        .unwrap();
+        let result = verify(lookup, DEFAULT_PACT_FILE.to_string()).await;
+        let report = std::fs::read_to_string(&junit).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let err = format!("{:#}", result.unwrap_err());
+        expect!(err.contains(&format!("{}:", dir.join("search.json").display()))).to(be_true());
+        expect!(err.contains("dashboard.json")).to(be_false());
+        expect!(report.contains("tests=\"2\" failures=\"1\"")).to(be_true());
+    }
+
+    #[test]
+    fn an_empty_pact_dir_is_an_error() {
+        let dir = env::temp_dir().join(format!("empty-pact-dir-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let result = pact_files(&dir.display().to_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let err = result.unwrap_err();
+        expect!(err.to_string()).to(be_equal_to(format!(
+            "no pact files found in {}",
+            dir.display()
+        )));
+    }
+
+    /// Produces every message as if it had been published to the wrong topic.
+    async fn message_in_the_wrong_topic() -> HttpResponse {
+        let product = Product {
+            id: None,
+            name: "Some Product".to_string(),
+            r#type: ProductType::Range,
+            version: None,
+        };
+        let event = create_event(product, EventKind::Created);
+        let metadata = message_metadata("wrong-topic", &event);
+        let mut response = HttpResponse::Ok().json(event);
+        response.headers_mut().insert(
+            HeaderName::from_static(MESSAGE_METADATA_HEADER),
+            HeaderValue::from_str(&encode_message_metadata(&metadata)).unwrap(),
+        );
+        response
+    }
+
+    #[tokio::test]
+    async fn fails_verification_when_the_message_is_in_the_wrong_topic() {
+        // the consumer pact matches the topic by type, as topics may be routed per event type,
+        // so this pact expects it exactly
+        let type_matcher = json!({ "combine": "AND", "matchers": [{ "match": "type" }] });
+        let pact = json!({
+            "consumer": { "name": "product-dashboard" },
+            "provider": { "name": PROVIDER_NAME },
+            "interactions": [{
+                "type": "Asynchronous/Messages",
+                "description": "a product event create",
+                "contents": {
+                    "content": {
+                        "id": "some-uuid-1234-5678",
+                        "type": "Product Range",
+                        "name": "Some Product",
+                        "version": "v1",
+                        "event": "CREATED"
+                    },
+                    "contentType": "application/json",
+                    "encoded": false
+                },
+                "metadata": { "kafka_topic": "products" },
+                "matchingRules": { "body": { "$.id": type_matcher } }
+            }],
+            "metadata": { "pactSpecification": { "version": "4.0" } }
+        });
+        let pact_file = env::temp_dir().join(format!("wrong-topic-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&pact_file, pact.to_string()).unwrap();
+        let server = HttpServer::new(|| {
+            App::new().route("/pact-messages", web::post().to(message_in_the_wrong_topic))
+        })
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let port = server.addrs()[0].port();
+        let server = server.run();
+        let server_handle = server.handle();
+        tokio::spawn(server);
+
+        let verification_options: VerificationOptions<NullRequestFilterExecutor> =
+            VerificationOptions::default();
+        let provider_state_executor = Arc::new(ProductStateExecutor {
+            store: ProductStore::new(Mutex::new(HashMap::new())),
+        });
+        let result = verify_provider_async(
+            provider_info(proxy_transports(port)),
+            vec![PactSource::File(pact_file.display().to_string())],
+            FilterInfo::None,
+            vec![],
+            &verification_options,
+            None,
+            &provider_state_executor,
+            None,
+        )
+        .await;
+        server_handle.stop(true).await;
+        std::fs::remove_file(&pact_file).unwrap();
+
+        let result = result.unwrap();
+        expect!(result.result).to(be_false());
+        let failures = verification_failures(&result);
+        expect!(failures.len()).to(be_equal_to(1));
+        expect!(failures[0].contains("kafka_topic")).to(be_true());
+        expect!(failures[0].contains("wrong-topic")).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn verifies_api_produces_correct_messages_for_consumers() {
+        if let Err(err) = verify(|name| env::var(name).ok(), DEFAULT_PACT_FILE.to_string()).await {
+            panic!("{:#}", err);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+
+/// Which component of a `major.minor.patch` version to increment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionBump {
+    Major,
+    Minor,
+    Patch,
+}
+
+/// How product versions are written. Incoming versions in any of the formats are accepted and
+/// normalised to the scheme when the next version is produced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VersionScheme {
+    /// A counter with a `v` prefix: `v1`, `v2`, ... (`prefixed-int`, the default, which the
+    /// consumer pacts expect).
+    #[default]
+    PrefixedInt,
+    /// A bare counter: `1`, `2`, ... (`plain-int`).
+    PlainInt,
+    /// `major.minor.patch` without a prefix: `1.0.0`, `1.0.1`, ... (`semver`).
+    SemVer,
+}
+
+impl VersionScheme {
+    pub const ALL: [VersionScheme; 3] = [
+        VersionScheme::PrefixedInt,
+        VersionScheme::PlainInt,
+        VersionScheme::SemVer,
+    ];
+
+    /// The name the scheme is configured by.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VersionScheme::PrefixedInt => "prefixed-int",
+            VersionScheme::PlainInt => "plain-int",
+            VersionScheme::SemVer => "semver",
+        }
+    }
+
+    /// Parses `version` in any supported format into this scheme. A semver version becomes
+    /// its major component under the counter schemes, and a counter `n` becomes `n.0.0`
+    /// under `SemVer`.
+    fn parse(&self, version: &str) -> Option<Version> {
+        let parsed = Version::parse(version)?;
+        let parts = match (self, parsed.parts) {
+            (VersionScheme::SemVer, Parts::Counter(counter)) => Parts::SemVer(counter, 0, 0),
+            (VersionScheme::SemVer, parts) => parts,
+            (_, Parts::SemVer(major, _, _)) => Parts::Counter(major),
+            (_, parts) => parts,
+        };
+        Some(self.version(parts))
+    }
+
+    fn version(&self, parts: Parts) -> Version {
+        Version {
+            prefixed: *self == VersionScheme::PrefixedInt,
+            parts,
+        }
+    }
+
+    /// The version of a product that has none yet.
+    pub fn initial(&self) -> String {
+        match self {
+            VersionScheme::SemVer => self.version(Parts::SemVer(1, 0, 0)),
+            _ => self.version(Parts::Counter(1)),
+        }
+        .to_string()
+    }
+
+    /// Like [`increment_version_with`], but the result is always written in this scheme. A
+    /// missing or malformed version starts at the scheme's [initial](Self::initial) version.
+    pub fn increment(&self, version: Option<&str>, bump: VersionBump) -> String {
+        match version.and_then(|version| self.parse(version)) {
+            Some(parsed) => parsed.bump(bump).to_string(),
+            None => self.initial(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parts {
+    /// A bare counter such as `v1` or `1`.
+    Counter(u64),
+    SemVer(u64, u64, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Version {
+    prefixed: bool,
+    parts: Parts,
+}
+
+impl Version {
+    fn parse(value: &str) -> Option<Version> {
+        let (prefixed, rest) = match value.strip_prefix('v') {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let numbers = rest
+            .split('.')
+            .map(|part| part.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let parts = match numbers.as_slice() {
+            [counter] => Parts::Counter(*counter),
+            [major, minor, patch] => Parts::SemVer(*major, *minor, *patch),
+            _ => return None,
+        };
+        Some(Version { prefixed, parts })
+    }
+
+    fn bump(self, bump: VersionBump) -> Version {
+        let parts = match (self.parts, bump) {
+            (Parts::Counter(counter), _) => Parts::Counter(counter.saturating_add(1)),
+            (Parts::SemVer(major, _, _), VersionBump::Major) => {
+                Parts::SemVer(major.saturating_add(1), 0, 0)
+            }
+            (Parts::SemVer(major, minor, _), VersionBump::Minor) => {
+                Parts::SemVer(major, minor.saturating_add(1), 0)
+            }
+            (Parts::SemVer(major, minor, patch), VersionBump::Patch) => {
+                Parts::SemVer(major, minor, patch.saturating_add(1))
+            }
+        };
+        Version { parts, ..self }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefixed {
+            write!(f, "v")?;
+        }
+        match self.parts {
+            Parts::Counter(counter) => write!(f, "{}", counter),
+            Parts::SemVer(major, minor, patch) => write!(f, "{}.{}.{}", major, minor, patch),
+        }
+    }
+}
+
+/// Whether `version` is a counter (`v1`, `1`) or semver (`1.2.3`, `v1.2.3`) version.
+pub fn is_valid_version(version: &str) -> bool {
+    Version::parse(version).is_some()
+}
+
+/// Returns the next version after `version`, bumping the patch component of semver versions.
+pub fn increment_version(version: Option<String>) -> String {
+    increment_version_with(version, VersionBump::Patch)
+}
+
+/// Returns the next version after `version`. Counter versions (`v1`, `2`) are always
+/// incremented by one, semver versions (`1.2.3`, `v1.2.3`) according to `bump`. A missing
+/// version starts at `v1`; a malformed one falls back to `1.0.0` if it looks like a semver
+/// attempt (contains a `.`) and to `v1` otherwise.
+pub fn increment_version_with(version: Option<String>, bump: VersionBump) -> String {
+    match version {
+        None => "v1".to_string(),
+        Some(version) => match Version::parse(&version) {
+            Some(parsed) => parsed.bump(bump).to_string(),
+            None if version.contains('.') => "1.0.0".to_string(),
+            None => "v1".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{increment_version, increment_version_with, VersionBump, VersionScheme};
+    use expectest::prelude::*;
+
+    #[test]
+    fn increments_counter_versions() {
+        expect!(increment_version(None)).to(be_equal_to("v1"));
+        expect!(increment_version(Some("v1".to_string()))).to(be_equal_to("v2"));
+        expect!(increment_version(Some("41".to_string()))).to(be_equal_to("42"));
+    }
+
+    #[test]
+    fn increments_semver_versions() {
+        let version = || Some("1.2.3".to_string());
+        expect!(increment_version(version())).to(be_equal_to("1.2.4"));
+        expect!(increment_version(Some("v1.2.3".to_string()))).to(be_equal_to("v1.2.4"));
+        expect!(increment_version_with(version(), VersionBump::Minor)).to(be_equal_to("1.3.0"));
+        expect!(increment_version_with(version(), VersionBump::Major)).to(be_equal_to("2.0.0"));
+    }
+
+    #[test]
+    fn falls_back_on_malformed_versions() {
+        expect!(increment_version(Some("".to_string()))).to(be_equal_to("v1"));
+        expect!(increment_version(Some("v".to_string()))).to(be_equal_to("v1"));
+        expect!(increment_version(Some("banana".to_string()))).to(be_equal_to("v1"));
+        expect!(increment_version(Some("1.x.3".to_string()))).to(be_equal_to("1.0.0"));
+        expect!(increment_version(Some("1.2".to_string()))).to(be_equal_to("1.0.0"));
+    }
+
+    #[test]
+    fn formats_versions_in_each_scheme() {
+        let next = |scheme: VersionScheme, version: Option<&str>| {
+            scheme.increment(version, VersionBump::Patch)
+        };
+
+        expect!(VersionScheme::default()).to(be_equal_to(VersionScheme::PrefixedInt));
+        expect!(next(VersionScheme::PrefixedInt, None)).to(be_equal_to("v1"));
+        expect!(next(VersionScheme::PrefixedInt, Some("v1"))).to(be_equal_to("v2"));
+        expect!(next(VersionScheme::PrefixedInt, Some("1"))).to(be_equal_to("v2"));
+        expect!(next(VersionScheme::PrefixedInt, Some("3.2.1"))).to(be_equal_to("v4"));
+
+        expect!(next(VersionScheme::PlainInt, None)).to(be_equal_to("1"));
+        expect!(next(VersionScheme::PlainInt, Some("v1"))).to(be_equal_to("2"));
+        expect!(next(VersionScheme::PlainInt, Some("41"))).to(be_equal_to("42"));
+
+        expect!(next(VersionScheme::SemVer, None)).to(be_equal_to("1.0.0"));
+        expect!(next(VersionScheme::SemVer, Some("v1.2.3"))).to(be_equal_to("1.2.4"));
+        expect!(next(VersionScheme::SemVer, Some("v2"))).to(be_equal_to("2.0.1"));
+        let minor = VersionScheme::SemVer.increment(Some("1.2.3"), VersionBump::Minor);
+        expect!(minor).to(be_equal_to("1.3.0"));
+
+        expect!(next(VersionScheme::PlainInt, Some("banana"))).to(be_equal_to("1"));
+    }
+}
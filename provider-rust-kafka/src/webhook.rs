@@ -0,0 +1,122 @@
+use crate::errors::PublishError;
+use product_events::ProductEvent;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// How long a call to the webhook may take before it is given up on.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body POSTed to the webhook for an event that could not be published.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PublishFailure<'a> {
+    pub id: &'a str,
+    pub event: &'a str,
+    pub version: &'a str,
+    pub error: String,
+    pub correlation_id: &'a str,
+}
+
+/// Tells an external endpoint (`FAILURE_WEBHOOK_URL`) about every event that still failed to
+/// publish after retries, so someone can be paged about the lost change.
+pub struct FailureWebhook {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl FailureWebhook {
+    pub fn new(url: &str) -> Self {
+        FailureWebhook {
+            url: url.to_string(),
+            http: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// POSTs the failure to the webhook. A webhook that cannot be reached or answers with an
+    /// error is only logged, the publish has failed either way.
+    pub async fn notify(&self, event: &ProductEvent, correlation_id: &str, err: &PublishError) {
+        let failure = PublishFailure {
+            id: &event.id,
+            event: event.event.as_str(),
+            version: &event.version,
+            error: err.to_string(),
+            correlation_id,
+        };
+        let result = self
+            .http
+            .post(&self.url)
+            .json(&failure)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => info!(product_id = %event.id, "reported failed publish to the webhook"),
+            Err(err) => {
+                error!(product_id = %event.id, error = %err, "failed to call the failure webhook")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailureWebhook;
+    use crate::errors::PublishError;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use expectest::prelude::*;
+    use product_events::{create_event, EventKind, Product, ProductType};
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn posts_the_failure_to_the_webhook() {
+        let (received, mut failures) = tokio::sync::mpsc::unbounded_channel::<Value>();
+        let server = HttpServer::new(move || {
+            let received = received.clone();
+            App::new().route(
+                "/failures",
+                web::post().to(move |body: web::Json<Value>| {
+                    let _ = received.send(body.into_inner());
+                    async { HttpResponse::Ok().finish() }
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/failures", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let event = create_event(
+            Product {
+                id: Some("42".to_string()),
+                name: "Some Product".to_string(),
+                r#type: ProductType::Range,
+                version: None,
+            },
+            EventKind::Created,
+        );
+        let err = PublishError::Delivery(KafkaError::MessageProduction(
+            RDKafkaErrorCode::MessageSizeTooLarge,
+        ));
+        FailureWebhook::new(&url)
+            .notify(&event, "some-correlation-id", &err)
+            .await;
+
+        let failure = tokio::time::timeout(Duration::from_secs(5), failures.recv()).await;
+        handle.stop(false).await;
+        expect!(failure.unwrap()).to(be_some().value(json!({
+            "id": "42",
+            "event": "CREATED",
+            "version": event.version,
+            "error": err.to_string(),
+            "correlation_id": "some-correlation-id"
+        })));
+    }
+}